@@ -1,14 +1,21 @@
 // SPDX-FileCopyrightText: 2026 The rsinit Authors
 // SPDX-License-Identifier: GPL-2.0-only
 
+#[cfg(not(feature = "no_std"))]
 use std::fs::read_dir;
+#[cfg(not(feature = "no_std"))]
 use std::io;
+#[cfg(not(feature = "no_std"))]
 use std::process;
 
+#[cfg(not(feature = "no_std"))]
 use nix::sys::reboot::{reboot, RebootMode};
+#[cfg(not(feature = "no_std"))]
 use rsinit::integration::find_vport;
+#[cfg(not(feature = "no_std"))]
 use rsinit::util::{read_file, Result};
 
+#[cfg(not(feature = "no_std"))]
 fn parse_mountinfo() -> Result<json::JsonValue> {
     let data = read_file("/proc/self/mountinfo")?;
     let mut mountinfo = json::JsonValue::new_array();
@@ -32,7 +39,7 @@ fn parse_mountinfo() -> Result<json::JsonValue> {
             if field == Some("-") {
                 break;
             }
-            optional_fields.push(field)?;
+            optional_fields.push(field).map_err(|e| e.to_string())?;
         }
         mount["optional-fields"] = optional_fields;
         mount["filesystem-type"] = fields.next().into();
@@ -43,11 +50,12 @@ fn parse_mountinfo() -> Result<json::JsonValue> {
             .split(",")
             .collect::<Vec<_>>()
             .into();
-        mountinfo.push(mount)?;
+        mountinfo.push(mount).map_err(|e| e.to_string())?;
     }
     Ok(mountinfo)
 }
 
+#[cfg(not(feature = "no_std"))]
 fn collect_block_devices() -> Result<json::JsonValue> {
     let mut devices = json::JsonValue::new_object();
     let entries = read_dir("/sys/dev/block")?;
@@ -61,6 +69,7 @@ fn collect_block_devices() -> Result<json::JsonValue> {
     Ok(devices)
 }
 
+#[cfg(not(feature = "no_std"))]
 fn main() -> Result<()> {
     println!("Collecting system state...");
 
@@ -82,3 +91,7 @@ fn main() -> Result<()> {
     }
     Ok(())
 }
+
+/* The no_std feature only builds the cmdline parser of the library */
+#[cfg(feature = "no_std")]
+fn main() {}
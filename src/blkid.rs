@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::canonicalize;
+use std::time::Duration;
+
+use log::info;
+
+use crate::util::{wait_for_device, Result, RsinitError};
+
+/// A block device as specified on the kernel command-line, e.g. with `root=`.
+#[derive(Debug, PartialEq)]
+pub enum DeviceSpec<'a> {
+    Path(&'a str),
+    Uuid(&'a str),
+    Label(&'a str),
+    PartLabel(&'a str),
+    /// The partition UUID and the `PARTNROFF=` offset to the partition that should be used.
+    PartUuid(&'a str, i32),
+}
+
+impl<'a> DeviceSpec<'a> {
+    pub fn parse(spec: &'a str) -> Result<DeviceSpec<'a>> {
+        if let Some(uuid) = spec.strip_prefix("UUID=") {
+            Ok(DeviceSpec::Uuid(uuid))
+        } else if let Some(label) = spec.strip_prefix("LABEL=") {
+            Ok(DeviceSpec::Label(label))
+        } else if let Some(label) = spec.strip_prefix("PARTLABEL=") {
+            Ok(DeviceSpec::PartLabel(label))
+        } else if let Some(partuuid) = spec.strip_prefix("PARTUUID=") {
+            match partuuid.split_once("/PARTNROFF=") {
+                None => Ok(DeviceSpec::PartUuid(partuuid, 0)),
+                Some((uuid, offset)) => {
+                    let offset = offset
+                        .parse::<i32>()
+                        .map_err(|e| format!("Invalid PARTNROFF in '{spec}': {e}"))?;
+                    Ok(DeviceSpec::PartUuid(uuid, offset))
+                }
+            }
+        } else {
+            Ok(DeviceSpec::Path(spec))
+        }
+    }
+
+    /// The symlink that udev (or mdev) creates for this device.
+    pub fn link(&self) -> String {
+        match self {
+            DeviceSpec::Path(path) => path.to_string(),
+            DeviceSpec::Uuid(uuid) => format!("/dev/disk/by-uuid/{}", encode_name(uuid)),
+            DeviceSpec::Label(label) => format!("/dev/disk/by-label/{}", encode_name(label)),
+            DeviceSpec::PartLabel(label) => {
+                format!("/dev/disk/by-partlabel/{}", encode_name(label))
+            }
+            /* udev uses lowercase for both GPT and MBR (<disk-id>-<partition>) partition UUIDs */
+            DeviceSpec::PartUuid(uuid, _) => {
+                format!("/dev/disk/by-partuuid/{}", uuid.to_ascii_lowercase())
+            }
+        }
+    }
+}
+
+/// Escape a name the same way udev does for the /dev/disk/by-* symlinks.
+fn encode_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"#+-.:=@_".contains(&b) || !b.is_ascii() {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("\\x{b:02x}"));
+        }
+    }
+    encoded
+}
+
+/// Find the partition `offset` partitions away from `device` on the same disk, e.g.
+/// `/dev/mmcblk0p2` with an offset of 1 is `/dev/mmcblk0p3`.
+fn partition_offset(device: &str, offset: i32) -> Result<String> {
+    if offset == 0 {
+        return Ok(device.to_string());
+    }
+    let disk = device.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = device[disk.len()..]
+        .parse::<i32>()
+        .map_err(|_| format!("{device} is not a partition"))?;
+    let number = number + offset;
+    if number < 1 {
+        return Err(format!("PARTNROFF={offset} is out of range for {device}").into());
+    }
+    Ok(format!("{disk}{number}"))
+}
+
+/// Resolve `UUID=`, `LABEL=`, `PARTLABEL=` and `PARTUUID=` device specifications to the
+/// device node.
+///
+/// Plain paths are returned unchanged without waiting for them.
+pub fn resolve_device(spec: &str, timeout: Option<Duration>) -> Result<String> {
+    let device_spec = DeviceSpec::parse(spec)?;
+    let offset = match device_spec {
+        DeviceSpec::Path(path) => return Ok(path.to_string()),
+        DeviceSpec::PartUuid(_, offset) => offset,
+        _ => 0,
+    };
+    let link = device_spec.link();
+
+    wait_for_device(&link, timeout)
+        .map_err(|e| RsinitError::Device(format!("Failed to find {spec}: {e}")))?;
+    let device = canonicalize(&link)
+        .map_err(|e| RsinitError::Device(format!("Failed to resolve {link}: {e}")))?
+        .to_string_lossy()
+        .into_owned();
+    let device = partition_offset(&device, offset)?;
+    info!("Resolved {spec} to {device}");
+
+    Ok(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            DeviceSpec::parse("/dev/mmcblk0p1").unwrap(),
+            DeviceSpec::Path("/dev/mmcblk0p1")
+        );
+        assert_eq!(
+            DeviceSpec::parse("UUID=0a1b2c3d-4e5f-6789-abcd-ef0123456789").unwrap(),
+            DeviceSpec::Uuid("0a1b2c3d-4e5f-6789-abcd-ef0123456789")
+        );
+        assert_eq!(
+            DeviceSpec::parse("LABEL=rootfs").unwrap(),
+            DeviceSpec::Label("rootfs")
+        );
+        assert_eq!(
+            DeviceSpec::parse("PARTLABEL=root-a").unwrap(),
+            DeviceSpec::PartLabel("root-a")
+        );
+        assert_eq!(
+            DeviceSpec::parse("uuid=1234").unwrap(),
+            DeviceSpec::Path("uuid=1234")
+        );
+    }
+
+    #[test]
+    fn test_parse_partuuid() {
+        assert_eq!(
+            DeviceSpec::parse("PARTUUID=1B2C3D4E-0001-4000-8000-0123456789AB").unwrap(),
+            DeviceSpec::PartUuid("1B2C3D4E-0001-4000-8000-0123456789AB", 0)
+        );
+        assert_eq!(
+            DeviceSpec::parse("PARTUUID=cafe0123-02/PARTNROFF=1").unwrap(),
+            DeviceSpec::PartUuid("cafe0123-02", 1)
+        );
+        assert_eq!(
+            DeviceSpec::parse("PARTUUID=cafe0123-02/PARTNROFF=-1").unwrap(),
+            DeviceSpec::PartUuid("cafe0123-02", -1)
+        );
+        assert!(DeviceSpec::parse("PARTUUID=cafe0123-02/PARTNROFF=x").is_err());
+    }
+
+    #[test]
+    fn test_partition_offset() {
+        assert_eq!(partition_offset("/dev/sda2", 0).unwrap(), "/dev/sda2");
+        assert_eq!(partition_offset("/dev/sda2", 1).unwrap(), "/dev/sda3");
+        assert_eq!(
+            partition_offset("/dev/mmcblk0p9", 1).unwrap(),
+            "/dev/mmcblk0p10"
+        );
+        assert_eq!(
+            partition_offset("/dev/nvme0n1p3", -2).unwrap(),
+            "/dev/nvme0n1p1"
+        );
+        assert!(partition_offset("/dev/sda1", -1).is_err());
+        assert!(partition_offset("/dev/dm-", 1).is_err());
+    }
+
+    #[test]
+    fn test_link() {
+        assert_eq!(
+            DeviceSpec::parse("UUID=1234-ABCD").unwrap().link(),
+            "/dev/disk/by-uuid/1234-ABCD"
+        );
+        assert_eq!(
+            DeviceSpec::parse("LABEL=my root/fs").unwrap().link(),
+            "/dev/disk/by-label/my\\x20root\\x2ffs"
+        );
+        assert_eq!(
+            DeviceSpec::parse("PARTLABEL=root_a").unwrap().link(),
+            "/dev/disk/by-partlabel/root_a"
+        );
+        assert_eq!(
+            DeviceSpec::parse("PARTUUID=CAFE0123-02/PARTNROFF=1")
+                .unwrap()
+                .link(),
+            "/dev/disk/by-partuuid/cafe0123-02"
+        );
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        assert_eq!(resolve_device("/dev/nfs", None).unwrap(), "/dev/nfs");
+    }
+}
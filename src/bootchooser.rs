@@ -0,0 +1,260 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Boot attempt counting for A/B slots in a bootchooser state.
+//!
+//! The state uses the raw format of the barebox state framework: a header with magic, length
+//! and CRCs followed by the variables
+//!
+//! | offset | variable                     |
+//! |--------|------------------------------|
+//! | 0x00   | `system0.remaining_attempts` |
+//! | 0x04   | `system0.priority`           |
+//! | 0x08   | `system1.remaining_attempts` |
+//! | 0x0c   | `system1.priority`           |
+//! | 0x10   | `last_chosen`                |
+//!
+//! as little endian `u32`. `system0` is slot A and `system1` slot B. The state is stored in
+//! several redundant copies that are written one after the other, so at least one copy is
+//! always valid even if writing is interrupted.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use log::{info, warn};
+
+use crate::cmdline::{BootchooserOptions, Slot};
+use crate::util::{dry_run, Result};
+
+const HEADER_SIZE: usize = 16;
+const DATA_SIZE: usize = 20;
+
+/// The bootchooser variables.
+#[derive(Debug, PartialEq)]
+pub struct BootState {
+    /// The remaining boot attempts of slot A and B.
+    pub remaining_attempts: [u32; 2],
+    /// The priority of slot A and B. Slots with priority 0 are never booted.
+    pub priority: [u32; 2],
+    pub last_chosen: u32,
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+fn le_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+impl BootState {
+    /// Parse one copy of the state. Returns `None` if the magic, length or a CRC is wrong.
+    fn parse(copy: &[u8], magic: u32) -> Option<BootState> {
+        let header = copy.get(..HEADER_SIZE)?;
+        let data = copy.get(HEADER_SIZE..HEADER_SIZE + DATA_SIZE)?;
+        if le_u32(header, 0) != magic
+            || u16::from_le_bytes([header[6], header[7]]) as usize != DATA_SIZE
+            || le_u32(header, 8) != crc32(data)
+            || le_u32(header, 12) != crc32(&header[..12])
+        {
+            return None;
+        }
+        Some(BootState {
+            remaining_attempts: [le_u32(data, 0x0), le_u32(data, 0x8)],
+            priority: [le_u32(data, 0x4), le_u32(data, 0xc)],
+            last_chosen: le_u32(data, 0x10),
+        })
+    }
+
+    fn encode(&self, magic: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + DATA_SIZE);
+        for value in [
+            self.remaining_attempts[0],
+            self.priority[0],
+            self.remaining_attempts[1],
+            self.priority[1],
+            self.last_chosen,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(&magic.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&(DATA_SIZE as u16).to_le_bytes());
+        header.extend_from_slice(&crc32(&data).to_le_bytes());
+        header.extend_from_slice(&crc32(&header).to_le_bytes());
+
+        header.extend_from_slice(&data);
+        header
+    }
+
+    /// The slot with the highest priority that has boot attempts left. Slot A wins a tie.
+    pub fn choose(&self) -> Option<Slot> {
+        [Slot::A, Slot::B]
+            .into_iter()
+            .filter(|&slot| {
+                self.priority[slot as usize] > 0 && self.remaining_attempts[slot as usize] > 0
+            })
+            .max_by_key(|&slot| (self.priority[slot as usize], slot == Slot::A))
+    }
+}
+
+fn check_options(options: &BootchooserOptions) -> Result<(&str, u64)> {
+    if options.stride < (HEADER_SIZE + DATA_SIZE) as u64 {
+        return Err(format!("The bootchooser stride {} is too small", options.stride).into());
+    }
+    let (device, offset) = options
+        .device
+        .as_ref()
+        .ok_or("No bootchooser state device")?;
+    Ok((device, *offset))
+}
+
+/// Read the first valid copy of the state.
+pub fn read_state(options: &BootchooserOptions) -> Result<BootState> {
+    let (device, offset) = check_options(options)?;
+    let mut f = File::open(device).map_err(|e| format!("Failed to open {device}: {e}"))?;
+    for copy in 0..options.copies {
+        let mut data = [0u8; HEADER_SIZE + DATA_SIZE];
+        let result = f
+            .seek(SeekFrom::Start(offset + copy as u64 * options.stride))
+            .and_then(|_| f.read_exact(&mut data));
+        match result {
+            Ok(()) => match BootState::parse(&data, options.magic) {
+                Some(state) => return Ok(state),
+                None => warn!("Copy {copy} of the bootchooser state on {device} is invalid"),
+            },
+            Err(e) => warn!("Failed to read copy {copy} of the bootchooser state on {device}: {e}"),
+        }
+    }
+    Err(format!("No valid bootchooser state on {device}").into())
+}
+
+/// Write `state` to all copies, one after the other.
+pub fn write_state(options: &BootchooserOptions, state: &BootState) -> Result<()> {
+    let (device, offset) = check_options(options)?;
+    if dry_run() {
+        info!("Would write the bootchooser state {state:?} to {device}");
+        return Ok(());
+    }
+    let data = state.encode(options.magic);
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(device)
+        .map_err(|e| format!("Failed to open {device}: {e}"))?;
+    for copy in 0..options.copies {
+        f.seek(SeekFrom::Start(offset + copy as u64 * options.stride))
+            .and_then(|_| f.write_all(&data))
+            .and_then(|_| f.sync_data())
+            .map_err(|e| format!("Failed to write the bootchooser state to {device}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Choose the slot to boot from the state.
+pub fn choose_slot(options: &BootchooserOptions) -> Result<Slot> {
+    let state = read_state(options)?;
+    let slot = state
+        .choose()
+        .ok_or("No slot with remaining boot attempts")?;
+    info!(
+        "Bootchooser selected slot {} with {} remaining attempts",
+        slot.as_str(),
+        state.remaining_attempts[slot as usize]
+    );
+    Ok(slot)
+}
+
+/// Count the boot of `slot`: its remaining attempts are decremented and it is recorded as
+/// `last_chosen`. Userspace resets the attempts once the boot is confirmed.
+pub fn count_boot(options: &BootchooserOptions, slot: Slot) -> Result<()> {
+    let mut state = read_state(options)?;
+    let attempts = &mut state.remaining_attempts[slot as usize];
+    *attempts = attempts.saturating_sub(1);
+    state.last_chosen = slot as u32;
+    info!(
+        "Counting the boot of slot {}, {} attempts remaining",
+        slot.as_str(),
+        state.remaining_attempts[slot as usize]
+    );
+    write_state(options, &state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_choose() {
+        let mut state = BootState {
+            remaining_attempts: [3, 3],
+            priority: [10, 20],
+            last_chosen: 0,
+        };
+        assert_eq!(state.choose(), Some(Slot::B));
+        state.remaining_attempts[1] = 0;
+        assert_eq!(state.choose(), Some(Slot::A));
+        state.priority = [20, 20];
+        state.remaining_attempts[1] = 1;
+        assert_eq!(state.choose(), Some(Slot::A));
+        state.priority[0] = 0;
+        assert_eq!(state.choose(), Some(Slot::B));
+        state.remaining_attempts[1] = 0;
+        assert_eq!(state.choose(), None);
+    }
+
+    #[test]
+    fn test_state() {
+        let path = std::env::temp_dir().join(format!("rsinit-bootchooser-{}", std::process::id()));
+        let options = BootchooserOptions {
+            device: Some((path.to_str().unwrap().into(), 16)),
+            magic: 0xfee2ea9b,
+            stride: 64,
+            copies: 2,
+        };
+        let state = BootState {
+            remaining_attempts: [3, 1],
+            priority: [10, 20],
+            last_chosen: 0,
+        };
+        std::fs::write(&path, vec![0; 160]).unwrap();
+        assert!(read_state(&options).is_err());
+
+        write_state(&options, &state).unwrap();
+        assert_eq!(read_state(&options).unwrap(), state);
+
+        /* An interrupted write of the first copy falls back to the second one */
+        let mut data = std::fs::read(&path).unwrap();
+        data[16 + HEADER_SIZE] = 0xff;
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(read_state(&options).unwrap(), state);
+
+        assert_eq!(choose_slot(&options).unwrap(), Slot::B);
+        count_boot(&options, Slot::B).unwrap();
+        let counted = read_state(&options).unwrap();
+        assert_eq!(counted.remaining_attempts, [3, 0]);
+        assert_eq!(counted.last_chosen, 1);
+        assert_eq!(choose_slot(&options).unwrap(), Slot::A);
+
+        assert!(read_state(&BootchooserOptions {
+            magic: 1,
+            ..options
+        })
+        .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
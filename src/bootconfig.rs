@@ -0,0 +1,131 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Options from the kernel bootconfig.
+//!
+//! The kernel exposes the bootconfig in `/proc/bootconfig` with one fully qualified key per
+//! line, e.g.
+//!
+//! ```text
+//! rsinit.overlay = "tmpfs"
+//! rsinit.overlay_dir = "/etc", "/var"
+//! rsinit.dry_run = ""
+//! ```
+//!
+//! Only the keys in the `rsinit` subtree are used. Each value of an array results in an option
+//! of its own and an empty value in an option without value.
+
+use std::path::Path;
+
+use crate::util::{read_file_limited, Result};
+
+/// The maximum size of the bootconfig. The kernel limits the bootconfig data to 32 KiB, but
+/// the format in /proc is more verbose.
+const MAX_BOOTCONFIG_SIZE: u64 = 128 * 1024;
+
+/// The path of the bootconfig used by [`crate::init::InitContext::setup`].
+pub const BOOTCONFIG: &str = "/proc/bootconfig";
+
+fn parse_values(key: &str, mut values: &str) -> Result<Vec<String>> {
+    let mut result = Vec::new();
+    loop {
+        let quote = match values.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => {
+                let value = values.trim();
+                if value.contains([',', '"', '\'']) {
+                    return Err(format!("Invalid bootconfig value for {key}: {values}").into());
+                }
+                result.push(value.to_owned());
+                return Ok(result);
+            }
+        };
+        let (value, rest) = values[1..]
+            .split_once(quote)
+            .ok_or_else(|| format!("Unterminated bootconfig value for {key}"))?;
+        result.push(value.to_owned());
+        match rest.trim_start().strip_prefix(',') {
+            Some(rest) => values = rest.trim_start(),
+            None if rest.trim().is_empty() => return Ok(result),
+            None => return Err(format!("Invalid bootconfig value for {key}: {rest}").into()),
+        }
+    }
+}
+
+/// Parse the `rsinit.*` options from the bootconfig `content` into key/value pairs.
+pub fn parse_bootconfig(content: &str) -> Result<Vec<(String, Option<String>)>> {
+    let mut options = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, values) = match line.split_once('=') {
+            Some((key, values)) => (key.trim(), values.trim()),
+            None => (line, ""),
+        };
+        if !key.starts_with("rsinit.") {
+            continue;
+        }
+        for value in parse_values(key, values)? {
+            options.push((key.to_owned(), (!value.is_empty()).then_some(value)));
+        }
+    }
+    Ok(options)
+}
+
+/// Read the `rsinit.*` options from the bootconfig at `path`. A missing file, e.g. because the
+/// kernel does not support bootconfig, results in no options.
+pub fn read_bootconfig(path: &str) -> Result<Vec<(String, Option<String>)>> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    parse_bootconfig(&read_file_limited(path, MAX_BOOTCONFIG_SIZE)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = r#"kernel.console = "ttyS0,115200"
+rsinit.overlay = "tmpfs"
+rsinit.overlay_dir = "/etc", "/var"
+rsinit.dry_run = ""
+rsinit.setenv = 'FOO="a b"'
+init.systemd.log_level = "debug"
+# Parameters from bootloader:
+# root=/dev/sda1
+"#;
+        assert_eq!(
+            parse_bootconfig(content).expect("failed"),
+            [
+                ("rsinit.overlay".into(), Some("tmpfs".into())),
+                ("rsinit.overlay_dir".into(), Some("/etc".into())),
+                ("rsinit.overlay_dir".into(), Some("/var".into())),
+                ("rsinit.dry_run".into(), None),
+                ("rsinit.setenv".into(), Some(r#"FOO="a b""#.into())),
+            ]
+        );
+
+        assert_eq!(
+            parse_bootconfig("rsinit.loglevel = 7\nrsinit.cgroup\n").expect("failed"),
+            [
+                ("rsinit.loglevel".into(), Some("7".into())),
+                ("rsinit.cgroup".into(), None),
+            ]
+        );
+
+        assert!(parse_bootconfig(r#"rsinit.root = "/dev/sda1"#).is_err());
+        assert!(parse_bootconfig(r#"rsinit.root = "a" "b""#).is_err());
+        assert!(parse_bootconfig("rsinit.root = a, b").is_err());
+    }
+
+    #[test]
+    fn test_missing() {
+        assert_eq!(
+            read_bootconfig("/nonexistent/bootconfig").expect("failed"),
+            []
+        );
+    }
+}
@@ -2,65 +2,891 @@
 // SPDX-License-Identifier: GPL-2.0-only
 
 use std::fmt::Debug;
+use std::fs::read_dir;
+use std::mem::take;
+use std::path::Path;
+use std::time::Duration;
 
+use log::LevelFilter;
 use nix::mount::MsFlags;
 
-use crate::util::{read_file, Result};
+use crate::bootconfig::read_bootconfig;
+use crate::mount::DEFAULT_TMP_SIZE;
+#[cfg(feature = "net")]
+use crate::net::IpConfig;
+use crate::report::DEFAULT_REPORT_PATH;
+use crate::util::{read_file_limited, read_limited, Result, RsinitError};
+
+pub mod parse;
+
+use parse::{require_value, RootOptions};
+pub use parse::{tokens, Tokens};
 
 pub fn ensure_value<'a>(key: &str, value: Option<&'a str>) -> Result<&'a str> {
-    value.ok_or(format!("Cmdline option '{key}' must have an argument!").into())
+    Ok(require_value(key, value)?)
+}
+
+/// How fsck should repair errors, set with `fsck.repair=`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FsckRepair {
+    /// Only repair errors that can be fixed safely (`fsck.repair=preen`, `-a`).
+    Preen,
+    /// Answer yes to all questions (`fsck.repair=yes`, `-y`).
+    Yes,
+    /// Only check, do not repair anything (`fsck.repair=no`, `-n`).
+    No,
+}
+
+/// The format of the boot report, set with `rsinit.report_format=`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ReportFormat {
+    /// A JSON object (`rsinit.report_format=json`).
+    Json,
+    /// `key=value` lines (`rsinit.report_format=env`).
+    Env,
+}
+
+/// The points in the boot process where `rd.break=` starts a shell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BreakStage {
+    /// After the setup and parsing the cmdline (`rd.break=cmdline`).
+    Cmdline,
+    /// Before mounting the root filesystem (`rd.break=pre-mount`).
+    PreMount,
+    /// After mounting the root filesystem at /root (`rd.break=mount`).
+    Mount,
+    /// Before switching to the root filesystem (`rd.break=pre-pivot` or `rd.break`).
+    PrePivot,
+}
+
+impl BreakStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakStage::Cmdline => "cmdline",
+            BreakStage::PreMount => "pre-mount",
+            BreakStage::Mount => "mount",
+            BreakStage::PrePivot => "pre-pivot",
+        }
+    }
+
+    fn parse(stage: &str) -> Result<BreakStage> {
+        match stage {
+            "cmdline" => Ok(BreakStage::Cmdline),
+            "pre-mount" => Ok(BreakStage::PreMount),
+            "mount" => Ok(BreakStage::Mount),
+            "pre-pivot" => Ok(BreakStage::PrePivot),
+            _ => Err(format!("Unsupported rd.break stage '{stage}'").into()),
+        }
+    }
+}
+
+/// The slots of an A/B root filesystem setup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Slot::A => "a",
+            Slot::B => "b",
+        }
+    }
+
+    pub fn parse(slot: &str) -> Result<Slot> {
+        match slot {
+            "a" | "A" => Ok(Slot::A),
+            "b" | "B" => Ok(Slot::B),
+            _ => Err(format!("Unknown slot '{slot}'").into()),
+        }
+    }
+
+    /// The other slot, to fall back to.
+    pub fn other(&self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+/// The root filesystem of a slot (`rsinit.root.<slot>=`, `rsinit.rootfstype.<slot>=` and
+/// `rsinit.rootflags.<slot>=`).
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlotRoot {
+    pub root: Option<String>,
+    pub rootfstype: Option<String>,
+    pub rootflags: Option<String>,
+}
+
+/// A kernel console (`console=<device>[,<options>]`).
+#[derive(Debug, PartialEq)]
+pub struct Console {
+    /// The device name below `/dev`, e.g. `ttyS0`.
+    pub device: String,
+    /// The serial line options, e.g. `115200n8`.
+    pub options: Option<String>,
+}
+
+/// Parse the value of `console=`. Consoles without a device node like `uart8250,mmio,...` are
+/// ignored.
+pub fn parse_console(value: &str) -> Option<Console> {
+    let (device, options) = match value.split_once(',') {
+        Some((device, options)) => (device, Some(options.to_string())),
+        None => (value, None),
+    };
+    let device = device.strip_prefix("/dev/").unwrap_or(device);
+    if device.is_empty() || device.starts_with("uart") || device.contains('/') {
+        return None;
+    }
+    Some(Console {
+        device: device.to_string(),
+        options,
+    })
+}
+
+/// Map the kernel `loglevel=` to a filter: only messages more important than `loglevel` are
+/// shown.
+fn parse_kernel_loglevel(key: &str, value: Option<&str>) -> Result<LevelFilter> {
+    let value = ensure_value(key, value)?;
+    let loglevel = value
+        .parse::<u8>()
+        .map_err(|e| format!("Failed to parse '{key}={value}': {e}"))?;
+    Ok(match loglevel {
+        0..=3 => LevelFilter::Off,
+        4 => LevelFilter::Error,
+        5 | 6 => LevelFilter::Warn,
+        7 => LevelFilter::Info,
+        _ => LevelFilter::Trace,
+    })
+}
+
+fn parse_secs(key: &str, value: Option<&str>) -> Result<i64> {
+    let value = ensure_value(key, value)?;
+    value
+        .parse()
+        .map_err(|e| format!("Failed to parse '{key}={value}': {e}").into())
+}
+
+fn parse_hex_id(key: &str, value: Option<&str>) -> Result<u16> {
+    let value = ensure_value(key, value)?;
+    let digits = value
+        .strip_prefix("0x")
+        .or(value.strip_prefix("0X"))
+        .unwrap_or(value);
+    u16::from_str_radix(digits, 16).map_err(|e| {
+        format!("'{key}={value}' must be a 16 bit hexadecimal ID like 0x1d6b: {e}").into()
+    })
+}
+
+/// The USB descriptors of the 9pfs gadget.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct UsbgOptions {
+    /// `rsinit.usbg.vid=`
+    pub vid: u16,
+    /// `rsinit.usbg.pid=`
+    pub pid: u16,
+    /// `rsinit.usbg.serial=`
+    pub serial: String,
+    /// `rsinit.usbg.manufacturer=`
+    pub manufacturer: String,
+    /// `rsinit.usbg.product=`
+    pub product: String,
+    /// The USB Device Controller to attach the gadget to (`rsinit.usbg.udc=`).
+    pub udc: Option<String>,
+}
+
+impl Default for UsbgOptions {
+    fn default() -> UsbgOptions {
+        UsbgOptions {
+            vid: 0x1d6b,
+            pid: 0x0109,
+            serial: "01234567".into(),
+            manufacturer: "Pengutronix e.K.".into(),
+            product: "9PFS Gadget".into(),
+            udc: None,
+        }
+    }
+}
+
+/// The boot attempt counting with the bootchooser state (`rsinit.bootchooser.*`).
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct BootchooserOptions {
+    /// The device with the state and its offset (`rsinit.bootchooser=<dev>[@<offset>]`).
+    pub device: Option<(String, u64)>,
+    /// The magic of the state, like the `magic` property of the barebox state node
+    /// (`rsinit.bootchooser.magic=`).
+    pub magic: u32,
+    /// The distance between the redundant copies of the state (`rsinit.bootchooser.stride=`).
+    pub stride: u64,
+    /// The number of redundant copies of the state (`rsinit.bootchooser.copies=`).
+    pub copies: u32,
+}
+
+impl Default for BootchooserOptions {
+    fn default() -> BootchooserOptions {
+        BootchooserOptions {
+            device: None,
+            magic: 0,
+            stride: 64,
+            copies: 2,
+        }
+    }
+}
+
+/// A dm-crypt device in plain mode without LUKS header (`rsinit.plain_crypt.*`).
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PlainCryptOptions {
+    /// The encrypted device (`rsinit.plain_crypt=`).
+    pub device: Option<String>,
+    /// The dm-crypt cipher specification (`rsinit.plain_crypt.cipher=`).
+    pub cipher: String,
+    /// The key size in bits (`rsinit.plain_crypt.key_size=`).
+    pub key_size: u32,
+    /// The hash that derives the key from the passphrase (`rsinit.plain_crypt.hash=`). `plain`
+    /// uses the passphrase as key.
+    pub hash: String,
+    /// A file with the raw key (`rsinit.plain_crypt.key_file=`).
+    pub key_file: Option<String>,
+    /// A file with the passphrase that is hashed (`rsinit.plain_crypt.passphrase_file=`).
+    pub passphrase_file: Option<String>,
+    /// The start of the encrypted data in 512 byte sectors (`rsinit.plain_crypt.offset=`).
+    pub offset: u64,
+    /// The IV offset in 512 byte sectors (`rsinit.plain_crypt.skip=`).
+    pub skip: u64,
+}
+
+impl Default for PlainCryptOptions {
+    fn default() -> PlainCryptOptions {
+        PlainCryptOptions {
+            device: None,
+            cipher: "aes-xts-plain64".into(),
+            key_size: 256,
+            hash: "sha256".into(),
+            key_file: None,
+            passphrase_file: None,
+            offset: 0,
+            skip: 0,
+        }
+    }
+}
+
+/// Parse `<device>[@<offset>]`.
+fn parse_device_offset(key: &str, value: Option<&str>) -> Result<(String, u64)> {
+    let value = ensure_value(key, value)?;
+    Ok(match value.rsplit_once('@') {
+        Some((device, offset)) => (
+            device.to_string(),
+            parse_number(offset).ok_or(format!("Failed to parse the offset in '{key}={value}'"))?,
+        ),
+        None => (value.to_string(), 0),
+    })
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(digits) => u64::from_str_radix(digits, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// (De)serialize [`MsFlags`] as a list of flag names.
+#[cfg(feature = "serde")]
+mod ms_flags {
+    use nix::mount::MsFlags;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(flags: &MsFlags, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(flags.iter_names().map(|(name, _)| name))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<MsFlags, D::Error> {
+        Vec::<String>::deserialize(deserializer)?.iter().try_fold(
+            MsFlags::empty(),
+            |flags, name| {
+                MsFlags::from_name(name)
+                    .map(|flag| flags | flag)
+                    .ok_or_else(|| D::Error::custom(format!("unknown mount flag '{name}'")))
+            },
+        )
+    }
 }
 
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct CmdlineOptions {
+    /// The first root device from `root=<device>[,<device>...]`.
     pub root: Option<String>,
+    /// The other root devices from `root=`, tried in order if mounting `root` fails.
+    pub root_fallbacks: Vec<String>,
     pub rootfstype: Option<String>,
     pub rootflags: Option<String>,
+    /// Serialized as the list of flag names, e.g. `["MS_RDONLY"]`.
+    #[cfg_attr(feature = "serde", serde(with = "ms_flags"))]
     pub rootfsflags: MsFlags,
+    /// How long to wait for the root device to appear. `None` waits forever.
+    ///
+    /// Defaults to 5 seconds, `rootwait` waits forever.
+    pub rootwait: Option<Duration>,
+    /// How often mounting the rootfs is retried after transient errors (`rsinit.root_retries=`).
+    pub root_retries: u32,
+    /// Time to wait unconditionally before looking for the root device (`rootdelay=`).
+    pub rootdelay: Duration,
     pub verity_root: Option<String>,
+    /// The PCR that the dm-verity root hash is measured into, `None` to disable the
+    /// measurement (`rsinit.verity_pcr=`).
+    ///
+    /// Defaults to 11 like systemd with the `tpm` feature.
+    pub verity_pcr: Option<u32>,
+    /// dm-verity parameters from `rsinit.verity.<key>=` options as `VERITY_<KEY>` and value.
+    ///
+    /// They take precedence over the parameters in `/verity-params`.
+    pub verity_params: Vec<(String, String)>,
+    /// Read the dm-verity parameters from the veritysetup superblock on `VERITY_HASH_DEVICE`
+    /// (`rsinit.verity_superblock`).
+    pub verity_superblock: bool,
+    /// Additional dm-verity volumes from `/verity-params.<name>` as name and dm device.
+    pub verity_volumes: Vec<(String, String)>,
+    /// The LUKS device that is unlocked with a key sealed in the TPM2 (`rsinit.luks=`).
+    pub luks: Option<String>,
+    /// The device and path of a key file with a LUKS keyslot passphrase, which is used instead
+    /// of the TPM2 (`rsinit.luks_keyfile=<device>:<path>`).
+    pub luks_keyfile: Option<(String, String)>,
+    /// The plain dm-crypt rootfs.
+    pub plain_crypt: PlainCryptOptions,
+    /// The data device of a standalone dm-integrity device (`rsinit.integrity=`).
+    pub integrity: Option<String>,
+    /// dm-integrity parameters from `rsinit.integrity.<key>=` options as `INTEGRITY_<KEY>` and
+    /// value. They take precedence over the parameters in `/integrity-params`.
+    pub integrity_params: Vec<(String, String)>,
+    /// The activated dm-integrity device, e.g. to be mounted by a callback.
+    pub integrity_device: Option<String>,
     pub nfsroot: Option<String>,
+    /// The network configuration (`ip=`).
+    pub ip: Option<String>,
+    /// The server for `nfsroot=` without a server, from `ip=` or DHCP. If it is not set,
+    /// /proc/net/pnp is used.
+    pub bootserver: Option<String>,
     pub init: String,
+    /// The arguments for `init` after `--` on the cmdline. They are passed before the arguments
+    /// of rsinit itself.
+    pub init_args: Vec<String>,
     pub cleanup: bool,
+    /// Delete the files of the initramfs before switching root to free its memory
+    /// (`rsinit.delete_initramfs`). Only done with `cleanup`.
+    pub delete_initramfs: bool,
     /// Attempt to bind-mount `/lib/modules` from the initrd at `/root/lib/modules`.
     ///
     /// Enabled by the `rsinit.bind_modules` cmdline flag.
     pub bind_modules: bool,
+    /// Make the mounts below `/root` private before switching root, so later mounts do not
+    /// propagate to or from the initramfs (`rsinit.private_root`).
+    pub private_root: bool,
+    /// Switch root with `pivot_root()` and keep the initramfs at `/oldroot` instead of only
+    /// changing the root directory (`rsinit.pivot_root`). Disables the cleanup.
+    pub pivot_root: bool,
+    /// Mount a tmpfs at `/run` in the rootfs before switching root (`rsinit.run=0` to disable).
+    pub run: bool,
+    /// The size of a tmpfs that is mounted at `/tmp` in the rootfs before switching root, if
+    /// any (`rsinit.tmp[=<size>]`).
+    pub tmp: Option<String>,
+    /// Mount a writable overlay with a tmpfs as upper layer on top of the root filesystem.
+    ///
+    /// Enabled by the `rsinit.overlay=tmpfs` cmdline option.
+    pub tmpfs_overlay: bool,
+    /// Directories in the rootfs that get a writable overlay with a tmpfs as upper layer
+    /// (`rsinit.overlay_dir=<dir>`, can be repeated).
+    pub overlay_dirs: Vec<String>,
+    /// Check the root filesystem before mounting it.
+    ///
+    /// Enabled by `rsinit.fsck` or `fsck.mode=force` and disabled by `fsck.mode=skip`.
+    pub fsck: bool,
+    /// Check the root filesystem even if it seems clean (`fsck.mode=force`).
+    pub fsck_force: bool,
+    pub fsck_repair: FsckRepair,
+    pub usbg: UsbgOptions,
+    /// Kernel modules to load before mounting the rootfs (`rd.modules=`).
+    pub modules: Vec<String>,
+    /// Directory to load firmware from (`rsinit.firmware[=<dir>]`, `/lib/firmware` by default).
+    pub firmware: Option<String>,
+    /// Start a shell on the console if booting fails.
+    ///
+    /// Enabled by default with the `emergency-shell` feature and set with `rd.shell[=0|1]`.
+    pub shell: bool,
+    /// Start a shell at these stages (`rd.break[=<stage>,...]`).
+    pub breaks: Vec<BreakStage>,
+    /// The maximum log level, set with `loglevel=<0-7>`, `quiet` or
+    /// `rsinit.loglevel=<off|error|warn|info|debug|trace>`. The last option wins.
+    pub loglevel: LevelFilter,
+    /// Write log messages to the console in addition to /dev/kmsg (`rsinit.log_console`).
+    pub log_console: bool,
+    /// Identical log messages within this time are only logged once, followed by how often they
+    /// were repeated (`rsinit.log_coalesce=<secs>`, 0 disables it). Defaults to 5 seconds.
+    pub log_coalesce: Duration,
+    /// Seconds to wait before rebooting after a failure, negative to halt instead.
+    ///
+    /// Set with `rsinit.reboot_delay=<secs>` or `panic=<secs>`, which is interpreted like the
+    /// kernel does. Only used with the `reboot-on-failure` feature.
+    pub reboot_delay: Option<i64>,
+    /// Environment variables for the next init, in addition to the ones from the kernel.
+    ///
+    /// `PATH` is set by default, `rsinit.setenv=<name>=<value>` adds or replaces variables.
+    pub env: Vec<(String, String)>,
+    /// Resume from the hibernation image on this device (`resume=`), disabled by `noresume`.
+    pub resume: Option<String>,
+    /// The offset of the hibernation image in pages for swap files (`resume_offset=`).
+    pub resume_offset: Option<u64>,
+    pub noresume: bool,
+    /// Enable swapping to this device before mounting the rootfs (`rsinit.swap=`).
+    pub swap: Option<String>,
+    /// The priority of the swap device (`rsinit.swap_priority=`).
+    pub swap_priority: Option<u16>,
+    /// Abort the boot if swap cannot be enabled instead of logging an error
+    /// (`rsinit.swap_required`).
+    pub swap_required: bool,
+    /// Set up a zram swap device with `rsinit.zram=<size>[,<algorithm>]`.
+    pub zram: Option<String>,
+    /// The active slot of an A/B setup (`rsinit.slot=a|b` or `rsinit.slot_device=`).
+    pub slot: Option<Slot>,
+    /// The slot is booted for the first time and must be confirmed by userspace
+    /// (`rsinit.slot_try` or an upper case slot on `rsinit.slot_device=`).
+    pub slot_try: bool,
+    /// The device with the slot state byte and its offset (`rsinit.slot_device=<dev>[@<offset>]`).
+    pub slot_device: Option<(String, u64)>,
+    /// The root filesystems of slot A and B.
+    pub slot_roots: [SlotRoot; 2],
+    pub bootchooser: BootchooserOptions,
+    /// Mount cgroups at /sys/fs/cgroup (`rsinit.cgroup`).
+    pub cgroup: bool,
+    /// Mount the legacy cgroup v1 hierarchy instead of cgroup2
+    /// (`systemd.unified_cgroup_hierarchy=0`).
+    pub cgroup_legacy: bool,
+    /// Only log the mounts, switching root and starting init instead of performing them
+    /// (`rsinit.dry_run`).
+    pub dry_run: bool,
+    /// Write a boot report to this path in the rootfs before starting init
+    /// (`rsinit.report[=<path>]`).
+    pub report: Option<String>,
+    pub report_format: ReportFormat,
+    /// Execute this binary with the same arguments after the setup instead of continuing the
+    /// boot (`rsinit.reexec=<path>`). Only used with the `debug` feature.
+    pub reexec: Option<String>,
+    /// The options that rsinit does not handle itself, e.g. `console=` or options for
+    /// callbacks, with their values in cmdline order.
+    pub extra: Vec<(String, Option<String>)>,
 }
 
 impl Default for CmdlineOptions {
     fn default() -> CmdlineOptions {
         CmdlineOptions {
             root: None,
+            root_fallbacks: Vec::new(),
             rootfstype: None,
             rootflags: None,
             rootfsflags: MsFlags::MS_RDONLY,
+            rootwait: Some(Duration::from_secs(5)),
+            root_retries: 3,
+            rootdelay: Duration::ZERO,
             verity_root: None,
+            verity_pcr: cfg!(feature = "tpm").then_some(11),
+            verity_params: Vec::new(),
+            verity_superblock: false,
+            verity_volumes: Vec::new(),
+            luks: None,
+            luks_keyfile: None,
+            plain_crypt: PlainCryptOptions::default(),
+            integrity: None,
+            integrity_params: Vec::new(),
+            integrity_device: None,
             nfsroot: None,
+            ip: None,
+            bootserver: None,
             init: "/sbin/init".into(),
+            init_args: Vec::new(),
             cleanup: true,
+            delete_initramfs: false,
             bind_modules: false,
+            private_root: false,
+            pivot_root: false,
+            run: true,
+            tmp: None,
+            tmpfs_overlay: false,
+            overlay_dirs: Vec::new(),
+            fsck: false,
+            fsck_force: false,
+            fsck_repair: FsckRepair::Preen,
+            usbg: UsbgOptions::default(),
+            modules: Vec::new(),
+            firmware: None,
+            shell: cfg!(feature = "emergency-shell"),
+            breaks: Vec::new(),
+            loglevel: LevelFilter::Trace,
+            log_console: false,
+            log_coalesce: Duration::from_secs(5),
+            reboot_delay: None,
+            env: vec![(
+                "PATH".into(),
+                "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".into(),
+            )],
+            resume: None,
+            resume_offset: None,
+            noresume: false,
+            swap: None,
+            swap_priority: None,
+            swap_required: false,
+            zram: None,
+            slot: None,
+            slot_try: false,
+            slot_device: None,
+            slot_roots: Default::default(),
+            bootchooser: BootchooserOptions::default(),
+            cgroup: false,
+            cgroup_legacy: false,
+            dry_run: false,
+            report: None,
+            report_format: ReportFormat::Json,
+            reexec: None,
+            extra: Vec::new(),
         }
     }
 }
 
 impl CmdlineOptions {
+    /// The value of the last unhandled option `key`. `Some(None)` means it was given without a
+    /// value.
+    pub fn extra_value(&self, key: &str) -> Option<Option<&str>> {
+        self.extra
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_deref())
+    }
+
+    /// The consoles from all `console=` options in cmdline order. The kernel uses the last one
+    /// for `/dev/console`.
+    pub fn consoles(&self) -> Vec<Console> {
+        self.extra
+            .iter()
+            .filter(|(key, _)| key == "console")
+            .filter_map(|(_, value)| value.as_deref().and_then(parse_console))
+            .collect()
+    }
+
+    /// Apply one of the [`RootOptions::KEYS`] with the parser of [`RootOptions`].
+    fn parse_root_option(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        let mut root_options = RootOptions {
+            root: self.root.take(),
+            root_fallbacks: take(&mut self.root_fallbacks),
+            rootfstype: self.rootfstype.take(),
+            rootflags: self.rootflags.take(),
+            ..Default::default()
+        };
+        let parsed = root_options.parse_option(key, value);
+
+        /* Move the values back even if parsing failed */
+        self.root = root_options.root;
+        self.root_fallbacks = root_options.root_fallbacks;
+        self.rootfstype = root_options.rootfstype;
+        self.rootflags = root_options.rootflags;
+        match root_options.read_only {
+            Some(true) => self.rootfsflags.insert(MsFlags::MS_RDONLY),
+            Some(false) => self.rootfsflags.remove(MsFlags::MS_RDONLY),
+            None => (),
+        }
+        if let Some(init) = root_options.init {
+            self.init = init;
+        }
+        parsed?;
+        Ok(())
+    }
+
     fn parse_option<'a>(
         &mut self,
         key: &str,
         value: Option<&str>,
         callbacks: &mut [Box<dyn CmdlineCallback + 'a>],
     ) -> Result<()> {
+        if RootOptions::KEYS.contains(&key) {
+            return self.parse_root_option(key, value);
+        }
         match key {
-            "root" => self.root = Some(ensure_value(key, value)?.to_string()),
-            "rootfstype" => self.rootfstype = Some(ensure_value(key, value)?.to_string()),
-            "rootflags" => self.rootflags = value.map(str::to_string),
-            "ro" => self.rootfsflags.insert(MsFlags::MS_RDONLY),
-            "rw" => self.rootfsflags.remove(MsFlags::MS_RDONLY),
+            "rootwait" => self.rootwait = None,
+            "rootdelay" => {
+                let value = ensure_value(key, value)?;
+                let secs = value
+                    .parse::<u64>()
+                    .map_err(|e| format!("Failed to parse 'rootdelay={value}': {e}"))?;
+                self.rootdelay = Duration::from_secs(secs);
+            }
+            "rsinit.root_retries" => {
+                let value = ensure_value(key, value)?;
+                self.root_retries = value
+                    .parse()
+                    .map_err(|e| format!("Failed to parse '{key}={value}': {e}"))?;
+            }
             "rsinit.verity_root" => self.verity_root = Some(ensure_value(key, value)?.to_string()),
+            "rsinit.verity_pcr" => {
+                self.verity_pcr = match ensure_value(key, value)? {
+                    "off" => None,
+                    value => Some(
+                        value
+                            .parse()
+                            .map_err(|e| format!("Failed to parse '{key}={value}': {e}"))?,
+                    ),
+                }
+            }
+            "rsinit.verity_superblock" => self.verity_superblock = true,
+            "rsinit.luks" => self.luks = Some(ensure_value(key, value)?.to_string()),
+            "rsinit.luks_keyfile" => {
+                let value = ensure_value(key, value)?;
+                self.luks_keyfile = match value.rsplit_once(':') {
+                    Some((device, path)) if !device.is_empty() && path.starts_with('/') => {
+                        Some((device.to_string(), path.to_string()))
+                    }
+                    _ => return Err(format!("'{key}=' must be <device>:<path>").into()),
+                }
+            }
+            "rsinit.plain_crypt" => {
+                self.plain_crypt.device = Some(ensure_value(key, value)?.to_string())
+            }
+            "rsinit.plain_crypt.cipher" => {
+                self.plain_crypt.cipher = ensure_value(key, value)?.to_string()
+            }
+            "rsinit.plain_crypt.key_size" => {
+                let value = ensure_value(key, value)?;
+                self.plain_crypt.key_size = value
+                    .parse()
+                    .ok()
+                    .filter(|size| size % 8 == 0 && *size > 0)
+                    .ok_or(format!("Invalid key size '{key}={value}'"))?
+            }
+            "rsinit.plain_crypt.hash" => {
+                self.plain_crypt.hash = ensure_value(key, value)?.to_string()
+            }
+            "rsinit.plain_crypt.key_file" => {
+                self.plain_crypt.key_file = Some(ensure_value(key, value)?.to_string())
+            }
+            "rsinit.plain_crypt.passphrase_file" => {
+                self.plain_crypt.passphrase_file = Some(ensure_value(key, value)?.to_string())
+            }
+            "rsinit.plain_crypt.offset" | "rsinit.plain_crypt.skip" => {
+                let value = ensure_value(key, value)?;
+                let number = value
+                    .parse()
+                    .map_err(|e| format!("Failed to parse '{key}={value}': {e}"))?;
+                if key == "rsinit.plain_crypt.offset" {
+                    self.plain_crypt.offset = number;
+                } else {
+                    self.plain_crypt.skip = number;
+                }
+            }
+            "rsinit.integrity" => self.integrity = Some(ensure_value(key, value)?.to_string()),
             "nfsroot" => self.nfsroot = Some(ensure_value(key, value)?.to_string()),
-            "init" => self.init = ensure_value(key, value)?.into(),
+            "ip" => self.ip = Some(ensure_value(key, value)?.to_string()),
             "rsinit.bind_modules" => self.bind_modules = true,
+            "rsinit.private_root" => self.private_root = true,
+            "rsinit.pivot_root" => self.pivot_root = true,
+            "rsinit.delete_initramfs" => self.delete_initramfs = true,
+            "rsinit.run" => self.run = !matches!(value, Some("0" | "no" | "false" | "off")),
+            "rsinit.tmp" => self.tmp = Some(value.unwrap_or(DEFAULT_TMP_SIZE).into()),
+            "rsinit.log_console" => self.log_console = true,
+            "rsinit.log_coalesce" => {
+                let value = ensure_value(key, value)?;
+                let secs = value
+                    .parse::<u64>()
+                    .map_err(|e| format!("Failed to parse '{key}={value}': {e}"))?;
+                self.log_coalesce = Duration::from_secs(secs);
+            }
+            "rsinit.cgroup" => self.cgroup = true,
+            "systemd.unified_cgroup_hierarchy" => {
+                self.cgroup_legacy = matches!(value, Some("0" | "no" | "false" | "off"))
+            }
+            "resume" => self.resume = Some(ensure_value(key, value)?.to_string()),
+            "rsinit.swap" => self.swap = Some(ensure_value(key, value)?.to_string()),
+            "rsinit.swap_priority" => {
+                let value = ensure_value(key, value)?;
+                self.swap_priority = match value.parse::<u16>() {
+                    Ok(priority) if priority <= 32767 => Some(priority),
+                    _ => {
+                        return Err(format!(
+                            "'{key}={value}' must be a priority between 0 and 32767"
+                        )
+                        .into())
+                    }
+                };
+            }
+            "rsinit.swap_required" => self.swap_required = true,
+            "rsinit.zram" => self.zram = Some(ensure_value(key, value)?.to_string()),
+            "resume_offset" => {
+                let value = ensure_value(key, value)?;
+                self.resume_offset = Some(
+                    value
+                        .parse()
+                        .map_err(|e| format!("Failed to parse 'resume_offset={value}': {e}"))?,
+                );
+            }
+            "noresume" => self.noresume = true,
+            "rsinit.setenv" => {
+                let value = ensure_value(key, value)?;
+                let (name, value) = value
+                    .split_once('=')
+                    .filter(|(name, _)| !name.is_empty())
+                    .ok_or(format!("'{key}={value}' must be '{key}=<name>=<value>'"))?;
+                self.env.retain(|(n, _)| n != name);
+                self.env.push((name.into(), value.into()));
+            }
+            "rsinit.reboot_delay" => self.reboot_delay = Some(parse_secs(key, value)?),
+            "panic" => {
+                /* The kernel waits forever for 0 and reboots immediately for negative values */
+                self.reboot_delay = Some(match parse_secs(key, value)? {
+                    0 => -1,
+                    secs if secs < 0 => 0,
+                    secs => secs,
+                })
+            }
+            "rsinit.fsck" => self.fsck = true,
+            "rsinit.dry_run" => self.dry_run = true,
+            "rsinit.report" => self.report = Some(value.unwrap_or(DEFAULT_REPORT_PATH).into()),
+            "rsinit.reexec" => self.reexec = Some(ensure_value(key, value)?.into()),
+            "rsinit.report_format" => {
+                self.report_format = match ensure_value(key, value)? {
+                    "json" => ReportFormat::Json,
+                    "env" => ReportFormat::Env,
+                    format => return Err(format!("Invalid rsinit.report_format '{format}'").into()),
+                }
+            }
+            "fsck.mode" => match ensure_value(key, value)? {
+                "auto" => (),
+                "force" => {
+                    self.fsck = true;
+                    self.fsck_force = true;
+                }
+                "skip" => self.fsck = false,
+                mode => return Err(format!("Invalid fsck.mode '{mode}'").into()),
+            },
+            "fsck.repair" => {
+                self.fsck_repair = match ensure_value(key, value)? {
+                    "preen" => FsckRepair::Preen,
+                    "yes" => FsckRepair::Yes,
+                    "no" => FsckRepair::No,
+                    repair => return Err(format!("Invalid fsck.repair '{repair}'").into()),
+                }
+            }
+            "rsinit.usbg.vid" => self.usbg.vid = parse_hex_id(key, value)?,
+            "rsinit.usbg.pid" => self.usbg.pid = parse_hex_id(key, value)?,
+            "rsinit.usbg.serial" => self.usbg.serial = ensure_value(key, value)?.into(),
+            "rsinit.usbg.manufacturer" => self.usbg.manufacturer = ensure_value(key, value)?.into(),
+            "rsinit.usbg.product" => self.usbg.product = ensure_value(key, value)?.into(),
+            "rsinit.usbg.udc" => self.usbg.udc = Some(ensure_value(key, value)?.into()),
+            "rd.modules" => self.modules.extend(
+                ensure_value(key, value)?
+                    .split(',')
+                    .filter(|module| !module.is_empty())
+                    .map(str::to_string),
+            ),
+            "rsinit.firmware" => self.firmware = Some(value.unwrap_or("/lib/firmware").to_string()),
+            "rd.shell" => {
+                self.shell = match value {
+                    None | Some("1") => true,
+                    Some("0") => false,
+                    Some(value) => return Err(format!("Invalid rd.shell '{value}'").into()),
+                }
+            }
+            "loglevel" => self.loglevel = parse_kernel_loglevel(key, value)?,
+            "quiet" => self.loglevel = LevelFilter::Warn,
+            "rsinit.loglevel" => {
+                let value = ensure_value(key, value)?;
+                self.loglevel = value
+                    .parse()
+                    .map_err(|_| format!("Invalid rsinit.loglevel '{value}'"))?;
+            }
+            "rd.break" => match value {
+                None => self.breaks.push(BreakStage::PrePivot),
+                Some(stages) => {
+                    for stage in stages.split(',').filter(|stage| !stage.is_empty()) {
+                        self.breaks.push(BreakStage::parse(stage)?);
+                    }
+                }
+            },
+            "rsinit.overlay" => match ensure_value(key, value)? {
+                "tmpfs" => self.tmpfs_overlay = true,
+                overlay => return Err(format!("Unsupported root overlay '{overlay}'").into()),
+            },
+            "rsinit.overlay_dir" => match ensure_value(key, value)?.trim_end_matches('/') {
+                dir if dir.starts_with('/') => self.overlay_dirs.push(dir.to_string()),
+                _ => return Err(format!("'{key}=' must be an absolute directory").into()),
+            },
+            "rsinit.slot" => self.slot = Some(Slot::parse(ensure_value(key, value)?)?),
+            "rsinit.slot_try" => self.slot_try = true,
+            "rsinit.slot_device" => self.slot_device = Some(parse_device_offset(key, value)?),
+            "rsinit.bootchooser" => {
+                self.bootchooser.device = Some(parse_device_offset(key, value)?)
+            }
+            "rsinit.bootchooser.magic"
+            | "rsinit.bootchooser.stride"
+            | "rsinit.bootchooser.copies" => {
+                let value = ensure_value(key, value)?;
+                let number =
+                    parse_number(value).ok_or(format!("Failed to parse '{key}={value}'"))?;
+                match key {
+                    "rsinit.bootchooser.magic" => {
+                        self.bootchooser.magic = u32::try_from(number)
+                            .map_err(|e| format!("Failed to parse '{key}={value}': {e}"))?
+                    }
+                    "rsinit.bootchooser.stride" => self.bootchooser.stride = number,
+                    _ => {
+                        self.bootchooser.copies = u32::try_from(number)
+                            .ok()
+                            .filter(|&copies| copies > 0)
+                            .ok_or(format!("Invalid number of copies '{key}={value}'"))?
+                    }
+                }
+            }
+            _ if key.starts_with("rsinit.root.")
+                || key.starts_with("rsinit.rootfstype.")
+                || key.starts_with("rsinit.rootflags.") =>
+            {
+                let (name, slot) = key["rsinit.".len()..].split_once('.').unwrap_or_default();
+                let slot_root = &mut self.slot_roots[Slot::parse(slot)? as usize];
+                let value = Some(ensure_value(key, value)?.to_string());
+                match name {
+                    "root" => slot_root.root = value,
+                    "rootfstype" => slot_root.rootfstype = value,
+                    _ => slot_root.rootflags = value,
+                }
+            }
+            _ if key.starts_with("rsinit.integrity.") => {
+                let name = format!(
+                    "INTEGRITY_{}",
+                    key["rsinit.integrity.".len()..].to_uppercase()
+                );
+                self.integrity_params
+                    .push((name, ensure_value(key, value)?.to_string()));
+            }
+            _ if key.starts_with("rsinit.verity.") => {
+                let name = format!("VERITY_{}", key["rsinit.verity.".len()..].to_uppercase());
+                self.verity_params
+                    .push((name, ensure_value(key, value)?.to_string()));
+            }
             _ => {
+                self.extra
+                    .push((key.to_string(), value.map(str::to_string)));
                 for cb in callbacks {
                     cb.call(key, value)?
                 }
@@ -69,8 +895,52 @@ impl CmdlineOptions {
         Ok(())
     }
 
-    fn parse_nfsroot(&mut self) -> Result<()> {
-        if self.root.as_deref() != Some("/dev/nfs") && self.rootfstype.as_deref() != Some("nfs") {
+    /// Use `device` as rootfs instead of `root=`, e.g. a device-mapper target on top of it. The
+    /// fallbacks from `root=` are dropped, so the boot cannot fall back to a device that bypasses
+    /// `device`.
+    pub fn replace_root(&mut self, device: String) {
+        self.root = Some(device);
+        self.root_fallbacks.clear();
+    }
+
+    /// Use the root filesystem of `slot`. Settings of the slot replace `root=`, `rootfstype=`
+    /// and `rootflags=`, the slot is passed to the next init as `RSINIT_SLOT` and
+    /// `RSINIT_SLOT_TRY`.
+    pub fn select_slot(&mut self, slot: Slot) -> Result<()> {
+        let slot_root = &self.slot_roots[slot as usize];
+        let root = slot_root
+            .root
+            .clone()
+            .ok_or(format!("rsinit.root.{}= is missing", slot.as_str()))?;
+        self.root = Some(root);
+        self.root_fallbacks.clear();
+        if slot_root.rootfstype.is_some() {
+            self.rootfstype = slot_root.rootfstype.clone();
+        }
+        if slot_root.rootflags.is_some() {
+            self.rootflags = slot_root.rootflags.clone();
+        }
+        self.slot = Some(slot);
+
+        self.env
+            .retain(|(name, _)| !name.starts_with("RSINIT_SLOT"));
+        self.env.push(("RSINIT_SLOT".into(), slot.as_str().into()));
+        if self.slot_try {
+            self.env.push(("RSINIT_SLOT_TRY".into(), "1".into()));
+        }
+        Ok(())
+    }
+
+    /// Derive `root=` and `rootflags=` from `nfsroot=`.
+    ///
+    /// NFSv4 is used with `root=/dev/nfs4` or a `vers=4[.x]`/`v4[.x]` option in `nfsroot=`. The
+    /// path is relative to the pseudo-root of the server then, and `nolock` is not added.
+    pub fn parse_nfsroot(&mut self) -> Result<()> {
+        let nfs4 = self.root.as_deref() == Some("/dev/nfs4");
+        if self.root.as_deref() != Some("/dev/nfs")
+            && !nfs4
+            && self.rootfstype.as_deref() != Some("nfs")
+        {
             return Ok(());
         }
 
@@ -78,29 +948,42 @@ impl CmdlineOptions {
             .nfsroot
             .as_ref()
             .ok_or("Missing nfsroot command-line option!")?;
-        let mut rootflags = String::from("nolock");
-        let mut nfsroot = match nfsroot_option.split_once(',') {
-            None => nfsroot_option.to_string(),
-            Some((root, flags)) => {
+        let (mut nfsroot, flags) = match nfsroot_option.split_once(',') {
+            None => (nfsroot_option.to_string(), ""),
+            Some((root, flags)) => (root.to_string(), flags),
+        };
+        let version = flags.split(',').find_map(|flag| {
+            flag.strip_prefix("vers=")
+                .or(flag.strip_prefix("nfsvers="))
+                .or(flag.strip_prefix('v'))
+                .filter(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+        });
+        let mut rootflags = match version {
+            Some(version) if version.starts_with('4') => String::new(),
+            None if nfs4 => String::from("vers=4"),
+            _ => String::from("nolock"),
+        };
+        if !flags.is_empty() {
+            if !rootflags.is_empty() {
                 rootflags.push(',');
-                rootflags.push_str(flags);
-                root.to_string()
             }
-        };
+            rootflags.push_str(flags);
+        }
         rootflags.push_str(",addr=");
         if !nfsroot.contains(':') {
-            let pnp = read_file("/proc/net/pnp")?;
-            for line in pnp.lines() {
-                match line.split_once(' ') {
-                    None => continue,
-                    Some((key, value)) => {
-                        if key == "bootserver" {
-                            nfsroot = value.to_owned() + ":" + &nfsroot;
-                            rootflags.push_str(value);
-                            break;
-                        }
-                    }
-                }
+            let bootserver =
+                match &self.bootserver {
+                    Some(bootserver) => Some(bootserver.clone()),
+                    None => read_file_limited("/proc/net/pnp", 4096)?
+                        .lines()
+                        .find_map(|line| match line.split_once(' ') {
+                            Some(("bootserver", value)) => Some(value.to_string()),
+                            _ => None,
+                        }),
+                };
+            if let Some(bootserver) = bootserver {
+                nfsroot = bootserver.clone() + ":" + &nfsroot;
+                rootflags.push_str(&bootserver);
             }
         } else {
             let (bootserver, _) = nfsroot
@@ -131,6 +1014,8 @@ where
 #[derive(Default)]
 pub struct CmdlineOptionsParser<'a> {
     callbacks: Vec<Box<dyn CmdlineCallback + 'a>>,
+    /* Whether `ro` (true) or `rw` (false) was given explicitly */
+    read_only: Option<bool>,
 }
 
 impl<'a> CmdlineOptionsParser<'a> {
@@ -143,66 +1028,195 @@ impl<'a> CmdlineOptionsParser<'a> {
     }
 
     pub fn parse_file(&mut self, path: &str) -> Result<CmdlineOptions> {
-        let cmdline = read_file(path)?;
+        let cmdline = read_file_limited(path, MAX_CMDLINE_SIZE)?;
         self.parse_string(&cmdline)
     }
 
+    /// Parse the cmdline from all `sources` in order, so options from later sources override
+    /// earlier ones. See [`read_cmdline_sources`] for the supported sources.
+    pub fn parse_sources(&mut self, sources: &[&[&str]]) -> Result<CmdlineOptions> {
+        let mut options = CmdlineOptions::default();
+        self.parse_sources_onto(&mut options, sources)?;
+        self.finish(options)
+    }
+
+    /// Like [`Self::parse_sources`], but additionally apply the `rsinit.*` options from the
+    /// bootconfig at `bootconfig` after the cmdline.
+    pub fn parse_sources_and_bootconfig(
+        &mut self,
+        sources: &[&[&str]],
+        bootconfig: &str,
+    ) -> Result<CmdlineOptions> {
+        let mut options = CmdlineOptions::default();
+        self.parse_sources_onto(&mut options, sources)?;
+        let bootconfig =
+            read_bootconfig(bootconfig).map_err(|e| e.or_kind(RsinitError::Cmdline))?;
+        self.parse_options_onto(&mut options, bootconfig)?;
+        self.finish(options)
+    }
+
+    fn parse_sources_onto(
+        &mut self,
+        options: &mut CmdlineOptions,
+        sources: &[&[&str]],
+    ) -> Result<()> {
+        for cmdline in read_cmdline_sources(sources)? {
+            self.parse_string_onto(options, &cmdline)?;
+        }
+        Ok(())
+    }
+
     pub fn parse_string(&mut self, cmdline: &str) -> Result<CmdlineOptions> {
         let mut options = CmdlineOptions::default();
-        let mut have_value = false;
-        let mut quoted = false;
-        let mut key = &cmdline[0..0];
-        let mut start = 0;
-
-        for (i, c) in cmdline.char_indices() {
-            let mut skip = false;
-            match c {
-                '=' => {
-                    if !have_value {
-                        skip = true;
-                        key = &cmdline[start..i];
-                        start = i;
-                    }
-                    have_value = true;
-                }
-                '"' => {
-                    quoted = !quoted;
-                    skip = true;
-                }
-                ' ' | '\n' if !quoted => {
-                    if !have_value {
-                        key = &cmdline[start..i];
-                    }
-                    if !key.is_empty() {
-                        options.parse_option(
-                            key,
-                            if have_value {
-                                Some(&cmdline[start..i])
-                            } else {
-                                None
-                            },
-                            &mut self.callbacks,
-                        )?;
-                    }
-                    key = &cmdline[0..0];
-                    have_value = false;
-                    skip = true;
-                }
-                _ => {}
+        self.parse_string_onto(&mut options, cmdline)?;
+        self.finish(options)
+    }
+
+    /// Apply the options from `cmdline` to the already parsed `options`.
+    ///
+    /// Unlike [`Self::parse_string`], options derived from several others (e.g. from
+    /// `nfsroot=`) are not updated.
+    pub fn parse_string_onto(&mut self, options: &mut CmdlineOptions, cmdline: &str) -> Result<()> {
+        let mut tokens = tokens(cmdline);
+        self.parse_options_onto(options, &mut tokens)?;
+        if let Some(init_args) = tokens.init_args() {
+            options.init_args = init_args;
+        }
+        Ok(())
+    }
+
+    /// Apply already split key/value pairs to `options` like [`Self::parse_string_onto`].
+    pub fn parse_options_onto(
+        &mut self,
+        options: &mut CmdlineOptions,
+        pairs: impl IntoIterator<Item = (String, Option<String>)>,
+    ) -> Result<()> {
+        for (key, value) in pairs {
+            match key.as_str() {
+                "ro" => self.read_only = Some(true),
+                "rw" => self.read_only = Some(false),
+                _ => (),
             }
-            if skip {
-                start = i + 1;
+            options
+                .parse_option(&key, value.as_deref(), &mut self.callbacks)
+                .map_err(|e| e.or_kind(RsinitError::Cmdline))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, mut options: CmdlineOptions) -> Result<CmdlineOptions> {
+        if let Some(slot) = options.slot {
+            options
+                .select_slot(slot)
+                .map_err(|e| e.or_kind(RsinitError::Cmdline))?;
+        }
+
+        /* Network and overlay roots are often not meant to be read-only, so only an explicit
+         * `ro` makes them read-only */
+        let network_root = matches!(options.root.as_deref(), Some("/dev/nfs" | "/dev/nfs4"))
+            || matches!(
+                options.rootfstype.as_deref(),
+                Some("9p" | "nfs" | "overlay")
+            );
+        if self.read_only.take().is_none() && network_root {
+            options.rootfsflags.remove(MsFlags::MS_RDONLY);
+        }
+
+        #[cfg(feature = "net")]
+        if let Some(ip) = &options.ip {
+            let config = IpConfig::parse(ip).map_err(|e| e.or_kind(RsinitError::Cmdline))?;
+            /* With DHCP in rsinit, the bootserver is only known once the network is configured */
+            if config.dhcp_interface().is_some() {
+                return Ok(options);
             }
+            options.bootserver = config.server.map(|server| server.to_string());
         }
 
-        options.parse_nfsroot()?;
+        options
+            .parse_nfsroot()
+            .map_err(|e| e.or_kind(RsinitError::Cmdline))?;
 
         Ok(options)
     }
 }
 
+/// The maximum size of a cmdline source. This is far more than the kernel supports for its
+/// cmdline.
+const MAX_CMDLINE_SIZE: u64 = 64 * 1024;
+
+/// The sources of the cmdline used by [`crate::init::InitContext::setup`]. The device tree
+/// bootargs are only used if `/proc/cmdline` is empty, because the kernel usually uses them as
+/// its cmdline.
+pub const CMDLINE_SOURCES: [&[&str]; 2] = [
+    &["/proc/cmdline", "/proc/device-tree/chosen/bootargs"],
+    &["/etc/cmdline.d"],
+];
+
+/// Convert the content of a cmdline source to a single line cmdline.
+///
+/// Trailing NUL bytes are stripped, so the device tree `chosen/bootargs` property can be used
+/// as is. Lines are joined with spaces and comment lines starting with `#` are dropped.
+pub fn cmdline_from_bytes(content: &[u8]) -> String {
+    String::from_utf8_lossy(content)
+        .lines()
+        .map(|line| line.trim_end_matches('\0').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Read the cmdline from each of the `sources`.
+///
+/// Each source is a list of alternatives, of which the first one that exists and is not empty
+/// is used. An alternative is either a file, which may be NUL-terminated like the device tree
+/// bootargs, or a directory with `*.conf` files that are read in sorted order. Lines starting
+/// with `#` are comments.
+pub fn read_cmdline_sources(sources: &[&[&str]]) -> Result<Vec<String>> {
+    let mut cmdlines = Vec::new();
+    for alternatives in sources {
+        for alternative in alternatives.iter() {
+            let cmdline = read_cmdline_source(Path::new(alternative))?;
+            if !cmdline.is_empty() {
+                cmdlines.extend(cmdline);
+                break;
+            }
+        }
+    }
+    Ok(cmdlines)
+}
+
+/// Read the non-empty cmdlines from the file or `*.conf` files of the directory `source`.
+fn read_cmdline_source(source: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    if source.is_dir() {
+        let mut confs = read_dir(source)
+            .map_err(|e| format!("Failed to list {}: {e}", source.display()))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+            .collect::<Vec<_>>();
+        confs.sort();
+        files.extend(confs);
+    } else if source.exists() {
+        files.push(source.to_path_buf());
+    }
+
+    let mut cmdlines = Vec::new();
+    for file in files {
+        let content = read_limited(&file, MAX_CMDLINE_SIZE)?;
+        let cmdline = cmdline_from_bytes(&content);
+        if !cmdline.is_empty() {
+            cmdlines.push(cmdline);
+        }
+    }
+    Ok(cmdlines)
+}
+
 #[cfg(test)]
 mod tests {
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
     use super::*;
 
     #[test]
@@ -231,7 +1245,10 @@ mod tests {
             rootflags: Some("nolock,v3,tcp,addr=192.168.42.23".into()),
             rootfsflags: MsFlags::MS_RDONLY,
             nfsroot: Some("192.168.42.23:/path/to/nfsroot,v3,tcp".into()),
+            ip: Some("dhcp".into()),
             rootfstype: Some("nfs".into()),
+            rootwait: None,
+            extra: vec![("console".into(), Some("ttymxc1,115200n8".into()))],
             ..Default::default()
         };
 
@@ -242,6 +1259,44 @@ mod tests {
         assert_eq!(options, expected);
     }
 
+    #[test]
+    fn test_nfs4() {
+        let cmdline = "root=/dev/nfs nfsroot=192.168.42.23:/nfsroot,vers=4.2,tcp rw\n";
+
+        let expected = CmdlineOptions {
+            root: Some("192.168.42.23:/nfsroot".into()),
+            rootflags: Some("vers=4.2,tcp,addr=192.168.42.23".into()),
+            rootfsflags: MsFlags::empty(),
+            nfsroot: Some("192.168.42.23:/nfsroot,vers=4.2,tcp".into()),
+            rootfstype: Some("nfs".into()),
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/nfs4 nfsroot=192.168.42.23:/nfsroot\n")
+            .expect("failed");
+
+        assert_eq!(
+            options.rootflags.as_deref(),
+            Some("vers=4,addr=192.168.42.23")
+        );
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/nfs nfsroot=192.168.42.23:/nfsroot,v3\n")
+            .expect("failed");
+
+        assert_eq!(
+            options.rootflags.as_deref(),
+            Some("nolock,v3,addr=192.168.42.23")
+        );
+    }
+
     #[test]
     fn test_9p_qemu() {
         let cmdline =
@@ -251,6 +1306,8 @@ mod tests {
             root: Some("/dev/root".into()),
             rootfstype: Some("9p".into()),
             rootflags: Some("trans=virtio".into()),
+            rootfsflags: MsFlags::empty(),
+            extra: vec![("console".into(), Some("ttyAMA0,115200".into()))],
             ..Default::default()
         };
 
@@ -261,6 +1318,29 @@ mod tests {
         assert_eq!(options, expected);
     }
 
+    #[test]
+    fn test_9p_ro() {
+        let cmdline = "root=/dev/root rootfstype=9p rootflags=trans=virtio ro\n";
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options.rootfsflags, MsFlags::MS_RDONLY);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/nfs nfsroot=192.168.42.23:/nfsroot\n")
+            .expect("failed");
+
+        assert_eq!(options.rootfsflags, MsFlags::empty());
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/mmcblk0p1 rootfstype=ext4\n")
+            .expect("failed");
+
+        assert_eq!(options.rootfsflags, MsFlags::MS_RDONLY);
+    }
+
     #[test]
     fn test_9p_usbg() {
         let cmdline = "root=rootdev rootfstype=9p rootflags=trans=usbg,cache=loose,uname=root,dfltuid=0,dfltgid=0,aname=/path/to/9pfsroot rw\n";
@@ -284,12 +1364,14 @@ mod tests {
     }
 
     #[test]
-    fn test_init() {
-        let cmdline = "root=/dev/mmcblk0p1 init=/bin/sh\n";
+    fn test_tmpfs() {
+        let cmdline = "root=none rootfstype=tmpfs rootflags=size=50% rw\n";
 
         let expected = CmdlineOptions {
-            root: Some("/dev/mmcblk0p1".into()),
-            init: "/bin/sh".into(),
+            root: Some("none".into()),
+            rootfstype: Some("tmpfs".into()),
+            rootflags: Some("size=50%".into()),
+            rootfsflags: MsFlags::empty(),
             ..Default::default()
         };
 
@@ -301,9 +1383,26 @@ mod tests {
     }
 
     #[test]
-    fn test_custom_option() {
-        let cmdline = "root=/dev/mmcblk0p1 rsinit.custom=xyz\n";
-        let custom_option = std::cell::RefCell::new(String::new());
+    fn test_init() {
+        let cmdline = "root=/dev/mmcblk0p1 init=/bin/sh\n";
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/mmcblk0p1".into()),
+            init: "/bin/sh".into(),
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+    }
+
+    #[test]
+    fn test_custom_option() {
+        let cmdline = "root=/dev/mmcblk0p1 rsinit.custom=xyz\n";
+        let custom_option = std::cell::RefCell::new(String::new());
 
         let cb = Box::new(|key: &str, value: Option<&str>| {
             if key == "rsinit.custom" {
@@ -337,13 +1436,823 @@ mod tests {
         assert_eq!(options, expected);
     }
 
+    #[test]
+    fn test_rootwait() {
+        let cmdline = "root=/dev/sda1 rootwait rootdelay=3\n";
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/sda1".into()),
+            rootwait: None,
+            rootdelay: Duration::from_secs(3),
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+
+        assert!(matches!(
+            CmdlineOptionsParser::new().parse_string("rootdelay=soon\n"),
+            Err(RsinitError::Cmdline(_))
+        ));
+    }
+
+    #[test]
+    fn test_no_trailing_newline() {
+        let cmdline = "root=/dev/sda1 rw";
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/sda1".into()),
+            rootfsflags: MsFlags::empty(),
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rw root=/dev/sda1")
+            .expect("failed");
+
+        assert_eq!(options, expected);
+    }
+
+    #[test]
+    fn test_quoted() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 rootflags=\"data=a b\" \"init=/sbin/my init\"\n")
+            .expect("failed");
+
+        assert_eq!(options.rootflags.as_deref(), Some("data=a b"));
+        assert_eq!(options.init, "/sbin/my init");
+    }
+
+    #[test]
+    fn test_escapes() {
+        let cmdline = r#"root=/dev/sda1 rootflags=a\ b,c\"d,e\\f init=/sbin/my\040init"#;
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options.rootflags.as_deref(), Some(r#"a b,c"d,e\f"#));
+        assert_eq!(options.init, "/sbin/my init");
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(r#"rootflags="x=\"1 2\"" init=C:\path\777"#)
+            .expect("failed");
+
+        assert_eq!(options.rootflags.as_deref(), Some(r#"x="1 2""#));
+        assert_eq!(options.init, r"C:\path\777");
+    }
+
+    #[test]
+    fn test_bootconfig() {
+        let dir = temp_dir().join("rsinit-test-bootconfig");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).expect("mkdir failed");
+        write(
+            dir.join("cmdline"),
+            "root=/dev/sda1 rsinit.overlay_dir=/etc\n",
+        )
+        .expect("write failed");
+        write(
+            dir.join("bootconfig"),
+            "kernel.root = \"/dev/sda2\"\nrsinit.overlay_dir = \"/var\", \"/srv\"\nrsinit.dry_run = \"\"\n",
+        )
+        .expect("write failed");
+
+        let cmdline = dir.join("cmdline").to_string_lossy().into_owned();
+        let bootconfig = dir.join("bootconfig").to_string_lossy().into_owned();
+        let options = CmdlineOptionsParser::new()
+            .parse_sources_and_bootconfig(&[&[&cmdline]], &bootconfig)
+            .expect("failed");
+        assert_eq!(options.root.as_deref(), Some("/dev/sda1"));
+        assert_eq!(options.overlay_dirs, ["/etc", "/var", "/srv"]);
+        assert!(options.dry_run);
+
+        let _ = remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_bootargs() {
+        let bootargs = b"console=ttymxc0,115200 root=/dev/mmcblk0p2 rootwait\0";
+        let cmdline = cmdline_from_bytes(bootargs);
+        assert_eq!(
+            cmdline,
+            "console=ttymxc0,115200 root=/dev/mmcblk0p2 rootwait"
+        );
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/mmcblk0p2".into()),
+            rootwait: None,
+            extra: vec![("console".into(), Some("ttymxc0,115200".into()))],
+            ..Default::default()
+        };
+        let options = CmdlineOptionsParser::new()
+            .parse_string(&cmdline)
+            .expect("failed");
+        assert_eq!(options, expected);
+
+        assert_eq!(cmdline_from_bytes(b"\0"), "");
+    }
+
+    #[test]
+    fn test_sources() {
+        let dir = temp_dir().join("rsinit-test-cmdline");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(dir.join("cmdline.d")).expect("mkdir failed");
+        write(dir.join("cmdline"), "root=/dev/sda1 ro quiet\n").expect("write failed");
+        write(dir.join("empty"), "\n").expect("write failed");
+        write(dir.join("bootargs"), "root=/dev/sda0 ro quiet\0").expect("write failed");
+        write(
+            dir.join("cmdline.d/20-rw.conf"),
+            "# development\nrw\nrootwait\n",
+        )
+        .expect("write failed");
+        write(dir.join("cmdline.d/10-root.conf"), "root=/dev/sda2\n").expect("write failed");
+        write(dir.join("cmdline.d/30-ignored"), "root=/dev/sda3\n").expect("write failed");
+
+        let paths = ["cmdline", "bootargs", "missing", "cmdline.d", "empty"]
+            .map(|source| dir.join(source).to_string_lossy().into_owned());
+        let [cmdline, bootargs, missing, cmdline_d, empty] =
+            paths.iter().map(String::as_str).collect::<Vec<_>>()[..]
+        else {
+            unreachable!()
+        };
+
+        let sources: [&[&str]; 3] = [&[cmdline, bootargs], &[missing], &[cmdline_d]];
+        assert_eq!(
+            read_cmdline_sources(&sources).expect("failed"),
+            ["root=/dev/sda1 ro quiet", "root=/dev/sda2", "rw rootwait"]
+        );
+        assert_eq!(
+            read_cmdline_sources(&[&[empty, bootargs], &[missing, empty]]).expect("failed"),
+            ["root=/dev/sda0 ro quiet"]
+        );
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/sda2".into()),
+            rootfsflags: MsFlags::empty(),
+            rootwait: None,
+            loglevel: LevelFilter::Warn,
+            ..Default::default()
+        };
+        let options = CmdlineOptionsParser::new()
+            .parse_sources(&sources)
+            .expect("failed");
+        assert_eq!(options, expected);
+
+        let _ = remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_extra() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("console=ttyS0,115200 root=/dev/sda1 vendor.flag console=ttymxc0 rw\n")
+            .expect("failed");
+
+        assert_eq!(
+            options.extra,
+            [
+                ("console".to_string(), Some("ttyS0,115200".to_string())),
+                ("vendor.flag".to_string(), None),
+                ("console".to_string(), Some("ttymxc0".to_string())),
+            ]
+        );
+        assert_eq!(options.extra_value("console"), Some(Some("ttymxc0")));
+        assert_eq!(options.extra_value("vendor.flag"), Some(None));
+        assert_eq!(options.extra_value("root"), None);
+    }
+
+    #[test]
+    fn test_consoles() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string(
+                "console=ttyS0,115200n8 console=uart8250,mmio,0x3f8 console=/dev/tty1 console\n",
+            )
+            .expect("failed");
+
+        assert_eq!(
+            options.consoles(),
+            [
+                Console {
+                    device: "ttyS0".into(),
+                    options: Some("115200n8".into()),
+                },
+                Console {
+                    device: "tty1".into(),
+                    options: None,
+                },
+            ]
+        );
+        assert_eq!(parse_console(""), None);
+    }
+
+    #[test]
+    fn test_root_fallbacks() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/mmcblk0p2,PARTLABEL=rootfs-b,/dev/mmcblk0p3\n")
+            .expect("failed");
+
+        assert_eq!(options.root.as_deref(), Some("/dev/mmcblk0p2"));
+        assert_eq!(
+            options.root_fallbacks,
+            ["PARTLABEL=rootfs-b", "/dev/mmcblk0p3"]
+        );
+
+        let mut replaced = options;
+        replaced.replace_root("/dev/dm-0".into());
+        assert_eq!(replaced.root.as_deref(), Some("/dev/dm-0"));
+        assert!(replaced.root_fallbacks.is_empty());
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1,/dev/sda2 root=/dev/sdb1\n")
+            .expect("failed");
+
+        assert_eq!(options.root.as_deref(), Some("/dev/sdb1"));
+        assert!(options.root_fallbacks.is_empty());
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("root=,\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_rootflags_append() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rootflags=commit=5,noacl,data=ordered rootflags+=commit=60,user_xattr\n")
+            .expect("failed");
+
+        assert_eq!(
+            options.rootflags.as_deref(),
+            Some("noacl,data=ordered,commit=60,user_xattr")
+        );
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rootflags+=discard rootflags+=discard\n")
+            .expect("failed");
+
+        assert_eq!(options.rootflags.as_deref(), Some("discard"));
+    }
+
+    #[test]
+    fn test_init_args() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 -- init=/bin/sh single\n")
+            .expect("failed");
+
+        assert_eq!(options.root.as_deref(), Some("/dev/sda1"));
+        assert_eq!(options.init, "/sbin/init");
+        assert_eq!(options.init_args, ["init=/bin/sh", "single"]);
+        assert!(options.extra.is_empty());
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 --")
+            .expect("failed");
+        assert!(options.extra.is_empty());
+        assert!(options.init_args.is_empty());
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("init=/bin/myinit -- --foo \"bar baz\" a\\ b --opt=\"x y\" -- \"\"\n")
+            .expect("failed");
+        assert_eq!(options.init, "/bin/myinit");
+        assert_eq!(
+            options.init_args,
+            ["--foo", "bar baz", "a b", "--opt=x y", "--"]
+        );
+    }
+
+    #[test]
+    fn test_cgroup() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.cgroup systemd.unified_cgroup_hierarchy=0\n")
+            .expect("failed");
+        assert!(options.cgroup);
+        assert!(options.cgroup_legacy);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("systemd.unified_cgroup_hierarchy=1\n")
+            .expect("failed");
+        assert!(!options.cgroup);
+        assert!(!options.cgroup_legacy);
+    }
+
+    #[test]
+    fn test_root_retries() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 rsinit.root_retries=0\n")
+            .expect("failed");
+        assert_eq!(options.root_retries, 0);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.root_retries=-1\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_overlay() {
+        let cmdline = "root=/dev/root rsinit.overlay=tmpfs\n";
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/root".into()),
+            tmpfs_overlay: true,
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.overlay=disk\n")
+            .is_err());
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.overlay_dir=/etc rsinit.overlay_dir=/var/lib/\n")
+            .expect("failed");
+        assert_eq!(options.overlay_dirs, ["/etc", "/var/lib"]);
+
+        for dir in ["/", "etc"] {
+            assert!(CmdlineOptionsParser::new()
+                .parse_string(&format!("rsinit.overlay_dir={dir}\n"))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_fsck() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 rsinit.fsck fsck.repair=yes\n")
+            .expect("failed");
+
+        assert!(options.fsck);
+        assert!(!options.fsck_force);
+        assert_eq!(options.fsck_repair, FsckRepair::Yes);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 fsck.mode=force\n")
+            .expect("failed");
+
+        assert!(options.fsck);
+        assert!(options.fsck_force);
+        assert_eq!(options.fsck_repair, FsckRepair::Preen);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 rsinit.fsck fsck.mode=skip\n")
+            .expect("failed");
+
+        assert!(!options.fsck);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("fsck.repair=maybe\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_verity_params() {
+        let cmdline = "rsinit.verity_root=/dev/mmcblk0p1 rsinit.verity.root_hash=c63dc40d rsinit.verity.hash_device=/dev/mmcblk0p2\n";
+
+        let expected = CmdlineOptions {
+            verity_root: Some("/dev/mmcblk0p1".into()),
+            verity_params: vec![
+                ("VERITY_ROOT_HASH".into(), "c63dc40d".into()),
+                ("VERITY_HASH_DEVICE".into(), "/dev/mmcblk0p2".into()),
+            ],
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+
+        assert_eq!(
+            CmdlineOptions::default().verity_pcr,
+            cfg!(feature = "tpm").then_some(11)
+        );
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.verity_pcr=9\n")
+            .expect("failed");
+        assert_eq!(options.verity_pcr, Some(9));
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.verity_pcr=off\n")
+            .expect("failed");
+        assert_eq!(options.verity_pcr, None);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.verity_superblock\n")
+            .expect("failed");
+        assert!(options.verity_superblock);
+    }
+
+    #[test]
+    fn test_integrity() {
+        let cmdline = "rsinit.integrity=PARTLABEL=data rsinit.integrity.tag_size=4 rsinit.integrity.algorithm=crc32c\n";
+
+        let expected = CmdlineOptions {
+            integrity: Some("PARTLABEL=data".into()),
+            integrity_params: vec![
+                ("INTEGRITY_TAG_SIZE".into(), "4".into()),
+                ("INTEGRITY_ALGORITHM".into(), "crc32c".into()),
+            ],
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/sda1 rw rd.break=mount loglevel=4 nosuid_option\n")
+            .expect("failed");
+
+        let json = serde_json::to_value(&options).unwrap();
+        assert_eq!(json["root"], "/dev/sda1");
+        assert_eq!(json["rootfsflags"], serde_json::json!([]));
+        assert_eq!(json["loglevel"], "ERROR");
+
+        let parsed = serde_json::from_value::<CmdlineOptions>(json).unwrap();
+        assert_eq!(parsed, options);
+
+        let parsed = serde_json::from_str::<CmdlineOptions>(
+            r#"{"rootfsflags": ["MS_RDONLY", "MS_NOSUID"]}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.rootfsflags, MsFlags::MS_RDONLY | MsFlags::MS_NOSUID);
+        assert_eq!(parsed.root_retries, 3);
+        assert!(serde_json::from_str::<CmdlineOptions>(r#"{"rootfsflags": ["MS_FOO"]}"#).is_err());
+    }
+
+    #[test]
+    fn test_swap() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string(
+                "rsinit.swap=PARTLABEL=swap rsinit.swap_priority=10 rsinit.swap_required\n",
+            )
+            .expect("failed");
+        assert_eq!(options.swap.as_deref(), Some("PARTLABEL=swap"));
+        assert_eq!(options.swap_priority, Some(10));
+        assert!(options.swap_required);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.swap_priority=32768\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_slot() {
+        let cmdline = "root=/dev/mmcblk0p1 rootfstype=ext4 rsinit.root.a=PARTLABEL=root-a rsinit.root.b=PARTLABEL=root-b rsinit.rootfstype.b=erofs rsinit.slot=b rsinit.slot_try\n";
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+        assert_eq!(options.slot, Some(Slot::B));
+        assert_eq!(options.root.as_deref(), Some("PARTLABEL=root-b"));
+        assert_eq!(options.rootfstype.as_deref(), Some("erofs"));
+        assert!(options.env.contains(&("RSINIT_SLOT".into(), "b".into())));
+        assert!(options
+            .env
+            .contains(&("RSINIT_SLOT_TRY".into(), "1".into())));
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(
+                "root=/dev/sda1 rsinit.root.a=/dev/sda2 rsinit.slot_device=/dev/sda3@512\n",
+            )
+            .expect("failed");
+        assert_eq!(options.slot, None);
+        assert_eq!(options.root.as_deref(), Some("/dev/sda1"));
+        assert_eq!(options.slot_device, Some(("/dev/sda3".into(), 512)));
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.root.c=/dev/sda2\n")
+            .is_err());
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.root.a=/dev/sda2 rsinit.slot=b\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_luks_keyfile() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string(
+                "rsinit.luks=/dev/sda2 rsinit.luks_keyfile=PARTLABEL=key:/keys/root.key\n",
+            )
+            .expect("failed");
+        assert_eq!(
+            options.luks_keyfile,
+            Some(("PARTLABEL=key".into(), "/keys/root.key".into()))
+        );
+
+        for cmdline in [
+            "rsinit.luks_keyfile=/keys/root.key",
+            "rsinit.luks_keyfile=/dev/sdb1:key",
+        ] {
+            assert!(CmdlineOptionsParser::new()
+                .parse_string(&format!("{cmdline}\n"))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_plain_crypt() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.plain_crypt=/dev/sda2 rsinit.plain_crypt.cipher=aes-cbc-essiv:sha256 rsinit.plain_crypt.hash=ripemd160 rsinit.plain_crypt.passphrase_file=/etc/passphrase rsinit.plain_crypt.offset=2048\n")
+            .expect("failed");
+        assert_eq!(
+            options.plain_crypt,
+            PlainCryptOptions {
+                device: Some("/dev/sda2".into()),
+                cipher: "aes-cbc-essiv:sha256".into(),
+                hash: "ripemd160".into(),
+                passphrase_file: Some("/etc/passphrase".into()),
+                offset: 2048,
+                ..Default::default()
+            }
+        );
+
+        for cmdline in [
+            "rsinit.plain_crypt.key_size=255",
+            "rsinit.plain_crypt.key_size=0",
+            "rsinit.plain_crypt.skip=x",
+        ] {
+            assert!(CmdlineOptionsParser::new()
+                .parse_string(&format!("{cmdline}\n"))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_bootchooser() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.bootchooser=/dev/mmcblk0@0x100000 rsinit.bootchooser.magic=0xfee2ea9b rsinit.bootchooser.copies=3\n")
+            .expect("failed");
+        assert_eq!(
+            options.bootchooser,
+            BootchooserOptions {
+                device: Some(("/dev/mmcblk0".into(), 0x100000)),
+                magic: 0xfee2ea9b,
+                stride: 64,
+                copies: 3,
+            }
+        );
+
+        for cmdline in [
+            "rsinit.bootchooser=/dev/mmcblk0@x",
+            "rsinit.bootchooser.magic=0x100000000",
+            "rsinit.bootchooser.copies=0",
+        ] {
+            assert!(CmdlineOptionsParser::new()
+                .parse_string(&format!("{cmdline}\n"))
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_usbg() {
+        let cmdline = "rootfstype=9p rootflags=trans=usbg rsinit.usbg.vid=0x0525 rsinit.usbg.pid=a4a5 rsinit.usbg.serial=42\n";
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options.usbg.vid, 0x0525);
+        assert_eq!(options.usbg.pid, 0xa4a5);
+        assert_eq!(options.usbg.serial, "42");
+        assert_eq!(options.usbg.manufacturer, "Pengutronix e.K.");
+        assert_eq!(options.usbg.udc, None);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.usbg.udc=ci_hdrc.0\n")
+            .expect("failed");
+
+        assert_eq!(options.usbg.udc.as_deref(), Some("ci_hdrc.0"));
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.usbg.vid=pengutronix\n")
+            .is_err());
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.usbg.pid=0x10000\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_modules() {
+        let cmdline = "rd.modules=mmci,ext4 root=/dev/mmcblk0p1 rd.modules=sdhci-pltfm\n";
+
+        let expected = CmdlineOptions {
+            root: Some("/dev/mmcblk0p1".into()),
+            modules: vec!["mmci".into(), "ext4".into(), "sdhci-pltfm".into()],
+            ..Default::default()
+        };
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options, expected);
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_nfs_dhcp() {
+        let cmdline = "root=/dev/nfs nfsroot=/path/to/nfsroot ip=:::::eth0:dhcp\n";
+
+        let mut options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options.root.as_deref(), Some("/dev/nfs"));
+        assert_eq!(options.ip.as_deref(), Some(":::::eth0:dhcp"));
+
+        options.bootserver = Some("192.168.42.23".into());
+        options.parse_nfsroot().expect("failed");
+
+        assert_eq!(
+            options.root.as_deref(),
+            Some("192.168.42.23:/path/to/nfsroot")
+        );
+        assert_eq!(
+            options.rootflags.as_deref(),
+            Some("nolock,addr=192.168.42.23")
+        );
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_nfs_static() {
+        let cmdline = "root=/dev/nfs nfsroot=/path/to/nfsroot,v3 ip=192.168.42.100:192.168.42.23:192.168.42.1:255.255.255.0::eth0:off\n";
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string(cmdline)
+            .expect("failed");
+
+        assert_eq!(options.bootserver.as_deref(), Some("192.168.42.23"));
+        assert_eq!(
+            options.root.as_deref(),
+            Some("192.168.42.23:/path/to/nfsroot")
+        );
+        assert_eq!(
+            options.rootflags.as_deref(),
+            Some("nolock,v3,addr=192.168.42.23")
+        );
+    }
+
+    #[test]
+    fn test_shell() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rd.shell\n")
+            .expect("failed");
+        assert!(options.shell);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rd.shell=0\n")
+            .expect("failed");
+        assert!(!options.shell);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rd.shell=yes\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_break() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rd.break\n")
+            .expect("failed");
+        assert_eq!(options.breaks, vec![BreakStage::PrePivot]);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rd.break=pre-mount,mount rd.break=cmdline\n")
+            .expect("failed");
+        assert_eq!(
+            options.breaks,
+            vec![BreakStage::PreMount, BreakStage::Mount, BreakStage::Cmdline]
+        );
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rd.break=initqueue\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_loglevel() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("root=/dev/root\n")
+            .expect("failed");
+        assert_eq!(options.loglevel, LevelFilter::Trace);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("quiet\n")
+            .expect("failed");
+        assert_eq!(options.loglevel, LevelFilter::Warn);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("loglevel=7\n")
+            .expect("failed");
+        assert_eq!(options.loglevel, LevelFilter::Info);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("quiet rsinit.loglevel=debug\n")
+            .expect("failed");
+        assert_eq!(options.loglevel, LevelFilter::Debug);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.loglevel=verbose\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_reboot_delay() {
+        let parse = |cmdline| {
+            CmdlineOptionsParser::new()
+                .parse_string(cmdline)
+                .expect("failed")
+                .reboot_delay
+        };
+        assert_eq!(parse("root=/dev/root\n"), None);
+        assert_eq!(parse("rsinit.reboot_delay=10\n"), Some(10));
+        assert_eq!(parse("rsinit.reboot_delay=-1\n"), Some(-1));
+        assert_eq!(parse("panic=5\n"), Some(5));
+        assert_eq!(parse("panic=0\n"), Some(-1));
+        assert_eq!(parse("panic=-1\n"), Some(0));
+    }
+
+    #[test]
+    fn test_setenv() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.setenv=FOO=bar rsinit.setenv=PATH=/bin rsinit.setenv=EMPTY=\n")
+            .expect("failed");
+        assert_eq!(
+            options.env,
+            vec![
+                ("FOO".into(), "bar".into()),
+                ("PATH".into(), "/bin".into()),
+                ("EMPTY".into(), "".into()),
+            ]
+        );
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.setenv=FOO\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("resume=PARTLABEL=swap resume_offset=4096\n")
+            .expect("failed");
+        assert_eq!(options.resume.as_deref(), Some("PARTLABEL=swap"));
+        assert_eq!(options.resume_offset, Some(4096));
+        assert!(!options.noresume);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("resume=/dev/sda2 noresume\n")
+            .expect("failed");
+        assert!(options.noresume);
+    }
+
+    #[test]
+    fn test_firmware() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.firmware\n")
+            .expect("failed");
+
+        assert_eq!(options.firmware.as_deref(), Some("/lib/firmware"));
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.firmware=/firmware\n")
+            .expect("failed");
+
+        assert_eq!(options.firmware.as_deref(), Some("/firmware"));
+    }
+
     #[test]
     fn test_rsinit_bind() {
-        let cmdline = "root=/dev/root rsinit.bind_modules\n";
+        let cmdline = "root=/dev/root rsinit.bind_modules rsinit.private_root rsinit.pivot_root rsinit.delete_initramfs rsinit.tmp\n";
 
         let expected = CmdlineOptions {
             root: Some("/dev/root".into()),
+            delete_initramfs: true,
             bind_modules: true,
+            private_root: true,
+            pivot_root: true,
+            tmp: Some("50%".into()),
             ..Default::default()
         };
 
@@ -352,5 +2261,46 @@ mod tests {
             .expect("failed");
 
         assert_eq!(options, expected);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.tmp=64M\n")
+            .expect("failed");
+        assert_eq!(options.tmp.as_deref(), Some("64M"));
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.run=0\n")
+            .expect("failed");
+        assert!(!options.run);
+    }
+
+    #[test]
+    fn test_report() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.report\n")
+            .expect("failed");
+        assert_eq!(options.report.as_deref(), Some(DEFAULT_REPORT_PATH));
+        assert_eq!(options.report_format, ReportFormat::Json);
+
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.report=/run/boot.env rsinit.report_format=env\n")
+            .expect("failed");
+        assert_eq!(options.report.as_deref(), Some("/run/boot.env"));
+        assert_eq!(options.report_format, ReportFormat::Env);
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.report_format=xml\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_reexec() {
+        let options = CmdlineOptionsParser::new()
+            .parse_string("rsinit.reexec=/init.test\n")
+            .expect("failed");
+        assert_eq!(options.reexec.as_deref(), Some("/init.test"));
+
+        assert!(CmdlineOptionsParser::new()
+            .parse_string("rsinit.reexec\n")
+            .is_err());
     }
 }
@@ -0,0 +1,283 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! The parts of the cmdline parser that only work on strings. They only use `core` and `alloc`,
+//! so a `#![no_std]` program, e.g. a bootloader, can share them with rsinit.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Like `ensure_value()` in rsinit, but with the error message as `String`.
+pub fn require_value<'a>(key: &str, value: Option<&'a str>) -> Result<&'a str, String> {
+    value.ok_or(format!("Cmdline option '{key}' must have an argument!"))
+}
+
+/// Split the value of `root=` into the root device and the fallbacks after it.
+pub fn split_roots(value: &str) -> Result<(String, Vec<String>), String> {
+    let mut roots = value
+        .split(',')
+        .filter(|root| !root.is_empty())
+        .map(str::to_string);
+    let root = roots.next().ok_or("root= must not be empty")?;
+    Ok((root, roots.collect()))
+}
+
+/// Merge the comma separated mount options `extra` into `flags`. An option in `extra` replaces
+/// the options with the same name (before `=`) in `flags`.
+pub fn merge_flags(flags: &str, extra: &str) -> String {
+    let name = |option: &str| {
+        option
+            .split_once('=')
+            .map_or(option, |(name, _)| name)
+            .to_string()
+    };
+    let mut merged: Vec<&str> = Vec::new();
+    for option in flags
+        .split(',')
+        .chain(extra.split(','))
+        .filter(|option| !option.is_empty())
+    {
+        merged.retain(|existing| name(existing) != name(option));
+        merged.push(option);
+    }
+    merged.join(",")
+}
+
+/// The value of a `\nnn` octal escape at the start of `bytes`.
+fn octal_escape(bytes: &[u8]) -> Option<u8> {
+    let digits = bytes.get(..3)?;
+    if !digits.iter().all(|digit| (b'0'..=b'7').contains(digit)) {
+        return None;
+    }
+    let value = digits
+        .iter()
+        .fold(0u16, |value, digit| value * 8 + u16::from(digit - b'0'));
+    u8::try_from(value).ok()
+}
+
+/// An iterator over the keys and optional values of the options in a cmdline, see [`tokens`].
+pub struct Tokens<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /* Split whole arguments after `--` instead of options */
+    args: bool,
+    /* The start of the arguments after `--`, once it was reached */
+    separator_end: Option<usize>,
+}
+
+impl Tokens<'_> {
+    /// The arguments after `--`, split with the same quoting and escapes as the options. `None`
+    /// if the cmdline has no `--` or the iterator did not reach it yet.
+    pub fn init_args(&self) -> Option<Vec<String>> {
+        let rest = Tokens {
+            bytes: &self.bytes[self.separator_end?..],
+            pos: 0,
+            args: true,
+            separator_end: None,
+        };
+        Some(rest.map(|(arg, _)| arg).collect())
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = (String, Option<String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.bytes;
+        let mut key = Vec::new();
+        let mut value: Option<Vec<u8>> = None;
+        let mut quoted = false;
+
+        loop {
+            let Some(&byte) = bytes.get(self.pos) else {
+                /* The last option is not followed by whitespace if the cmdline was not read
+                 * from /proc/cmdline */
+                if key == b"--" && value.is_none() && !self.args {
+                    self.separator_end = Some(self.pos);
+                    return None;
+                }
+                if key.is_empty() {
+                    return None;
+                }
+                break;
+            };
+            let mut c = byte;
+            self.pos += 1;
+            match c {
+                b'\\' => {
+                    if let Some(byte) = octal_escape(&bytes[self.pos..]) {
+                        c = byte;
+                        self.pos += 3;
+                    } else if let Some(&byte @ (b' ' | b'"' | b'\\')) = bytes.get(self.pos) {
+                        c = byte;
+                        self.pos += 1;
+                    }
+                }
+                b'"' => {
+                    quoted = !quoted;
+                    continue;
+                }
+                b'=' if value.is_none() && !self.args => {
+                    value = Some(Vec::new());
+                    continue;
+                }
+                b' ' | b'\n' if !quoted => {
+                    if key == b"--" && value.is_none() && !self.args {
+                        self.separator_end = Some(self.pos);
+                        self.pos = bytes.len();
+                        return None;
+                    }
+                    if key.is_empty() {
+                        value = None;
+                        continue;
+                    }
+                    break;
+                }
+                _ => {}
+            }
+            match &mut value {
+                Some(value) => value.push(c),
+                None => key.push(c),
+            }
+        }
+
+        Some((
+            String::from_utf8_lossy(&key).into_owned(),
+            value.map(|value| String::from_utf8_lossy(&value).into_owned()),
+        ))
+    }
+}
+
+/// Split the cmdline into the keys and optional values of the options without interpreting
+/// them. Parsing stops at `--`, the kernel passes everything after it to init as arguments. They
+/// are available from [`Tokens::init_args`].
+///
+/// Double quotes group whitespace into one option and are removed. `\ `, `\"`, `\\` and `\nnn`
+/// (octal) escape a single character, other backslashes are kept as they are. Because of this,
+/// the keys and values are owned strings.
+pub fn tokens(cmdline: &str) -> Tokens<'_> {
+    Tokens {
+        bytes: cmdline.as_bytes(),
+        pos: 0,
+        args: false,
+        separator_end: None,
+    }
+}
+
+/// The options that describe the rootfs and the next init with plain strings.
+#[derive(Debug, Default, PartialEq)]
+pub struct RootOptions {
+    pub root: Option<String>,
+    pub root_fallbacks: Vec<String>,
+    pub rootfstype: Option<String>,
+    pub rootflags: Option<String>,
+    /// Whether `ro` (true) or `rw` (false) was given.
+    pub read_only: Option<bool>,
+    pub init: Option<String>,
+    /// The arguments after `--`.
+    pub init_args: Vec<String>,
+}
+
+impl RootOptions {
+    /// The options that [`RootOptions::parse_option`] applies.
+    pub const KEYS: &'static [&'static str] = &[
+        "root",
+        "rootfstype",
+        "rootflags",
+        "rootflags+",
+        "ro",
+        "rw",
+        "init",
+    ];
+
+    pub fn from_string(cmdline: &str) -> Result<RootOptions, String> {
+        let mut options = RootOptions::default();
+        let mut tokens = tokens(cmdline);
+        for (key, value) in &mut tokens {
+            options.parse_option(&key, value.as_deref())?;
+        }
+        if let Some(init_args) = tokens.init_args() {
+            options.init_args = init_args;
+        }
+        Ok(options)
+    }
+
+    /// Apply the option `key`. Returns false if it is not one of these options.
+    pub fn parse_option(&mut self, key: &str, value: Option<&str>) -> Result<bool, String> {
+        match key {
+            "root" => {
+                let (root, fallbacks) = split_roots(require_value(key, value)?)?;
+                self.root = Some(root);
+                self.root_fallbacks = fallbacks;
+            }
+            "rootfstype" => self.rootfstype = Some(require_value(key, value)?.to_string()),
+            "rootflags" => self.rootflags = value.map(str::to_string),
+            "rootflags+" => {
+                self.rootflags = Some(merge_flags(
+                    self.rootflags.as_deref().unwrap_or_default(),
+                    require_value(key, value)?,
+                ))
+            }
+            "ro" => self.read_only = Some(true),
+            "rw" => self.read_only = Some(false),
+            "init" => self.init = Some(require_value(key, value)?.to_string()),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        let token = |key: &str, value: Option<&str>| (key.to_string(), value.map(str::to_string));
+
+        assert_eq!(
+            tokens("root=/dev/sda1  ro rootflags=\"data=a b\" \"init=/sbin/my init\" empty= =x -- single\n")
+                .collect::<Vec<_>>(),
+            [
+                token("root", Some("/dev/sda1")),
+                token("ro", None),
+                token("rootflags", Some("data=a b")),
+                token("init", Some("/sbin/my init")),
+                token("empty", Some("")),
+            ]
+        );
+        assert_eq!(
+            tokens(r"quiet a\040b=c\ d").collect::<Vec<_>>(),
+            [token("quiet", None), token("a b", Some("c d"))]
+        );
+        assert_eq!(tokens(" \n").count(), 0);
+        assert_eq!(tokens("--=x").collect::<Vec<_>>(), [token("--", Some("x"))]);
+    }
+
+    #[test]
+    fn test_root_options() {
+        let options = RootOptions::from_string(
+            "root=/dev/sda1,/dev/sdb1 rootfstype=ext4 rootflags=a=1 rootflags+=a=2,b quiet rw init=/bin/myinit -- --foo \"bar baz\"\n",
+        )
+        .expect("failed");
+
+        assert_eq!(
+            options,
+            RootOptions {
+                root: Some("/dev/sda1".into()),
+                root_fallbacks: vec!["/dev/sdb1".into()],
+                rootfstype: Some("ext4".into()),
+                rootflags: Some("a=2,b".into()),
+                read_only: Some(false),
+                init: Some("/bin/myinit".into()),
+                init_args: vec!["--foo".into(), "bar baz".into()],
+            }
+        );
+
+        assert!(RootOptions::from_string("root=,").is_err());
+        assert!(RootOptions::from_string("init").is_err());
+    }
+}
@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::termios::tcdrain;
+use nix::unistd::{dup2_stderr, dup2_stdout, pipe2};
+
+use crate::util::Result;
+
+/* How long to wait for the forwarding thread to write the pending output */
+const SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+
+/* The active tee of stdout and stderr, if any */
+static TEE: Mutex<Option<Tee>> = Mutex::new(None);
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Forwards everything written to a pipe to multiple consoles with a thread.
+struct Tee {
+    reader: Arc<File>,
+    /* Held by the thread while forwarding, so nothing is in flight without it */
+    consoles: Arc<Mutex<Vec<File>>>,
+}
+
+impl Tee {
+    /// Start forwarding to `consoles`. Returns the write end of the pipe.
+    fn start(consoles: Vec<File>) -> Result<(Tee, OwnedFd)> {
+        let (reader, writer) =
+            pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("Failed to create pipe: {e}"))?;
+        let tee = Tee {
+            reader: Arc::new(File::from(reader)),
+            consoles: Arc::new(Mutex::new(consoles)),
+        };
+
+        let reader = tee.reader.clone();
+        let consoles = tee.consoles.clone();
+        thread::Builder::new()
+            .name("console".into())
+            .spawn(move || forward(&reader, &consoles))?;
+
+        Ok((tee, writer))
+    }
+
+    /// Wait until the thread has written all pending output and the consoles have sent it.
+    fn sync(&self) {
+        let start = Instant::now();
+        loop {
+            let consoles = lock(&self.consoles);
+            let mut fds = [PollFd::new(self.reader.as_fd(), PollFlags::POLLIN)];
+            let empty = poll(&mut fds, PollTimeout::ZERO).map_or(true, |ready| ready == 0);
+            if empty || start.elapsed() > SYNC_TIMEOUT {
+                for console in consoles.iter() {
+                    let _ = tcdrain(console.as_fd());
+                }
+                return;
+            }
+            drop(consoles);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn forward(reader: &File, consoles: &Mutex<Vec<File>>) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let mut fds = [PollFd::new(reader.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) | Err(Errno::EINTR) => (),
+            Err(_) => return,
+        }
+
+        /* Only this thread reads, so this does not block after poll() */
+        let consoles = lock(consoles);
+        let len = match (&*reader).read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        };
+        for mut console in consoles.iter() {
+            /* A console that fails must not stop the others */
+            let _ = console.write_all(&buf[..len]);
+        }
+    }
+}
+
+/// Mirror stdout and stderr to all `consoles` with a thread. The thread does not survive
+/// `execve()`, so call [`untee_stdio`] before executing the next init. The output of the next
+/// init is not mirrored.
+pub fn tee_stdio(consoles: Vec<File>) -> Result<()> {
+    let (tee, writer) = Tee::start(consoles)?;
+    dup2_stdout(&writer)?;
+    dup2_stderr(&writer)?;
+    *lock(&TEE) = Some(tee);
+    Ok(())
+}
+
+/// Stop mirroring stdout and stderr and only write to the first console again.
+pub fn untee_stdio() -> Result<()> {
+    let Some(tee) = lock(&TEE).take() else {
+        return Ok(());
+    };
+    let _ = io::stdout().flush();
+    tee.sync();
+
+    let consoles = lock(&tee.consoles);
+    if let Some(console) = consoles.first() {
+        dup2_stdout(console)?;
+        dup2_stderr(console)?;
+    }
+    Ok(())
+}
+
+/// Make sure all output is written to the consoles, e.g. before rebooting.
+pub fn sync_consoles() {
+    let _ = io::stdout().flush();
+    match &*lock(&TEE) {
+        Some(tee) => tee.sync(),
+        None => {
+            let _ = tcdrain(io::stdout().as_fd());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, remove_file};
+
+    use nix::unistd::write;
+
+    use super::*;
+
+    #[test]
+    fn test_tee() {
+        let paths = [0, 1]
+            .map(|i| std::env::temp_dir().join(format!("rsinit-tee-{}-{i}", std::process::id())));
+        let consoles = paths
+            .iter()
+            .map(|path| File::create(path).expect("create failed"))
+            .collect();
+        let (tee, writer) = Tee::start(consoles).expect("start failed");
+
+        write(&writer, b"hello\n").expect("write failed");
+        write(&writer, b"world\n").expect("write failed");
+        tee.sync();
+
+        for path in &paths {
+            assert_eq!(read(path).expect("read failed"), b"hello\nworld\n");
+            let _ = remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// SPDX-FileCopyrightText: 2024 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! The device-mapper ioctls shared by the dm-verity and dm-crypt setup.
+
+use std::fs::{File, OpenOptions};
+use std::mem::size_of;
+use std::os::fd::AsRawFd;
+
+use nix::errno::Errno;
+use nix::ioctl_readwrite;
+use nix::libc::dev_t;
+use nix::sys::stat::minor;
+
+use crate::util::{uuid_random, Result};
+
+const DM_VERSION_MAJOR: u32 = 4;
+
+const DM_MAX_TYPE_NAME: usize = 16;
+pub(crate) const DM_NAME_LEN: usize = 128;
+const DM_UUID_LEN: usize = 129;
+
+#[cfg(any(feature = "dmverity", feature = "luks"))]
+pub(crate) const DM_READONLY_FLAG: u32 = 1;
+
+#[repr(C)]
+pub(crate) struct DmIoctl {
+    version: [u32; 3],
+    pub(crate) data_size: u32,
+    pub(crate) data_start: u32,
+    target_count: u32,
+    open_count: u32,
+    flags: u32,
+    event_nr: u32,
+    padding: u32,
+    dev: dev_t,
+    name: [u8; DM_NAME_LEN],
+    pub(crate) uuid: [u8; DM_UUID_LEN],
+    data: [u8; 7],
+}
+
+impl Default for DmIoctl {
+    fn default() -> Self {
+        DmIoctl {
+            version: [0; 3],
+            data_size: u32::default(),
+            data_start: u32::default(),
+            target_count: u32::default(),
+            open_count: u32::default(),
+            flags: u32::default(),
+            event_nr: u32::default(),
+            padding: u32::default(),
+            dev: dev_t::default(),
+            name: [0; DM_NAME_LEN],
+            uuid: [0; DM_UUID_LEN],
+            data: [0; 7],
+        }
+    }
+}
+
+impl DmIoctl {
+    pub(crate) fn uuid(prefix: &str, device: &str) -> String {
+        let rand = uuid_random(device);
+        let mut uuid_str = String::from(prefix);
+        for x in rand {
+            uuid_str.push_str(format!("{x:02x}").as_str());
+        }
+        uuid_str.push('-');
+        uuid_str.push_str(device.rsplit_once('/').unwrap_or(("", device)).1);
+        uuid_str
+    }
+
+    fn init_header(&mut self, size: u32, flags: u32, uuid: &str) {
+        let len = usize::min(uuid.len(), DM_UUID_LEN - 1);
+        let uuid = &uuid.as_bytes()[..len];
+        self.version[0] = DM_VERSION_MAJOR;
+        self.data_size = size;
+        self.data_start = size_of::<DmIoctl>() as u32;
+        self.flags = flags;
+        self.uuid[..uuid.len()].copy_from_slice(uuid);
+    }
+
+    pub(crate) fn new(uuid: &str) -> DmIoctl {
+        let mut create_data = DmIoctl::default();
+        create_data.init_header(size_of::<DmIoctl>() as u32, 0, uuid);
+        create_data
+    }
+}
+
+#[repr(C)]
+pub(crate) struct DmTargetSpec {
+    sector_start: u64,
+    pub(crate) length: u64,
+    status: u32,
+    next: u32,
+    target_type: [u8; DM_MAX_TYPE_NAME],
+}
+
+impl Default for DmTargetSpec {
+    fn default() -> Self {
+        DmTargetSpec {
+            sector_start: u64::default(),
+            length: u64::default(),
+            status: u32::default(),
+            next: u32::default(),
+            target_type: [0; DM_MAX_TYPE_NAME],
+        }
+    }
+}
+
+#[repr(C)]
+pub(crate) struct DmTableLoad {
+    pub(crate) header: DmIoctl,
+    pub(crate) target_spec: DmTargetSpec,
+    pub(crate) params: [u8; 1024],
+}
+
+impl Default for DmTableLoad {
+    fn default() -> Self {
+        DmTableLoad {
+            header: DmIoctl::default(),
+            target_spec: DmTargetSpec::default(),
+            params: [0; 1024],
+        }
+    }
+}
+
+impl DmTableLoad {
+    /// A table with a single `target_type` target of `sectors` length and the target parameters
+    /// `table`.
+    pub(crate) fn with_table(
+        target_type: &str,
+        sectors: u64,
+        flags: u32,
+        table: &str,
+        uuid: &str,
+    ) -> DmTableLoad {
+        let mut table_load_data = DmTableLoad::default();
+        table_load_data
+            .header
+            .init_header(size_of::<DmTableLoad>() as u32, flags, uuid);
+        table_load_data.header.target_count = 1;
+        table_load_data.target_spec.status = 0;
+        table_load_data.target_spec.sector_start = 0;
+        table_load_data.target_spec.length = sectors;
+
+        let target_type = target_type.as_bytes();
+        table_load_data.target_spec.target_type[..target_type.len()].copy_from_slice(target_type);
+
+        let table = table.as_bytes();
+        table_load_data.params[..table.len()].copy_from_slice(table);
+        table_load_data
+    }
+}
+
+const DM_DEV_CREATE_CMD: u8 = 3;
+#[cfg(feature = "luks")]
+const DM_DEV_REMOVE_CMD: u8 = 4;
+const DM_DEV_SUSPEND_CMD: u8 = 6;
+const DM_TABLE_LOAD_CMD: u8 = 9;
+
+ioctl_readwrite!(dm_dev_create, 0xfd, DM_DEV_CREATE_CMD, DmIoctl);
+#[cfg(feature = "luks")]
+ioctl_readwrite!(dm_dev_remove, 0xfd, DM_DEV_REMOVE_CMD, DmIoctl);
+ioctl_readwrite!(dm_table_load, 0xfd, DM_TABLE_LOAD_CMD, DmIoctl);
+ioctl_readwrite!(dm_dev_suspend, 0xfd, DM_DEV_SUSPEND_CMD, DmIoctl);
+
+fn open_control() -> Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .open("/dev/mapper/control")
+        .map_err(|e| format!("Failed to open /dev/mapper/control: {e}").into())
+}
+
+/// Create the device-mapper device `name`, load `table` and resume the device. `load_error`
+/// builds the error message if the kernel rejects the table.
+///
+/// Returns the path of the new dm device.
+pub(crate) fn create_device(
+    name: &str,
+    uuid: &str,
+    table: &mut DmTableLoad,
+    load_error: impl FnOnce(Errno) -> String,
+) -> Result<String> {
+    if name.len() >= DM_NAME_LEN {
+        return Err(format!("device-mapper name '{name}' is too long").into());
+    }
+
+    let f = open_control()?;
+    let dm_fd = f.as_raw_fd();
+
+    let mut create_data = DmIoctl::new(uuid);
+    create_data.name[..name.len()].copy_from_slice(name.as_bytes());
+
+    unsafe { dm_dev_create(dm_fd, &mut create_data) }
+        .map_err(|e| format!("Failed to create dm device: {e}"))?;
+
+    unsafe { dm_table_load(dm_fd, &mut table.header) }.map_err(load_error)?;
+
+    let mut suspend_data = DmIoctl::new(uuid);
+
+    unsafe { dm_dev_suspend(dm_fd, &mut suspend_data) }
+        .map_err(|e| format!("Failed to suspend dm device: {e}"))?;
+
+    Ok(format!("/dev/dm-{}", minor(suspend_data.dev)))
+}
+
+/// Remove the device-mapper device `name`, e.g. a temporary device that is no longer used.
+#[cfg(feature = "luks")]
+pub(crate) fn remove_device(name: &str) -> Result<()> {
+    if name.len() >= DM_NAME_LEN {
+        return Err(format!("device-mapper name '{name}' is too long").into());
+    }
+
+    let f = open_control()?;
+    /* Without UUID, the device is looked up by its name */
+    let mut remove_data = DmIoctl::new("");
+    remove_data.name[..name.len()].copy_from_slice(name.as_bytes());
+
+    unsafe { dm_dev_remove(f.as_raw_fd(), &mut remove_data) }
+        .map_err(|e| format!("Failed to remove dm device {name}: {e}"))?;
+    Ok(())
+}
@@ -1,25 +1,39 @@
 // SPDX-FileCopyrightText: 2024 The rsinit Authors
 // SPDX-License-Identifier: GPL-2.0-only
 
-use std::fs::OpenOptions;
-use std::mem::size_of;
-use std::os::fd::IntoRawFd;
+use std::ffi::CString;
+use std::fmt::Write as _;
+use std::fs::{read, read_dir, File};
+use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
 
-use getrandom::getrandom;
+#[cfg(feature = "tpm")]
+use log::warn;
 use log::{debug, info};
-use nix::ioctl_readwrite;
-use nix::libc::dev_t;
-use nix::sys::stat::minor;
+use nix::errno::Errno;
+use nix::libc;
 
 use crate::cmdline::CmdlineOptions;
-use crate::util::{read_file, wait_for_device, Result};
-
-const DM_VERSION_MAJOR: u32 = 4;
-
-const DM_MAX_TYPE_NAME: usize = 16;
-const DM_NAME_LEN: usize = 128;
-const DM_UUID_LEN: usize = 129;
+use crate::dm::{create_device, DmIoctl, DmTableLoad, DM_NAME_LEN, DM_READONLY_FLAG};
+#[cfg(feature = "tpm")]
+use crate::tpm::{hex, pcr_bank_name, Tpm};
+use crate::util::{
+    read_file_limited, wait_for_device, wait_for_devices, Result, RsinitError, MAX_PARAMS_SIZE,
+};
+
+/// The device-mapper name of the dm-verity rootfs.
+pub const DEFAULT_VERITY_NAME: &str = "verity-rootfs";
+/// The prefix of the device-mapper UUID of the dm-verity rootfs.
+pub const DEFAULT_VERITY_UUID_PREFIX: &str = "rsinit-verity-root-";
+/// The file with the dm-verity parameters of the rootfs in the initramfs.
+pub const DEFAULT_VERITY_PARAMS_PATH: &str = "/verity-params";
+
+const KEY_SPEC_THREAD_KEYRING: i32 = -1;
+
+const VERITY_MAGIC: &[u8] = b"verity\0\0";
+const SUPERBLOCK_SIZE: usize = 512;
+const MAX_SALT_SIZE: usize = 256;
 
 struct VerityParams<'a> {
     data_blocks: &'a str,
@@ -30,18 +44,36 @@ struct VerityParams<'a> {
     salt: &'a str,
     root_hash: &'a str,
     verity_params: (usize, &'a str),
+    fec_device: Option<&'a str>,
+    fec_blocks: &'a str,
+    fec_start: &'a str,
+    fec_roots: &'a str,
+    root_hash_sig: Option<&'a str>,
+    corruption: Option<&'a str>,
+    hash_device: Option<&'a str>,
+    hash_start_block: Option<&'a str>,
+    data_device: Option<&'a str>,
 }
 
 impl<'a> VerityParams<'a> {
     fn from_string(params: &'a str) -> Result<VerityParams<'a>> {
         let mut data_blocks = "";
-        let mut data_sectors = 0;
+        let mut data_sectors = None;
         let mut data_block_size = "";
         let mut hash_block_size = "";
         let mut hash_algorithm = "";
         let mut salt = "";
         let mut root_hash = "";
         let mut verity_params = (1, "ignore_zero_blocks");
+        let mut fec_device = None;
+        let mut fec_blocks = "";
+        let mut fec_start = "";
+        let mut fec_roots = "";
+        let mut root_hash_sig = None;
+        let mut corruption = None;
+        let mut hash_device = None;
+        let mut hash_start_block = None;
+        let mut data_device = None;
 
         for line in params.lines() {
             let (key, value) = match line.split_once('=') {
@@ -52,9 +84,9 @@ impl<'a> VerityParams<'a> {
             match key {
                 "VERITY_DATA_BLOCKS" => data_blocks = value,
                 "VERITY_DATA_SECTORS" => {
-                    data_sectors = value.parse::<u64>().map_err(|e| {
-                        format!("Failed to parse 'VERITY_DATA_SECTORS={data_sectors}: {e}")
-                    })?
+                    data_sectors = Some(value.parse::<u64>().map_err(|e| {
+                        format!("Failed to parse 'VERITY_DATA_SECTORS={value}': {e}")
+                    })?)
                 }
                 "VERITY_DATA_BLOCK_SIZE" => data_block_size = value,
                 "VERITY_HASH_BLOCK_SIZE" => hash_block_size = value,
@@ -62,9 +94,45 @@ impl<'a> VerityParams<'a> {
                 "VERITY_SALT" => salt = value,
                 "VERITY_ROOT_HASH" => root_hash = value,
                 "VERITY_PARAMS" => verity_params = (value.split_ascii_whitespace().count(), value),
+                "VERITY_FEC_DEVICE" => fec_device = Some(value),
+                "VERITY_FEC_BLOCKS" => fec_blocks = value,
+                "VERITY_FEC_START" => fec_start = value,
+                "VERITY_FEC_ROOTS" => fec_roots = value,
+                "VERITY_ROOT_HASH_SIG" => root_hash_sig = Some(value),
+                "VERITY_DATA_DEVICE" => data_device = Some(value),
+                "VERITY_HASH_DEVICE" => hash_device = Some(value),
+                "VERITY_HASH_START_BLOCK" => hash_start_block = Some(value),
+                "VERITY_CORRUPTION" => match value {
+                    "restart_on_corruption" | "panic_on_corruption" | "ignore_corruption" => {
+                        corruption = Some(value)
+                    }
+                    _ => return Err(format!("Invalid VERITY_CORRUPTION={value}").into()),
+                },
                 _ => (),
             }
         }
+        if fec_device.is_some() && [fec_blocks, fec_start, fec_roots].contains(&"") {
+            return Err(
+                "VERITY_FEC_DEVICE requires VERITY_FEC_BLOCKS, VERITY_FEC_START and VERITY_FEC_ROOTS"
+                    .into(),
+            );
+        }
+        let derived = Self::derived_data_sectors(data_blocks, data_block_size);
+        let data_sectors = match (data_sectors, derived) {
+            (Some(data_sectors), Some(derived)) if data_sectors != derived => {
+                return Err(format!(
+                    "VERITY_DATA_SECTORS={data_sectors} does not match the data blocks ({derived} sectors)"
+                )
+                .into())
+            }
+            (Some(data_sectors), _) | (None, Some(data_sectors)) => data_sectors,
+            (None, None) => {
+                return Err(
+                    "VERITY_DATA_SECTORS or VERITY_DATA_BLOCKS and VERITY_DATA_BLOCK_SIZE required"
+                        .into(),
+                )
+            }
+        };
         Ok(VerityParams {
             data_blocks,
             data_sectors,
@@ -74,214 +142,396 @@ impl<'a> VerityParams<'a> {
             salt,
             root_hash,
             verity_params,
+            fec_device,
+            fec_blocks,
+            fec_start,
+            fec_roots,
+            root_hash_sig,
+            corruption,
+            hash_device,
+            hash_start_block,
+            data_device,
         })
     }
-}
 
-#[repr(C)]
-struct DmIoctl {
-    version: [u32; 3],
-    data_size: u32,
-    data_start: u32,
-    target_count: u32,
-    open_count: u32,
-    flags: u32,
-    event_nr: u32,
-    padding: u32,
-    dev: dev_t,
-    name: [u8; DM_NAME_LEN],
-    uuid: [u8; DM_UUID_LEN],
-    data: [u8; 7],
-}
+    /// The size of the data in 512 byte sectors, if the number of data blocks and the block size
+    /// are valid.
+    fn derived_data_sectors(data_blocks: &str, data_block_size: &str) -> Option<u64> {
+        let data_blocks = data_blocks.parse::<u64>().ok()?;
+        let data_block_size = data_block_size.parse::<u64>().ok()?;
+        data_blocks
+            .checked_mul(data_block_size)
+            .map(|size| size / 512)
+    }
 
-impl Default for DmIoctl {
-    fn default() -> Self {
-        DmIoctl {
-            version: [0; 3],
-            data_size: u32::default(),
-            data_start: u32::default(),
-            target_count: u32::default(),
-            open_count: u32::default(),
-            flags: u32::default(),
-            event_nr: u32::default(),
-            padding: u32::default(),
-            dev: dev_t::default(),
-            name: [0; DM_NAME_LEN],
-            uuid: [0; DM_UUID_LEN],
-            data: [0; 7],
+    /// The first block of the hash tree on the hash device. By default, the hash tree follows the
+    /// data on the same device or starts after the veritysetup superblock on a separate device.
+    fn hash_start_block(&self) -> &'a str {
+        match (self.hash_start_block, self.hash_device) {
+            (Some(hash_start_block), _) => hash_start_block,
+            (None, Some(_)) => "1",
+            (None, None) => self.data_blocks,
         }
     }
-}
 
-impl DmIoctl {
-    fn uuid(device: &str) -> Result<String> {
-        let rand = {
-            let mut rand = [0u8; 16];
-            getrandom(&mut rand).map_err(|_| "Getrandom failed")?;
-            rand
-        };
-        let mut uuid_str = String::from("rsinit-verity-root-");
-        for x in rand {
-            uuid_str.push_str(format!("{x:02x}").as_str());
+    /// The number of optional arguments and the optional arguments for the verity table.
+    fn optional_params(&self, uuid: &str) -> (usize, String) {
+        let (mut count, params) = self.verity_params;
+        let mut params = params.to_string();
+        if let Some(fec_device) = self.fec_device {
+            count += 8;
+            params.push_str(&format!(
+                " use_fec_from_device {fec_device} fec_start {} fec_blocks {} fec_roots {}",
+                self.fec_start, self.fec_blocks, self.fec_roots
+            ));
         }
-        uuid_str.push('-');
-        uuid_str.push_str(device.rsplit_once('/').unwrap_or(("", device)).1);
-        Ok(uuid_str)
+        if let Some(corruption) = self.corruption {
+            count += 1;
+            params.push(' ');
+            params.push_str(corruption);
+        }
+        if self.root_hash_sig.is_some() {
+            count += 2;
+            params.push_str(&format!(" root_hash_sig_key_desc {}", sig_key_desc(uuid)));
+        }
+        (count, params)
     }
 
-    fn init_header(&mut self, size: u32, flags: u32, uuid: &str) {
-        let len = usize::min(uuid.len(), DM_UUID_LEN - 1);
-        let uuid = &uuid.as_bytes()[..len];
-        self.version[0] = DM_VERSION_MAJOR;
-        self.data_size = size;
-        self.data_start = size_of::<DmIoctl>() as u32;
-        self.flags = flags;
-        self.uuid[..uuid.len()].copy_from_slice(uuid);
+    /// The dm-verity target table for `data_device`. The kernel expects the parameters in this
+    /// exact order and the number of optional arguments before them.
+    fn table(&self, data_device: &str, uuid: &str) -> String {
+        let (optional_count, optional_params) = self.optional_params(uuid);
+        format!(
+            "1 {} {} {} {} {} {} {} {} {} {} {}",
+            data_device,
+            self.hash_device.unwrap_or(data_device),
+            self.data_block_size,
+            self.hash_block_size,
+            self.data_blocks,
+            self.hash_start_block(),
+            self.hash_algorithm,
+            self.root_hash,
+            self.salt,
+            optional_count,
+            optional_params
+        )
     }
+}
 
-    fn new(uuid: &str) -> DmIoctl {
-        let mut create_data = DmIoctl::default();
-        create_data.init_header(size_of::<DmIoctl>() as u32, 0, uuid);
-        create_data
-    }
+/// The description of the key that contains the root hash signature.
+fn sig_key_desc(uuid: &str) -> String {
+    format!("{uuid}-roothash-sig")
 }
 
-#[repr(C)]
-struct DmTargetSpec {
-    sector_start: u64,
-    length: u64,
-    status: u32,
-    next: u32,
-    target_type: [u8; DM_MAX_TYPE_NAME],
+/// Add the root hash signature as user key to the thread keyring where dm-verity will find it
+/// when the table is loaded.
+fn add_sig_key(desc: &str, sig: &[u8]) -> Result<()> {
+    let key_type = CString::new("user")?;
+    let desc = CString::new(desc)?;
+    Errno::result(unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            key_type.as_ptr(),
+            desc.as_ptr(),
+            sig.as_ptr(),
+            sig.len(),
+            KEY_SPEC_THREAD_KEYRING,
+        )
+    })
+    .map_err(|e| format!("Failed to add the root hash signature to the keyring: {e}"))?;
+    Ok(())
 }
 
-impl Default for DmTargetSpec {
-    fn default() -> Self {
-        DmTargetSpec {
-            sector_start: u64::default(),
-            length: u64::default(),
-            status: u32::default(),
-            next: u32::default(),
-            target_type: [0; DM_MAX_TYPE_NAME],
-        }
+impl DmTableLoad {
+    fn new(params: &VerityParams, root_device: &str, uuid: &str) -> DmTableLoad {
+        let table = params.table(root_device, uuid);
+        debug!("Configuring dm-verity with table = '{table}'");
+        DmTableLoad::with_table(
+            "verity",
+            params.data_sectors,
+            DM_READONLY_FLAG,
+            &table,
+            uuid,
+        )
     }
 }
 
-#[repr(C)]
-struct DmTableLoad {
-    header: DmIoctl,
-    target_spec: DmTargetSpec,
-    params: [u8; 1024],
+/// The parameters from the superblock written by `veritysetup format` at the start of
+/// `hash_device`, in the format of `/verity-params`. `None` if there is no superblock.
+fn read_superblock(hash_device: &str) -> Result<Option<String>> {
+    let mut superblock = [0u8; SUPERBLOCK_SIZE];
+    File::open(hash_device)
+        .and_then(|mut f| f.read_exact(&mut superblock))
+        .map_err(|e| format!("Failed to read the dm-verity superblock of {hash_device}: {e}"))?;
+    parse_superblock(&superblock).map_err(|e| format!("{hash_device}: {e}").into())
 }
 
-impl Default for DmTableLoad {
-    fn default() -> Self {
-        DmTableLoad {
-            header: DmIoctl::default(),
-            target_spec: DmTargetSpec::default(),
-            params: [0; 1024],
-        }
+fn parse_superblock(superblock: &[u8]) -> Result<Option<String>> {
+    if superblock.len() < SUPERBLOCK_SIZE || !superblock.starts_with(VERITY_MAGIC) {
+        return Ok(None);
+    }
+    let u32_at =
+        |offset: usize| u32::from_le_bytes(superblock[offset..offset + 4].try_into().unwrap());
+
+    let version = u32_at(8);
+    let hash_type = u32_at(12);
+    if version != 1 || hash_type != 1 {
+        return Err(format!(
+            "Unsupported dm-verity superblock version {version} with hash type {hash_type}"
+        )
+        .into());
+    }
+    let algorithm = &superblock[32..64];
+    let algorithm = &algorithm[..algorithm.iter().position(|&c| c == 0).unwrap_or(32)];
+    let algorithm =
+        std::str::from_utf8(algorithm).map_err(|_| "Invalid hash algorithm in the superblock")?;
+    let data_block_size = u32_at(64);
+    let hash_block_size = u32_at(68);
+    let data_blocks = u64::from_le_bytes(superblock[72..80].try_into().unwrap());
+    let salt_size = u16::from_le_bytes(superblock[80..82].try_into().unwrap()) as usize;
+    if salt_size > MAX_SALT_SIZE {
+        return Err(format!("Invalid salt size {salt_size} in the superblock").into());
     }
+
+    let salt = match &superblock[88..88 + salt_size] {
+        [] => "-".to_string(),
+        salt => salt.iter().fold(String::new(), |mut buf, b| {
+            let _ = write!(buf, "{b:02x}");
+            buf
+        }),
+    };
+    Ok(Some(format!(
+        "VERITY_DATA_BLOCKS={data_blocks}
+VERITY_DATA_BLOCK_SIZE={data_block_size}
+VERITY_HASH_BLOCK_SIZE={hash_block_size}
+VERITY_HASH_ALGORITHM={algorithm}
+VERITY_SALT={salt}
+"
+    )))
 }
 
-impl DmTableLoad {
-    fn new(params: &VerityParams, root_device: &str, uuid: &str) -> DmTableLoad {
-        let mut table_load_data = DmTableLoad::default();
-        table_load_data
-            .header
-            .init_header(size_of::<DmTableLoad>() as u32, DM_READONLY_FLAG, uuid);
-        table_load_data.header.target_count = 1;
-        table_load_data.target_spec.status = 0;
-        table_load_data.target_spec.sector_start = 0;
-        table_load_data.target_spec.length = params.data_sectors;
-
-        let target_type = "verity\0".as_bytes();
-        table_load_data.target_spec.target_type[..target_type.len()].copy_from_slice(target_type);
-
-        let table_str = format!(
-            "1 {} {} {} {} {} {} {} {} {} {} {}\0",
-            root_device,
-            root_device,
-            params.data_block_size,
-            params.hash_block_size,
-            params.data_blocks,
-            params.data_blocks,
-            params.hash_algorithm,
-            params.root_hash,
-            params.salt,
-            params.verity_params.0,
-            params.verity_params.1
-        );
-        let table = table_str.as_bytes();
-        table_load_data.params[..table.len()].copy_from_slice(table);
-        debug!("Configuring dm-verity with table = '{table_str}'");
-        table_load_data
+/// Activate the dm-verity device `name` for `data_device` with the parameters in `param_data`
+/// (the format of `/verity-params`). `uuid_prefix` is used to generate a unique UUID for the
+/// device.
+///
+/// Returns the path of the new dm device.
+pub fn activate_dmverity(
+    name: &str,
+    uuid_prefix: &str,
+    data_device: &str,
+    param_data: &str,
+    timeout: Option<Duration>,
+) -> Result<String> {
+    if name.len() >= DM_NAME_LEN {
+        return Err(format!("dm-verity device name '{name}' is too long").into());
     }
+
+    let params = VerityParams::from_string(param_data)?;
+    let devices = [Some(data_device), params.hash_device, params.fec_device];
+    wait_for_devices(&devices.into_iter().flatten().collect::<Vec<_>>(), timeout)?;
+
+    info!(
+        "Configuring dm-verity device {name} with root-hash = {}",
+        params.root_hash
+    );
+
+    let uuid = DmIoctl::uuid(uuid_prefix, data_device);
+    if let Some(root_hash_sig) = params.root_hash_sig {
+        let sig = read(root_hash_sig)
+            .map_err(|e| format!("Failed to read root hash signature {root_hash_sig}: {e}"))?;
+        add_sig_key(&sig_key_desc(&uuid), &sig)?;
+    }
+
+    let mut table_load_data = DmTableLoad::new(&params, data_device, &uuid);
+
+    create_device(name, &uuid, &mut table_load_data, |e| match e {
+        Errno::EKEYREJECTED | Errno::ENOKEY | Errno::EBADMSG | Errno::EKEYEXPIRED
+            if params.root_hash_sig.is_some() =>
+        {
+            format!(
+                "Root hash signature verification failed: {e}. Is the signing key in the .builtin_trusted_keys keyring?"
+            )
+        }
+        e => format!("Failed to load dm table: {e}"),
+    })
 }
 
-const DM_READONLY_FLAG: u32 = 1;
+/// Activate the dm-verity device `name` for `data_device` with the parameters from the file
+/// `params_path`.
+///
+/// Returns the path of the new dm device.
+pub fn prepare_dmverity_volume(
+    name: &str,
+    uuid_prefix: &str,
+    data_device: &str,
+    params_path: &str,
+    timeout: Option<Duration>,
+) -> Result<String> {
+    let param_data = read_file_limited(params_path, MAX_PARAMS_SIZE)?;
+    activate_dmverity(name, uuid_prefix, data_device, &param_data, timeout)
+}
 
-const DM_DEV_CREATE_CMD: u8 = 3;
-const DM_DEV_SUSPEND_CMD: u8 = 6;
-const DM_TABLE_LOAD_CMD: u8 = 9;
+/// Extend `pcr` in all allocated banks with the hash of the root hash, so the rootfs is part of
+/// the measured boot. Boards without a TPM still boot, failures are only logged.
+#[cfg(feature = "tpm")]
+fn measure_root_hash(pcr: u32, root_hash: &str) {
+    if !Path::new("/dev/tpmrm0").exists() {
+        debug!("No TPM found, not measuring the dm-verity root hash");
+        return;
+    }
+    match Tpm::open().and_then(|mut tpm| tpm.measure(pcr, root_hash.as_bytes())) {
+        Ok(digests) => info!(
+            "Extended PCR {pcr} with the dm-verity root hash: {}",
+            digests
+                .iter()
+                .map(|(bank, digest)| format!("{}:{}", pcr_bank_name(*bank), hex(digest)))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        Err(e) => warn!("Failed to measure the dm-verity root hash: {e}"),
+    }
+}
 
-ioctl_readwrite!(dm_dev_create, 0xfd, DM_DEV_CREATE_CMD, DmIoctl);
-ioctl_readwrite!(dm_table_load, 0xfd, DM_TABLE_LOAD_CMD, DmIoctl);
-ioctl_readwrite!(dm_dev_suspend, 0xfd, DM_DEV_SUSPEND_CMD, DmIoctl);
+/// The dm-verity parameters of the rootfs from `params_path` and the command-line. `None` if
+/// neither has any.
+fn root_param_data(params_path: &str, options: &CmdlineOptions) -> Result<Option<String>> {
+    let have_params_file = Path::new(params_path).exists();
+    if !have_params_file && options.verity_params.is_empty() {
+        return Ok(None);
+    }
 
-pub fn prepare_dmverity(options: &mut CmdlineOptions) -> Result<bool> {
-    if !Path::new("/verity-params").exists() {
-        return Ok(false);
+    /* Later lines override earlier ones, so the cmdline takes precedence */
+    let mut param_data = if have_params_file {
+        read_file_limited(params_path, MAX_PARAMS_SIZE)?
+    } else {
+        String::new()
+    };
+    for (key, value) in &options.verity_params {
+        param_data.push_str(&format!("\n{key}={value}"));
     }
+    Ok(Some(param_data))
+}
+
+/// Activate the dm-verity rootfs on `rsinit.verity_root=` with the parameters from
+/// `params_path` and the command-line, and replace `root` with the new dm device.
+///
+/// Returns false if there are no dm-verity parameters or the rootfs type does not use a block
+/// device.
+pub fn activate_verity(params_path: &str, options: &mut CmdlineOptions) -> Result<bool> {
+    activate_verity_root(params_path, options).map_err(|e| e.or_kind(RsinitError::Verity))
+}
+
+fn activate_verity_root(params_path: &str, options: &mut CmdlineOptions) -> Result<bool> {
+    let Some(mut param_data) = root_param_data(params_path, options)? else {
+        return Ok(false);
+    };
     match options.rootfstype.as_deref() {
-        Some("nfs") | Some("9p") => return Ok(false),
+        Some("nfs") | Some("9p") | Some("tmpfs") | Some("ramfs") => return Ok(false),
         _ => (),
     }
     let root_device = options
         .verity_root
         .as_ref()
         .ok_or("No verity root device")?;
-    wait_for_device(root_device)?;
-
-    let param_data = read_file("/verity-params")?;
-    let params = VerityParams::from_string(&param_data)?;
-
-    info!(
-        "Configuring dm-verity rootfs with root-hash = {}",
-        params.root_hash
-    );
-
-    let f = OpenOptions::new()
-        .write(true)
-        .open("/dev/mapper/control")
-        .map_err(|e| format!("Failed to open /dev/mapper/control: {e}"))?;
-    let dm_fd = f.into_raw_fd();
-
-    let uuid = DmIoctl::uuid(root_device)?;
-    let mut create_data = DmIoctl::new(&uuid);
-    let name = "verity-rootfs\0".as_bytes();
-    create_data.name[..name.len()].copy_from_slice(name);
-
-    unsafe { dm_dev_create(dm_fd, &mut create_data) }
-        .map_err(|e| format!("Failed to create dm device: {e}"))?;
 
-    let mut table_load_data = DmTableLoad::new(&params, root_device, &uuid);
+    if options.verity_superblock {
+        /* The superblock only has the geometry, so everything else still overrides it. Without
+         * the geometry, the parameters cannot be parsed completely yet. */
+        let hash_device = param_data
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .filter(|(key, _)| key.trim() == "VERITY_HASH_DEVICE")
+            .map(|(_, value)| value.trim().to_string())
+            .next_back()
+            .ok_or("rsinit.verity_superblock requires VERITY_HASH_DEVICE")?;
+        wait_for_device(&hash_device, options.rootwait)?;
+        match read_superblock(&hash_device)? {
+            Some(superblock_params) => {
+                debug!("Using the dm-verity superblock on {hash_device}");
+                param_data.insert_str(0, &superblock_params);
+            }
+            None if Path::new(params_path).exists() => {
+                info!("No dm-verity superblock on {hash_device}, using {params_path}")
+            }
+            None => return Err(format!("No dm-verity superblock on {hash_device}").into()),
+        }
+    }
 
-    unsafe { dm_table_load(dm_fd, &mut table_load_data.header) }
-        .map_err(|e| format!("Failed to load dm table: {e}"))?;
+    let device = activate_dmverity(
+        DEFAULT_VERITY_NAME,
+        DEFAULT_VERITY_UUID_PREFIX,
+        root_device,
+        &param_data,
+        options.rootwait,
+    )?;
+    options.replace_root(device);
+
+    #[cfg(feature = "tpm")]
+    if let Some(pcr) = options.verity_pcr {
+        measure_root_hash(pcr, VerityParams::from_string(&param_data)?.root_hash);
+    }
 
-    let mut suspend_data = DmIoctl::new(&uuid);
+    Ok(true)
+}
 
-    unsafe { dm_dev_suspend(dm_fd, &mut suspend_data) }
-        .map_err(|e| format!("Failed to suspend dm device: {e}"))?;
+/// Activate the additional volumes described by `/verity-params.<name>` files. The data device
+/// is specified with `VERITY_DATA_DEVICE` in each file.
+///
+/// A volume for the root device replaces `root`, all others are recorded in
+/// `options.verity_volumes`.
+fn prepare_dmverity_volumes(options: &mut CmdlineOptions, mut have_root: bool) -> Result<bool> {
+    let mut names = read_dir("/")
+        .map_err(|e| format!("Failed to list /: {e}"))?
+        .flatten()
+        .filter_map(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix("verity-params."))
+                .map(str::to_string)
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+
+    for name in names {
+        let path = format!("/verity-params.{name}");
+        let param_data = read_file_limited(&path, MAX_PARAMS_SIZE)?;
+        let data_device = VerityParams::from_string(&param_data)?
+            .data_device
+            .ok_or(format!("VERITY_DATA_DEVICE missing in {path}"))?
+            .to_string();
+
+        let device = activate_dmverity(
+            &format!("verity-{name}"),
+            &format!("rsinit-verity-{name}-"),
+            &data_device,
+            &param_data,
+            options.rootwait,
+        )?;
+
+        if !have_root && options.root.as_deref() == Some(data_device.as_str()) {
+            options.replace_root(device);
+            have_root = true;
+        } else {
+            options.verity_volumes.push((name, device));
+        }
+    }
 
-    options.root = Some(format!("/dev/dm-{}", minor(suspend_data.dev)));
+    Ok(have_root)
+}
 
-    Ok(true)
+/// Activate the dm-verity rootfs with the parameters from [`DEFAULT_VERITY_PARAMS_PATH`] and
+/// the additional volumes from `/verity-params.<name>`.
+pub fn prepare_dmverity(options: &mut CmdlineOptions) -> Result<bool> {
+    activate_verity_root(DEFAULT_VERITY_PARAMS_PATH, options)
+        .and_then(|have_root| prepare_dmverity_volumes(options, have_root))
+        .map_err(|e| e.or_kind(RsinitError::Verity))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::mem::size_of;
+
     use super::*;
 
     #[test]
@@ -341,4 +591,276 @@ VERITY_DATA_SECTORS=212992";
             expected_table
         );
     }
+
+    #[test]
+    fn test_data_sectors() {
+        let param_data = "
+VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973
+VERITY_ROOT_HASH=c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df";
+
+        let params = VerityParams::from_string(param_data).expect("parsing params failed");
+        assert_eq!(params.data_sectors, 212992);
+
+        let consistent = format!("{param_data}\nVERITY_DATA_SECTORS=212992");
+        assert!(VerityParams::from_string(&consistent).is_ok());
+
+        let inconsistent = format!("{param_data}\nVERITY_DATA_SECTORS=212990");
+        assert!(VerityParams::from_string(&inconsistent).is_err());
+
+        let missing = param_data.replace("VERITY_DATA_BLOCK_SIZE=4096", "");
+        assert!(VerityParams::from_string(&missing).is_err());
+        let missing = format!("{missing}\nVERITY_DATA_SECTORS=212992");
+        assert!(VerityParams::from_string(&missing).is_ok());
+    }
+
+    #[test]
+    fn test_fec() {
+        let param_data = "
+VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973
+VERITY_ROOT_HASH=c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df
+VERITY_DATA_SECTORS=212992
+VERITY_FEC_DEVICE=/dev/mmcblk3p3
+VERITY_FEC_BLOCKS=26832
+VERITY_FEC_START=0
+VERITY_FEC_ROOTS=2";
+
+        let root_device = "/dev/mmcblk3p2";
+        let uuid = "rsinit-verity-root-test-uuid".to_string();
+
+        let params = VerityParams::from_string(param_data).expect("parsing params failed");
+        let table_load_data = DmTableLoad::new(&params, root_device, &uuid);
+        let expected_table = *b"1 /dev/mmcblk3p2 /dev/mmcblk3p2 4096 4096 26624 26624 sha256 c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973 9 ignore_zero_blocks use_fec_from_device /dev/mmcblk3p3 fec_start 0 fec_blocks 26832 fec_roots 2\0";
+        assert_eq!(
+            table_load_data.params[..expected_table.len()],
+            expected_table
+        );
+
+        let param_data = "
+VERITY_FEC_DEVICE=/dev/mmcblk3p3
+VERITY_FEC_ROOTS=2";
+        assert!(VerityParams::from_string(param_data).is_err());
+    }
+
+    #[test]
+    fn test_hash_device() {
+        let param_data = "
+VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973
+VERITY_ROOT_HASH=c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df
+VERITY_DATA_SECTORS=212992
+VERITY_HASH_DEVICE=/dev/mmcblk3p3";
+
+        let root_device = "/dev/mmcblk3p2";
+        let uuid = "rsinit-verity-root-test-uuid".to_string();
+
+        let params = VerityParams::from_string(param_data).expect("parsing params failed");
+        let table_load_data = DmTableLoad::new(&params, root_device, &uuid);
+        let expected_table = *b"1 /dev/mmcblk3p2 /dev/mmcblk3p3 4096 4096 26624 1 sha256 c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973 1 ignore_zero_blocks\0";
+        assert_eq!(
+            table_load_data.params[..expected_table.len()],
+            expected_table
+        );
+
+        let param_data = format!("{param_data}\nVERITY_HASH_START_BLOCK=0");
+        let params = VerityParams::from_string(&param_data).expect("parsing params failed");
+        let table_load_data = DmTableLoad::new(&params, root_device, &uuid);
+        let expected_table = *b"1 /dev/mmcblk3p2 /dev/mmcblk3p3 4096 4096 26624 0 sha256";
+        assert_eq!(
+            table_load_data.params[..expected_table.len()],
+            expected_table
+        );
+    }
+
+    fn superblock(salt: &[u8]) -> Vec<u8> {
+        let mut superblock = vec![0u8; SUPERBLOCK_SIZE];
+        superblock[..8].copy_from_slice(VERITY_MAGIC);
+        superblock[8..12].copy_from_slice(&1u32.to_le_bytes());
+        superblock[12..16].copy_from_slice(&1u32.to_le_bytes());
+        superblock[32..38].copy_from_slice(b"sha256");
+        superblock[64..68].copy_from_slice(&4096u32.to_le_bytes());
+        superblock[68..72].copy_from_slice(&4096u32.to_le_bytes());
+        superblock[72..80].copy_from_slice(&26624u64.to_le_bytes());
+        superblock[80..82].copy_from_slice(&(salt.len() as u16).to_le_bytes());
+        superblock[88..88 + salt.len()].copy_from_slice(salt);
+        superblock
+    }
+
+    #[test]
+    fn test_superblock() {
+        let param_data = parse_superblock(&superblock(&[0xa2, 0x24, 0x90, 0x81]))
+            .expect("parsing superblock failed")
+            .expect("no superblock");
+        assert_eq!(
+            param_data,
+            "VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a2249081
+"
+        );
+
+        let param_data =
+            format!("{param_data}VERITY_ROOT_HASH=c63dc40d\nVERITY_HASH_DEVICE=/dev/mmcblk3p3");
+        let params = VerityParams::from_string(&param_data).expect("parsing params failed");
+        let uuid = "rsinit-verity-root-test-uuid".to_string();
+        let table_load_data = DmTableLoad::new(&params, "/dev/mmcblk3p2", &uuid);
+        let expected_table =
+            *b"1 /dev/mmcblk3p2 /dev/mmcblk3p3 4096 4096 26624 1 sha256 c63dc40d a2249081 1";
+        assert_eq!(
+            table_load_data.params[..expected_table.len()],
+            expected_table
+        );
+
+        let param_data = parse_superblock(&superblock(&[]))
+            .expect("parsing superblock failed")
+            .expect("no superblock");
+        assert!(param_data.contains("VERITY_SALT=-\n"));
+
+        let mut unsupported = superblock(&[]);
+        unsupported[8] = 2;
+        assert!(parse_superblock(&unsupported).is_err());
+        assert_eq!(
+            parse_superblock(&[0u8; SUPERBLOCK_SIZE]).expect("parsing failed"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_corruption() {
+        let param_data = "
+VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973
+VERITY_ROOT_HASH=c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df
+VERITY_DATA_SECTORS=212992
+VERITY_CORRUPTION=restart_on_corruption";
+
+        let root_device = "/dev/mmcblk3p2";
+        let uuid = "rsinit-verity-root-test-uuid".to_string();
+
+        let params = VerityParams::from_string(param_data).expect("parsing params failed");
+        let table_load_data = DmTableLoad::new(&params, root_device, &uuid);
+        let expected_table = *b"1 /dev/mmcblk3p2 /dev/mmcblk3p2 4096 4096 26624 26624 sha256 c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973 2 ignore_zero_blocks restart_on_corruption\0";
+        assert_eq!(
+            table_load_data.params[..expected_table.len()],
+            expected_table
+        );
+
+        assert!(VerityParams::from_string("VERITY_CORRUPTION=reboot").is_err());
+    }
+
+    #[test]
+    fn test_root_hash_sig() {
+        let param_data = "
+VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973
+VERITY_ROOT_HASH=c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df
+VERITY_DATA_SECTORS=212992
+VERITY_ROOT_HASH_SIG=/verity-roothash.p7s";
+
+        let root_device = "/dev/mmcblk3p2";
+        let uuid = "rsinit-verity-root-test-uuid".to_string();
+
+        let params = VerityParams::from_string(param_data).expect("parsing params failed");
+        let table_load_data = DmTableLoad::new(&params, root_device, &uuid);
+        let expected_table = *b"1 /dev/mmcblk3p2 /dev/mmcblk3p2 4096 4096 26624 26624 sha256 c63dc40d73bdbb4093e3c54592182a6b74ea9e611145ba498033b696c6e072df a224908192cf3202b8c3eda4a5f5c320a82f2f750681e1cb30bac367b08f3973 3 ignore_zero_blocks root_hash_sig_key_desc rsinit-verity-root-test-uuid-roothash-sig\0";
+        assert_eq!(
+            table_load_data.params[..expected_table.len()],
+            expected_table
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let param_data = "
+VERITY_DATA_BLOCKS=26624
+VERITY_DATA_BLOCK_SIZE=4096
+VERITY_HASH_BLOCK_SIZE=4096
+VERITY_HASH_ALGORITHM=sha256
+VERITY_SALT=a224
+VERITY_ROOT_HASH=c63d";
+        let uuid = "rsinit-verity-root-test-uuid";
+
+        let params = VerityParams::from_string(param_data).expect("parsing params failed");
+        assert_eq!(
+            params.table("/dev/mmcblk3p2", uuid),
+            "1 /dev/mmcblk3p2 /dev/mmcblk3p2 4096 4096 26624 26624 sha256 c63d a224 1 ignore_zero_blocks"
+        );
+
+        let param_data = format!(
+            "{param_data}
+VERITY_PARAMS=check_at_most_once
+VERITY_HASH_DEVICE=/dev/mmcblk3p3
+VERITY_HASH_START_BLOCK=8
+VERITY_FEC_DEVICE=/dev/mmcblk3p4
+VERITY_FEC_BLOCKS=26832
+VERITY_FEC_START=0
+VERITY_FEC_ROOTS=2
+VERITY_CORRUPTION=panic_on_corruption
+VERITY_ROOT_HASH_SIG=/verity-roothash.p7s"
+        );
+        let params = VerityParams::from_string(&param_data).expect("parsing params failed");
+        assert_eq!(
+            params.table("/dev/mmcblk3p2", uuid),
+            "1 /dev/mmcblk3p2 /dev/mmcblk3p3 4096 4096 26624 8 sha256 c63d a224 12 check_at_most_once use_fec_from_device /dev/mmcblk3p4 fec_start 0 fec_blocks 26832 fec_roots 2 panic_on_corruption root_hash_sig_key_desc rsinit-verity-root-test-uuid-roothash-sig"
+        );
+    }
+
+    #[test]
+    fn test_root_param_data() {
+        let path =
+            std::env::temp_dir().join(format!("rsinit-verity-params-{}", std::process::id()));
+        let path = path.to_str().expect("invalid path");
+        let mut options = CmdlineOptions::default();
+        assert_eq!(root_param_data(path, &options).expect("failed"), None);
+
+        std::fs::write(path, "VERITY_ROOT_HASH=1234\nVERITY_SALT=-").expect("write failed");
+        options
+            .verity_params
+            .push(("VERITY_ROOT_HASH".into(), "abcd".into()));
+        let param_data = root_param_data(path, &options)
+            .expect("failed")
+            .expect("no params");
+        let _ = std::fs::remove_file(path);
+        assert_eq!(
+            param_data,
+            "VERITY_ROOT_HASH=1234\nVERITY_SALT=-\nVERITY_ROOT_HASH=abcd"
+        );
+        assert_eq!(
+            VerityParams::from_string(&format!(
+                "{param_data}\nVERITY_DATA_BLOCKS=1\nVERITY_DATA_BLOCK_SIZE=4096"
+            ))
+            .expect("parsing params failed")
+            .root_hash,
+            "abcd"
+        );
+    }
+
+    #[test]
+    fn test_uuid() {
+        let uuid = DmIoctl::uuid("rsinit-verity-appfs-", "/dev/mmcblk3p4");
+        assert!(uuid.starts_with("rsinit-verity-appfs-"));
+        assert!(uuid.ends_with("-mmcblk3p4"));
+        assert_eq!(
+            uuid.len(),
+            "rsinit-verity-appfs-".len() + 32 + "-mmcblk3p4".len()
+        );
+    }
 }
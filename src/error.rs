@@ -0,0 +1,144 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::error::Error;
+use std::ffi::NulError;
+use std::{fmt, io};
+
+use log::SetLoggerError;
+use nix::errno::Errno;
+
+/// The errors returned by rsinit.
+///
+/// Most errors only carry a message for the log. The variant tells callers embedding rsinit
+/// what kind of step failed. More variants may be added, so matches need a wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RsinitError {
+    /// mount(2) failed with `errno`.
+    Mount { errno: Errno, message: String },
+    /// An invalid kernel cmdline option.
+    Cmdline(String),
+    /// A device was not found or could not be resolved.
+    Device(String),
+    /// Setting up a dm-verity device failed.
+    Verity(String),
+    /// Unlocking an encrypted device failed.
+    Luks(String),
+    /// Setting up a dm-integrity device failed.
+    Integrity(String),
+    /// An I/O error without further context.
+    Io(io::Error),
+    /// A failed system call without further context.
+    Errno(Errno),
+    /// Any other failure.
+    Other(String),
+}
+
+impl RsinitError {
+    /// Turn an error without a specific kind into `kind` with the same message.
+    ///
+    /// I/O errors and failed system calls are returned unchanged, so [`Error::source`] still
+    /// returns the underlying error.
+    pub fn or_kind(self, kind: fn(String) -> RsinitError) -> RsinitError {
+        match self {
+            RsinitError::Other(message) => kind(message),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for RsinitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RsinitError::Mount { message, .. }
+            | RsinitError::Cmdline(message)
+            | RsinitError::Device(message)
+            | RsinitError::Verity(message)
+            | RsinitError::Luks(message)
+            | RsinitError::Integrity(message)
+            | RsinitError::Other(message) => write!(f, "{message}"),
+            RsinitError::Io(e) => write!(f, "{e}"),
+            RsinitError::Errno(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for RsinitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RsinitError::Mount { errno, .. } | RsinitError::Errno(errno) => Some(errno),
+            RsinitError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for RsinitError {
+    fn from(message: String) -> Self {
+        RsinitError::Other(message)
+    }
+}
+
+impl From<&str> for RsinitError {
+    fn from(message: &str) -> Self {
+        RsinitError::Other(message.to_string())
+    }
+}
+
+impl From<io::Error> for RsinitError {
+    fn from(e: io::Error) -> Self {
+        RsinitError::Io(e)
+    }
+}
+
+impl From<Errno> for RsinitError {
+    fn from(e: Errno) -> Self {
+        RsinitError::Errno(e)
+    }
+}
+
+impl From<NulError> for RsinitError {
+    fn from(e: NulError) -> Self {
+        RsinitError::Other(e.to_string())
+    }
+}
+
+impl From<fmt::Error> for RsinitError {
+    fn from(e: fmt::Error) -> Self {
+        RsinitError::Other(e.to_string())
+    }
+}
+
+impl From<SetLoggerError> for RsinitError {
+    fn from(e: SetLoggerError) -> Self {
+        RsinitError::Other(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_or_kind() {
+        let e = RsinitError::from("bad table").or_kind(RsinitError::Verity);
+        assert!(matches!(&e, RsinitError::Verity(message) if message == "bad table"));
+        assert!(e.source().is_none());
+
+        let e = RsinitError::from(Errno::ENOENT).or_kind(RsinitError::Verity);
+        assert!(matches!(e, RsinitError::Errno(Errno::ENOENT)));
+        assert!(e.source().is_some());
+
+        let e =
+            RsinitError::from(io::Error::from(io::ErrorKind::NotFound)).or_kind(RsinitError::Luks);
+        assert!(matches!(&e, RsinitError::Io(_)));
+        assert!(e.source().is_some());
+
+        let e = RsinitError::from("bad key").or_kind(RsinitError::Luks);
+        assert!(matches!(
+            e.or_kind(RsinitError::Cmdline),
+            RsinitError::Luks(_)
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::{read, read_dir, write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::uevent::UeventSocket;
+use crate::util::{dry_run, read_file, Result};
+
+/// Answer a firmware request in `request` (a directory in /sys/class/firmware) with the
+/// firmware from `dir`.
+fn serve_request(request: &Path, dir: &str) -> Result<()> {
+    let uevent = read_file(&request.join("uevent").to_string_lossy())?;
+    let name = uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("FIRMWARE="))
+        .ok_or(format!("No firmware name in {}", request.display()))?;
+    let loading = request.join("loading");
+
+    let firmware = match read(format!("{dir}/{name}")) {
+        Ok(firmware) => firmware,
+        Err(e) => {
+            /* Tell the kernel to give up instead of waiting for the timeout */
+            let _ = write(&loading, "-1");
+            return Err(format!("Failed to read firmware {dir}/{name}: {e}").into());
+        }
+    };
+
+    info!("Loading firmware {name}");
+    let result = write(&loading, "1")
+        .and_then(|_| write(request.join("data"), &firmware))
+        .and_then(|_| write(&loading, "0"));
+    if let Err(e) = result {
+        let _ = write(&loading, "-1");
+        return Err(format!("Failed to load firmware {name}: {e}").into());
+    }
+    Ok(())
+}
+
+/// Answer all pending firmware requests.
+fn serve_requests(dir: &str) {
+    let Ok(entries) = read_dir("/sys/class/firmware") else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let request = entry.path();
+        if !request.join("loading").exists() {
+            continue;
+        }
+        if let Err(e) = serve_request(&request, dir) {
+            warn!("{e}");
+        }
+    }
+}
+
+/// Load firmware from `dir` for drivers in the initramfs.
+///
+/// The kernel is told to look in `dir` itself. In addition, a background thread answers
+/// requests that fall back to the sysfs interface in /sys/class/firmware. It runs until the
+/// next init is started.
+pub fn setup_firmware_loader(dir: &str) -> Result<()> {
+    if dry_run() {
+        info!("Would load firmware from {dir}");
+        return Ok(());
+    }
+    let path_param = "/sys/module/firmware_class/parameters/path";
+    if Path::new(path_param).exists() {
+        write(path_param, dir).map_err(|e| format!("Failed to write to {path_param}: {e}"))?;
+    }
+
+    if !Path::new("/sys/class/firmware").exists() {
+        debug!("No firmware fallback interface, relying on direct firmware loading");
+        return Ok(());
+    }
+
+    let dir = dir.to_string();
+    let uevents = UeventSocket::open()?;
+    thread::Builder::new()
+        .name("firmware".into())
+        .spawn(move || loop {
+            serve_requests(&dir);
+            if let Err(e) = uevents.wait_for_add(Duration::from_secs(1)) {
+                warn!("{e}");
+                thread::sleep(Duration::from_secs(1));
+            }
+        })
+        .map_err(|e| format!("Failed to start the firmware loader: {e}"))?;
+
+    Ok(())
+}
@@ -0,0 +1,238 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::path::Path;
+use std::time::Duration;
+
+use log::{info, warn};
+use nix::mount::MsFlags;
+
+use crate::blkid::resolve_device;
+use crate::mount::{do_mount, mount_regular};
+use crate::util::{read_file, wait_for_device, Result};
+
+/// A filesystem from an fstab file.
+#[derive(Debug, PartialEq)]
+pub struct MountSpec {
+    pub source: String,
+    /// The mountpoint in the rootfs.
+    pub target: String,
+    /// `None` for `auto`, to probe the usual filesystems.
+    pub fstype: Option<String>,
+    /// The filesystem specific options that are passed to the kernel as mount data.
+    pub options: String,
+    pub flags: MsFlags,
+    /// Only warn if the mount fails (`nofail`).
+    pub nofail: bool,
+}
+
+/// Split the fstab mount options into the mount flags, the filesystem specific options and
+/// whether `nofail` is set. Options only meaningful to userspace tools are dropped.
+fn parse_options(options: &str) -> (MsFlags, String, bool) {
+    let mut flags = MsFlags::empty();
+    let mut data = Vec::new();
+    let mut nofail = false;
+
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        match option {
+            "ro" => flags.insert(MsFlags::MS_RDONLY),
+            "rw" => flags.remove(MsFlags::MS_RDONLY),
+            "nosuid" => flags.insert(MsFlags::MS_NOSUID),
+            "suid" => flags.remove(MsFlags::MS_NOSUID),
+            "nodev" => flags.insert(MsFlags::MS_NODEV),
+            "dev" => flags.remove(MsFlags::MS_NODEV),
+            "noexec" => flags.insert(MsFlags::MS_NOEXEC),
+            "exec" => flags.remove(MsFlags::MS_NOEXEC),
+            "sync" => flags.insert(MsFlags::MS_SYNCHRONOUS),
+            "async" => flags.remove(MsFlags::MS_SYNCHRONOUS),
+            "dirsync" => flags.insert(MsFlags::MS_DIRSYNC),
+            "noatime" => flags.insert(MsFlags::MS_NOATIME),
+            "atime" => flags.remove(MsFlags::MS_NOATIME),
+            "nodiratime" => flags.insert(MsFlags::MS_NODIRATIME),
+            "diratime" => flags.remove(MsFlags::MS_NODIRATIME),
+            "relatime" => flags.insert(MsFlags::MS_RELATIME),
+            "norelatime" => flags.remove(MsFlags::MS_RELATIME),
+            "strictatime" => flags.insert(MsFlags::MS_STRICTATIME),
+            "lazytime" => flags.insert(MsFlags::MS_LAZYTIME),
+            "bind" => flags.insert(MsFlags::MS_BIND),
+            "rbind" => flags.insert(MsFlags::MS_BIND | MsFlags::MS_REC),
+            "silent" => flags.insert(MsFlags::MS_SILENT),
+            "loud" => flags.remove(MsFlags::MS_SILENT),
+            "nofail" => nofail = true,
+            "defaults" | "auto" | "noauto" | "user" | "nouser" | "users" | "owner" | "group"
+            | "_netdev" => (),
+            _ if option.starts_with("x-") || option.starts_with("comment=") => (),
+            _ => data.push(option),
+        }
+    }
+    (flags, data.join(","), nofail)
+}
+
+/// Parse the `content` of an fstab file. Entries with `noauto` and swap are skipped. With
+/// `initrd_only`, only entries with the `x-initrd.mount` option are returned.
+///
+/// The dump and pass fields are optional and ignored.
+pub fn parse_fstab(content: &str, initrd_only: bool) -> Result<Vec<MountSpec>> {
+    let mut mounts = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (source, target, fstype, options) = match fields[..] {
+            [source, target, fstype] => (source, target, fstype, "defaults"),
+            [source, target, fstype, options, ..] if fields.len() <= 6 => {
+                (source, target, fstype, options)
+            }
+            _ => return Err(format!("Invalid fstab entry '{line}'").into()),
+        };
+
+        let options = options.split(',').collect::<Vec<_>>();
+        if options.contains(&"noauto")
+            || (initrd_only && !options.contains(&"x-initrd.mount"))
+            || fstype == "swap"
+        {
+            continue;
+        }
+
+        let (flags, options, nofail) = parse_options(&options.join(","));
+        mounts.push(MountSpec {
+            source: source.to_string(),
+            target: target.to_string(),
+            fstype: match fstype {
+                "auto" => None,
+                fstype => Some(fstype.to_string()),
+            },
+            options,
+            flags,
+            nofail,
+        });
+    }
+    Ok(mounts)
+}
+
+/// Read the fstab file at `path`. Nothing is returned if it does not exist.
+pub fn read_fstab(path: &str, initrd_only: bool) -> Result<Vec<MountSpec>> {
+    if !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    parse_fstab(&read_file(path)?, initrd_only).map_err(|e| format!("{path}: {e}").into())
+}
+
+fn mount_spec(spec: &MountSpec, timeout: Option<Duration>) -> Result<()> {
+    let target = format!("/root{}", spec.target);
+    let options = Some(spec.options.as_str()).filter(|options| !options.is_empty());
+
+    if spec.flags.contains(MsFlags::MS_BIND) {
+        let source = format!("/root{}", spec.source);
+        info!("Bind mounting {source} -> {target}");
+        return do_mount(Some(&source), &target, None, spec.flags, options);
+    }
+
+    let source = resolve_device(&spec.source, timeout)?;
+    if source.starts_with("/dev/") {
+        wait_for_device(&source, timeout)?;
+    }
+    info!(
+        "Mounting {source} -> {target} as '{}' with flags = {:#x}, data = '{}'",
+        spec.fstype.as_deref().unwrap_or_default(),
+        spec.flags.bits(),
+        spec.options
+    );
+    mount_regular(
+        Some(&source),
+        &target,
+        spec.fstype.as_deref(),
+        spec.flags,
+        options,
+    )
+}
+
+/// Mount the filesystems from /etc/fstab in the initramfs and the ones marked with
+/// `x-initrd.mount` in /root/etc/fstab below /root.
+///
+/// Mountpoints and bind mount sources are paths in the rootfs. The rootfs itself is skipped.
+pub fn mount_fstab(timeout: Option<Duration>) -> Result<()> {
+    let mut mounts = read_fstab("/etc/fstab", false)?;
+    mounts.extend(read_fstab("/root/etc/fstab", true)?);
+
+    for spec in mounts.iter().filter(|spec| spec.target != "/") {
+        match mount_spec(spec, timeout) {
+            Err(e) if spec.nofail => warn!("{e}"),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fstab() {
+        let fstab = "# <file system> <mount point> <type> <options> <dump> <pass>\n\
+            \n\
+            /dev/mmcblk0p3\t/data\text4\tnoatime,nodev,commit=30\t0\t2\n\
+            UUID=1234   /boot  vfat   ro,x-initrd.mount,umask=0077  0 0\n\
+            tmpfs /tmp tmpfs defaults\n\
+            \t# indented comment\n\
+            /data/srv /srv none bind,nofail\n\
+            /dev/sdb1 /mnt auto noauto 0 0\n\
+            /dev/sda2 none swap sw 0 0\n";
+
+        assert_eq!(
+            parse_fstab(fstab, false).unwrap(),
+            [
+                MountSpec {
+                    source: "/dev/mmcblk0p3".into(),
+                    target: "/data".into(),
+                    fstype: Some("ext4".into()),
+                    options: "commit=30".into(),
+                    flags: MsFlags::MS_NOATIME | MsFlags::MS_NODEV,
+                    nofail: false,
+                },
+                MountSpec {
+                    source: "UUID=1234".into(),
+                    target: "/boot".into(),
+                    fstype: Some("vfat".into()),
+                    options: "umask=0077".into(),
+                    flags: MsFlags::MS_RDONLY,
+                    nofail: false,
+                },
+                MountSpec {
+                    source: "tmpfs".into(),
+                    target: "/tmp".into(),
+                    fstype: Some("tmpfs".into()),
+                    options: String::new(),
+                    flags: MsFlags::empty(),
+                    nofail: false,
+                },
+                MountSpec {
+                    source: "/data/srv".into(),
+                    target: "/srv".into(),
+                    fstype: Some("none".into()),
+                    options: String::new(),
+                    flags: MsFlags::MS_BIND,
+                    nofail: true,
+                },
+            ]
+        );
+
+        let initrd = parse_fstab(fstab, true).unwrap();
+        assert_eq!(initrd.len(), 1);
+        assert_eq!(initrd[0].target, "/boot");
+    }
+
+    #[test]
+    fn test_parse_fstab_invalid() {
+        assert!(parse_fstab("/dev/sda1 /data\n", false).is_err());
+        assert!(parse_fstab("/dev/sda1 /data ext4 defaults 0 0 extra\n", false).is_err());
+        assert_eq!(
+            parse_fstab("/dev/sda1 /data auto\n", false).unwrap()[0].fstype,
+            None
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::read_dir;
+use std::path::Path;
+use std::process::Command;
+
+use log::{info, warn};
+
+use crate::util::{dry_run, Result};
+
+/// Run the executables in `dir` in sorted order with the kernel cmdline in `RSINIT_CMDLINE`.
+///
+/// The boot is aborted if a hook fails, unless its name ends with `.optional`. Nothing is done
+/// if `dir` does not exist.
+pub fn run_hooks(dir: &str, cmdline: &str) -> Result<()> {
+    if !Path::new(dir).exists() {
+        return Ok(());
+    }
+    let mut hooks = read_dir(dir)
+        .map_err(|e| format!("Failed to list {dir}: {e}"))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| !path.is_dir())
+        .collect::<Vec<_>>();
+    hooks.sort();
+
+    for hook in hooks {
+        if dry_run() {
+            info!("Would run {}", hook.display());
+            continue;
+        }
+        let optional = hook.extension().is_some_and(|ext| ext == "optional");
+        info!("Running {}", hook.display());
+        let result = Command::new(&hook)
+            .env("RSINIT_CMDLINE", cmdline)
+            .status()
+            .map_err(|e| format!("Failed to run {}: {e}", hook.display()))
+            .and_then(|status| {
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("{} failed with {status}", hook.display()))
+                }
+            });
+        match result {
+            Err(e) if optional => warn!("{e}"),
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{
+        create_dir_all, read_to_string, remove_dir_all, remove_file, set_permissions, write,
+    };
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn hook(dir: &Path, name: &str, script: &str) {
+        let path = dir.join(name);
+        write(&path, format!("#!/bin/sh\n{script}\n")).expect("write failed");
+        set_permissions(&path, PermissionsExt::from_mode(0o755)).expect("chmod failed");
+    }
+
+    #[test]
+    fn test_run_hooks() {
+        let dir = temp_dir().join("rsinit-test-hooks");
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).expect("mkdir failed");
+        let log = temp_dir().join("rsinit-test-hooks.log");
+        let _ = remove_file(&log);
+        let log = log.to_string_lossy();
+
+        hook(&dir, "20-second", &format!("echo second >> {log}"));
+        hook(
+            &dir,
+            "10-first",
+            &format!("echo \"first $RSINIT_CMDLINE\" >> {log}"),
+        );
+        hook(&dir, "30-fail.optional", "exit 1");
+        let dir_str = dir.to_string_lossy();
+
+        run_hooks(&dir_str, "root=/dev/root").expect("failed");
+        assert_eq!(
+            read_to_string(&*log).expect("read failed"),
+            "first root=/dev/root\nsecond\n"
+        );
+
+        hook(&dir, "40-fail", "exit 1");
+        assert!(run_hooks(&dir_str, "").is_err());
+
+        let logged = read_to_string(&*log).expect("read failed");
+        crate::util::set_dry_run(true);
+        let result = run_hooks(&dir_str, "");
+        crate::util::set_dry_run(false);
+        assert!(result.is_ok());
+        assert_eq!(read_to_string(&*log).expect("read failed"), logged);
+
+        let _ = remove_dir_all(&dir);
+        let _ = remove_file(&*log);
+        assert!(run_hooks(&dir_str, "").is_ok());
+    }
+}
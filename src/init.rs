@@ -3,62 +3,361 @@
 
 use std::env;
 use std::env::current_exe;
-use std::ffi::CString;
+use std::ffi::{CString, OsStr, OsString};
 use std::fmt::Write as _;
-use std::fs::OpenOptions;
+use std::fs::{canonicalize, OpenOptions};
+#[cfg(feature = "reboot-on-failure")]
 use std::io;
+#[cfg(feature = "reboot-on-failure")]
+use std::io::Write as _;
 use std::mem::take;
 use std::os::fd::AsFd;
 use std::os::unix::ffi::OsStrExt;
 use std::panic::set_hook;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+#[cfg(feature = "reboot-on-failure")]
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use git_version::git_version;
-use log::{error, info};
+use log::{debug, error, info, warn};
+#[cfg(feature = "debug")]
+use nix::errno::Errno;
+use nix::mount::MsFlags;
+#[cfg(feature = "debug")]
+use nix::mount::{umount2, MntFlags};
 #[cfg(feature = "reboot-on-failure")]
 use nix::sys::reboot::{reboot, RebootMode};
-use nix::sys::termios::tcdrain;
-use nix::unistd::{chdir, chroot, dup2_stderr, dup2_stdout, execv, unlink};
+use nix::sys::signal::{SigSet, Signal};
+#[cfg(feature = "debug")]
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use nix::unistd::{chdir, chroot, dup2_stderr, dup2_stdout, execve, pivot_root, unlink};
 
-use crate::cmdline::{CmdlineOptions, CmdlineOptionsParser};
+use crate::blkid::resolve_device;
+#[cfg(feature = "bootchooser")]
+use crate::bootchooser::{choose_slot, count_boot};
+use crate::bootconfig::BOOTCONFIG;
+use crate::cmdline::{
+    BreakStage, CmdlineCallback, CmdlineOptions, CmdlineOptionsParser, Console, CMDLINE_SOURCES,
+};
+use crate::console::{sync_consoles, tee_stdio, untee_stdio};
 #[cfg(feature = "dmverity")]
 use crate::dmverity::prepare_dmverity;
+#[cfg(feature = "firmware")]
+use crate::firmware::setup_firmware_loader;
+#[cfg(feature = "fstab")]
+use crate::fstab::mount_fstab;
+use crate::hooks::run_hooks;
 #[cfg(feature = "integration-test")]
 use crate::integration::IntegrationLogger as Logger;
+#[cfg(feature = "integrity")]
+use crate::integrity::prepare_integrity;
+use crate::kmsg::KmsgLogger;
 #[cfg(not(feature = "integration-test"))]
 use crate::kmsg::KmsgLogger as Logger;
+#[cfg(feature = "luks")]
+use crate::luks::prepare_luks;
+#[cfg(feature = "modules")]
+use crate::modules::load_modules;
 use crate::mount::{
-    mount_bind_kernel_modules, mount_move_special, mount_overlay, mount_root, mount_special,
-    mount_tmpfs_overlay,
+    delete_initramfs, is_initial_rootfs, mount_bind_kernel_modules, mount_cgroup,
+    mount_move_special, mount_overlay, mount_root_from_options, mount_run, mount_special,
+    mount_tmp, mount_tmpfs_overlay, set_propagation,
 };
+#[cfg(feature = "net")]
+use crate::net::{is_9p_tcp, resolve_9p_server, setup_network, wait_for_link, IpConfig};
+use crate::report::{write_report, BootReport};
+#[cfg(feature = "resume")]
+use crate::resume::{resume, RESUME_TIMEOUT};
+use crate::slot::{read_slot_state, write_slot_state};
+#[cfg(feature = "swap")]
+use crate::swap::swapon;
 #[cfg(feature = "systemd")]
 use crate::systemd::{mount_systemd, shutdown};
 #[cfg(feature = "usb9pfs")]
 use crate::usbg_9pfs::prepare_9pfs_gadget;
-use crate::util::Result;
+use crate::util::{
+    abort_boot, check_abort, dry_run, mkdir, read_file, set_dry_run, wait_for_device, Result,
+};
+#[cfg(feature = "zram")]
+use crate::zram::setup_zram;
+
+/* Used for stdout/stderr if /dev/console is missing, so rsinit can still boot */
+const CONSOLE_FALLBACKS: [&str; 2] = ["/dev/kmsg", "/dev/null"];
 
 /*
- * Setup stdout/stderr. The kernel will create /dev/console in the
- * initramfs, so we can use that.
+ * Setup stdout/stderr. Without console devices, the kernel will create
+ * /dev/console in the initramfs, so we can use that.
  * Remove the device node since it is no longer needed and devtmpfs will be
  * mounted over it anyways.
+ * With multiple console devices, the output is mirrored to all of them that
+ * can be opened.
  */
-fn setup_console() -> Result<()> {
-    let f = OpenOptions::new().write(true).open("/dev/console")?;
-    let fd = f.as_fd();
+fn setup_console(devices: &[PathBuf]) -> Result<()> {
+    if devices.is_empty() {
+        let console_error = match OpenOptions::new().write(true).open("/dev/console") {
+            Ok(f) => {
+                dup2_stdout(f.as_fd())?;
+                dup2_stderr(f.as_fd())?;
+                let _ = unlink("/dev/console");
+                return Ok(());
+            }
+            Err(e) => e,
+        };
 
-    dup2_stdout(fd)?;
-    dup2_stderr(fd)?;
+        let (path, f) = CONSOLE_FALLBACKS
+            .into_iter()
+            .find_map(|path| {
+                OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .ok()
+                    .map(|f| (path, f))
+            })
+            .ok_or(format!("Failed to open /dev/console: {console_error}"))?;
+        dup2_stdout(f.as_fd())?;
+        dup2_stderr(f.as_fd())?;
+        /* The logger is not set up yet */
+        eprintln!("rsinit: Failed to open /dev/console: {console_error}, using {path}");
+        return Ok(());
+    }
 
-    let _ = unlink("/dev/console");
+    let mut consoles = Vec::new();
+    for device in devices {
+        match OpenOptions::new().write(true).open(device) {
+            Ok(f) => consoles.push(f),
+            Err(e) => warn!("Failed to open console {}: {e}", device.display()),
+        }
+    }
+    match consoles.as_slice() {
+        [] => Err("No console could be opened".into()),
+        [f] => {
+            dup2_stdout(f.as_fd())?;
+            dup2_stderr(f.as_fd())?;
+            Ok(())
+        }
+        _ => tee_stdio(consoles),
+    }
+}
+
+/*
+ * Check whether pivot_to_root() can work before the initramfs is kept for it. The kernel
+ * refuses to pivot away from the initial rootfs, and /oldroot cannot be created on a read-only
+ * rootfs.
+ */
+fn check_pivot_root() -> Result<()> {
+    if is_initial_rootfs(&read_file("/proc/self/mountinfo")?) {
+        return Err("the initramfs is the initial rootfs".into());
+    }
+    mkdir("/root/oldroot")
+}
 
+/*
+ * Make /root the root mount and move the initramfs to /oldroot, which must exist.
+ */
+fn pivot_to_root() -> Result<()> {
+    pivot_root("/root", "/root/oldroot").map_err(|e| format!("pivot_root failed: {e}"))?;
+    /* pivot_root() changed the root directory, but the working directory is still /root */
+    chdir("/")?;
     Ok(())
 }
 
+/* The existing device nodes of the `console=` options, starting with the last one like the
+ * kernel prefers it */
+fn console_devices(consoles: &[Console]) -> Vec<PathBuf> {
+    let mut devices: Vec<PathBuf> = Vec::new();
+    for console in consoles.iter().rev() {
+        let path = Path::new("/dev").join(&console.device);
+        if path.exists() && !devices.contains(&path) {
+            devices.push(path);
+        }
+    }
+    devices
+}
+
+/* Seconds to wait before rebooting after a failure, negative to halt instead */
+#[cfg(feature = "reboot-on-failure")]
+static REBOOT_DELAY: AtomicI64 = AtomicI64::new(0);
+
+#[cfg(feature = "reboot-on-failure")]
+fn reboot_after(delay: u64) {
+    for remaining in (1..=delay).rev() {
+        print!("\rRebooting in {remaining} seconds... ");
+        let _ = io::stdout().flush();
+        thread::sleep(Duration::from_secs(1));
+    }
+    if delay > 0 {
+        println!();
+    }
+    sync_consoles();
+    let _ = reboot(RebootMode::RB_AUTOBOOT);
+}
+
 fn finalize() {
     /* Make sure all output is written before exiting */
-    let _ = tcdrain(io::stdout().as_fd());
+    log::logger().flush();
+    sync_consoles();
+    /* A dry run never reboots, so its output stays readable */
     #[cfg(feature = "reboot-on-failure")]
-    let _ = reboot(RebootMode::RB_AUTOBOOT);
+    if let (false, Ok(delay)) = (
+        dry_run(),
+        u64::try_from(REBOOT_DELAY.load(Ordering::Relaxed)),
+    ) {
+        reboot_after(delay);
+        return;
+    }
+
+    if dry_run() {
+        println!("Dry run finished, halting");
+    } else {
+        println!("Boot failed, halting");
+    }
+    sync_consoles();
+    loop {
+        thread::sleep(Duration::from_secs(3600));
+    }
+}
+
+/* Set for the binary started by rsinit.reexec=, so it does not start itself again */
+#[cfg(feature = "debug")]
+const REEXEC_ENV: &str = "RSINIT_REEXEC";
+
+/*
+ * Execute `path` with the arguments and environment of rsinit for testing.
+ * The special filesystems are detached and /dev/console is recreated, so it
+ * starts like the initial init.
+ */
+#[cfg(feature = "debug")]
+fn reexec(path: &str) -> Result<()> {
+    if env::var_os(REEXEC_ENV).is_some() {
+        debug!("Already re-executed, continuing");
+        return Ok(());
+    }
+    info!("Re-executing {path}");
+
+    let mut args = Vec::new();
+    for arg in env::args_os() {
+        args.push(CString::new(arg.as_bytes())?);
+    }
+    let mut envp = vec![CString::new(format!("{REEXEC_ENV}=1"))?];
+    for (name, value) in env::vars_os() {
+        envp.push(CString::new(
+            [name.as_bytes(), b"=", value.as_bytes()].concat(),
+        )?);
+    }
+
+    abort_signals().thread_unblock()?;
+    log::logger().flush();
+    untee_stdio()?;
+    for dir in ["/dev", "/sys", "/proc"] {
+        umount2(dir, MntFlags::MNT_DETACH).map_err(|e| format!("Failed to detach {dir}: {e}"))?;
+    }
+    match mknod(
+        "/dev/console",
+        SFlag::S_IFCHR,
+        Mode::from_bits_truncate(0o600),
+        makedev(5, 1),
+    ) {
+        Ok(()) | Err(Errno::EEXIST) => (),
+        Err(e) => return Err(format!("Failed to create /dev/console: {e}").into()),
+    }
+
+    let cpath = CString::new(path)?;
+    let Err(e) = execve(&cpath, &args, &envp);
+    Err(format!("Failed to re-execute {path}: {e}").into())
+}
+
+/// The signals that abort the boot while rsinit is PID 1.
+///
+/// SIGCHLD keeps the default handling, child processes are waited for where they are started.
+/// Orphans that are reparented to rsinit, e.g. daemons forked by hooks, are not reaped: rsinit
+/// only runs until it executes the next init, which keeps PID 1 and reaps them.
+fn abort_signals() -> SigSet {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGINT);
+    signals
+}
+
+/*
+ * Block the signals in all threads and wait for them in a dedicated thread. It only asks the
+ * main thread to abort, which fails at the next check and then halts or reboots in finalize()
+ * like after any other failure. This must be done before any other thread is started.
+ */
+fn setup_signals() -> Result<()> {
+    let signals = abort_signals();
+    signals.thread_block()?;
+    thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || {
+            if let Ok(signal) = signals.wait() {
+                error!("Received {signal}, aborting the boot");
+                abort_boot();
+            }
+        })
+        .map_err(|e| format!("Failed to start the signal thread: {e}"))?;
+    Ok(())
+}
+
+/// The arguments for the next init from the arguments of rsinit (without `argv[0]`).
+///
+/// If there is a `--`, only the arguments after it are passed on unmodified. Otherwise all
+/// arguments are passed on.
+fn init_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let args = args.collect::<Vec<_>>();
+    match args.iter().position(|arg| arg == "--") {
+        Some(separator) => args[separator + 1..].to_vec(),
+        None => args,
+    }
+}
+
+/// The arguments from the cmdline after `--` followed by the `forwarded` arguments of rsinit.
+///
+/// The kernel appends the arguments after `--` in /proc/cmdline to the arguments of rsinit, so
+/// they are removed from `forwarded` to not pass them twice.
+fn merge_init_args(cmdline: &[String], forwarded: Vec<OsString>) -> Vec<OsString> {
+    let mut args = cmdline.iter().map(OsString::from).collect::<Vec<_>>();
+    let forwarded = forwarded
+        .strip_suffix(args.as_slice())
+        .unwrap_or(&forwarded)
+        .to_vec();
+    args.extend(forwarded);
+    args
+}
+
+/// The fallbacks that are tried if `init=` cannot be started, like the kernel does.
+const INIT_FALLBACKS: [&str; 4] = ["/sbin/init", "/etc/init", "/bin/init", "/bin/sh"];
+
+/// The programs to try as next init in order: `init` followed by the fallbacks.
+fn init_candidates(init: &str) -> Vec<&str> {
+    let mut candidates = vec![init];
+    candidates.extend(
+        INIT_FALLBACKS
+            .into_iter()
+            .filter(|fallback| *fallback != init),
+    );
+    candidates
+}
+
+/// Run an interactive shell on the console and wait for it to exit.
+fn run_shell() -> Result<()> {
+    let console = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/console")
+        .map_err(|e| format!("Failed to open /dev/console: {e}"))?;
+
+    info!("Starting /bin/sh, exit the shell to continue");
+    let status = Command::new("/bin/sh")
+        .stdin(Stdio::from(console.try_clone()?))
+        .stdout(Stdio::from(console.try_clone()?))
+        .stderr(Stdio::from(console))
+        .status()
+        .map_err(|e| format!("Failed to start /bin/sh: {e}"))?;
+    info!("/bin/sh exited with {status}");
+    Ok(())
 }
 
 /// The lifecycle phases where callbacks can be registered.
@@ -98,18 +397,124 @@ where
     }
 }
 
+/// Builds an [`InitContext`] with callbacks.
+///
+/// # Example
+///
+/// ```no_run
+/// use rsinit::init::InitContext;
+///
+/// InitContext::builder()
+///     .on_cmdline(|key, _val| {
+///         if key == "my.option" {
+///             println!("my.option is set");
+///         }
+///         Ok(())
+///     })
+///     .after_root_mount(|ctx| {
+///         println!("Mounted {:?}", ctx.options.root);
+///         Ok(())
+///     })
+///     .build()?
+///     .run_from_env()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Default)]
+pub struct InitContextBuilder<'a> {
+    cmdline_callbacks: Vec<Box<dyn CmdlineCallback + 'a>>,
+    callbacks: Vec<(CallBack, Box<dyn InitCallback + 'a>)>,
+}
+
+impl<'a> InitContextBuilder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a command line parser callback, see [`InitContext::add_cmdline_parser_callback`].
+    pub fn on_cmdline<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(&str, Option<&str>) -> Result<()> + 'a,
+    {
+        self.cmdline_callbacks.push(Box::new(cb));
+        self
+    }
+
+    /// Add a [`CallBack::PostSetup`] callback.
+    pub fn after_setup<F>(self, cb: F) -> Self
+    where
+        F: FnMut(&mut InitContext) -> Result<()> + 'a,
+    {
+        self.callback(CallBack::PostSetup, cb)
+    }
+
+    /// Add a [`CallBack::PostRootMount`] callback.
+    pub fn after_root_mount<F>(self, cb: F) -> Self
+    where
+        F: FnMut(&mut InitContext) -> Result<()> + 'a,
+    {
+        self.callback(CallBack::PostRootMount, cb)
+    }
+
+    /// Add a [`CallBack::PostSwitchRoot`] callback.
+    pub fn after_switch_root<F>(self, cb: F) -> Self
+    where
+        F: FnMut(&mut InitContext) -> Result<()> + 'a,
+    {
+        self.callback(CallBack::PostSwitchRoot, cb)
+    }
+
+    /// Add a callback for the phase `kind`, see [`InitContext::add_callback`].
+    pub fn callback<F>(mut self, kind: CallBack, cb: F) -> Self
+    where
+        F: FnMut(&mut InitContext) -> Result<()> + 'a,
+    {
+        self.callbacks.push((kind, Box::new(cb)));
+        self
+    }
+
+    /// Create the [`InitContext`] with [`InitContext::new`] and register the callbacks in the
+    /// order they were added.
+    pub fn build(self) -> Result<InitContext<'a>> {
+        let mut ctx = InitContext::new()?;
+        for cb in self.cmdline_callbacks {
+            ctx.parser.add_callback(cb);
+        }
+        ctx.callbacks.extend(self.callbacks);
+        Ok(ctx)
+    }
+}
+
 pub struct InitContext<'a> {
     pub options: CmdlineOptions,
     parser: CmdlineOptionsParser<'a>,
     callbacks: Vec<(CallBack, Box<dyn InitCallback + 'a>)>,
+    /// The duration of the boot stages timed with [`Self::timed`], in boot order.
+    timings: Vec<(&'static str, Duration)>,
+    /// Whether the rootfs is a dm-verity device, for the boot report.
+    verity: bool,
+    /// How often mounting the rootfs was retried, for the boot report.
+    mount_retries: u32,
+    /// The command rsinit runs as, `argv[0]` or the one passed to [`Self::run`].
+    cmd: String,
 }
 
 impl<'a> InitContext<'a> {
+    /// Start building an [`InitContext`] with callbacks.
+    pub fn builder() -> InitContextBuilder<'a> {
+        InitContextBuilder::new()
+    }
+
     pub fn new() -> Result<Self> {
-        setup_console()?;
+        setup_console(&[])?;
+        setup_signals()?;
 
         set_hook(Box::new(|panic_info| {
-            println!("panic occurred: {panic_info}");
+            error!("panic occurred: {panic_info}");
+            /* The kernel may not show the log on the console, e.g. with quiet, or the logger
+             * may not be set up yet */
+            if !KmsgLogger::logs_to_console() {
+                eprintln!("rsinit: panic occurred: {panic_info}");
+            }
             finalize();
         }));
 
@@ -117,6 +522,10 @@ impl<'a> InitContext<'a> {
             options: CmdlineOptions::default(),
             parser: CmdlineOptionsParser::new(),
             callbacks: Vec::default(),
+            timings: Vec::default(),
+            verity: false,
+            mount_retries: 0,
+            cmd: env::args().next().unwrap_or_default(),
         })
     }
 
@@ -140,7 +549,7 @@ impl<'a> InitContext<'a> {
     ///     Ok(())
     /// });
     ///
-    /// // When `setup()` parses /proc/cmdline, the callback will be invoked
+    /// // When `setup()` parses the cmdline, the callback will be invoked
     /// ctx.setup()?;
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
@@ -179,19 +588,174 @@ impl<'a> InitContext<'a> {
 
         Logger::enable()?;
         info!(
-            concat!(env!("CARGO_PKG_NAME"), " version {}"),
-            git_version!(fallback = env!("CARGO_PKG_VERSION"))
+            concat!(env!("CARGO_PKG_NAME"), " version {}, running {}"),
+            git_version!(fallback = env!("CARGO_PKG_VERSION")),
+            self.cmd
         );
 
-        self.options = self.parser.parse_file("/proc/cmdline")?;
+        self.options = self
+            .parser
+            .parse_sources_and_bootconfig(&CMDLINE_SOURCES, BOOTCONFIG)?;
+        /* Logging starts with all messages until the cmdline is parsed */
+        log::set_max_level(self.options.loglevel);
+        KmsgLogger::log_to_console(self.options.log_console);
+        KmsgLogger::coalesce_repeats(self.options.log_coalesce);
+        set_dry_run(self.options.dry_run);
+        #[cfg(feature = "serde")]
+        if let Ok(options) = serde_json::to_string(&self.options) {
+            debug!("Options: {options}");
+        }
+        #[cfg(feature = "reboot-on-failure")]
+        if let Some(delay) = self.options.reboot_delay {
+            REBOOT_DELAY.store(delay, Ordering::Relaxed);
+        }
+
+        if self.options.cgroup {
+            mount_cgroup(self.options.cgroup_legacy)?;
+        }
+
+        #[cfg(feature = "firmware")]
+        if let Some(dir) = &self.options.firmware {
+            setup_firmware_loader(dir)?;
+        }
+
+        #[cfg(feature = "modules")]
+        load_modules(&self.options.modules)?;
+
+        /* After loading modules, which may provide the console driver */
+        let devices = console_devices(&self.options.consoles());
+        if !devices.is_empty() {
+            if let Err(e) = setup_console(&devices) {
+                warn!("Failed to use the consoles from the cmdline: {e}");
+            }
+        }
+
+        #[cfg(feature = "net")]
+        setup_network(&mut self.options)?;
 
         Ok(())
     }
 
-    #[cfg(any(feature = "dmverity", feature = "usb9pfs"))]
+    /// Select the slot from the state byte on `rsinit.slot_device=` unless `rsinit.slot=` is
+    /// set. A slot that is tried for the first time is only booted once: The state is set to the
+    /// other slot before, so userspace must confirm the slot by writing it back.
+    pub fn select_slot(self: &mut InitContext<'a>) -> Result<()> {
+        if self.options.slot.is_some() {
+            return Ok(());
+        }
+        let (device, offset) = match &self.options.slot_device {
+            Some((device, offset)) => (device.clone(), *offset),
+            #[cfg(feature = "bootchooser")]
+            None if self.options.bootchooser.device.is_some() => {
+                return self.select_bootchooser_slot()
+            }
+            None => return Ok(()),
+        };
+        let device = resolve_device(&device, self.options.rootwait)?;
+        wait_for_device(&device, self.options.rootwait)?;
+
+        let (slot, try_slot) = read_slot_state(&device, offset)?;
+        if try_slot {
+            info!("Trying slot {} once", slot.as_str());
+            write_slot_state(&device, offset, slot.other())?;
+            self.options.slot_try = true;
+        } else {
+            info!("Booting slot {}", slot.as_str());
+        }
+        self.options.select_slot(slot)
+    }
+
+    #[cfg(feature = "bootchooser")]
+    fn select_bootchooser_slot(self: &mut InitContext<'a>) -> Result<()> {
+        if let Some((device, _)) = &mut self.options.bootchooser.device {
+            *device = resolve_device(device, self.options.rootwait)?;
+            wait_for_device(device, self.options.rootwait)?;
+        }
+        let slot = choose_slot(&self.options.bootchooser)?;
+        self.options.select_slot(slot)
+    }
+
+    /// Resolve `UUID=`, `LABEL=`, `PARTLABEL=` and `PARTUUID=` specifications of the root devices to the
+    /// actual device nodes.
+    pub fn resolve_root(self: &mut InitContext<'a>) -> Result<()> {
+        if !self.options.rootdelay.is_zero() {
+            info!("Waiting {:?} for the root device", self.options.rootdelay);
+            thread::sleep(self.options.rootdelay);
+        }
+        /* With fallbacks, each root device is resolved when trying to mount it */
+        if let (Some(root), true) = (&self.options.root, self.options.root_fallbacks.is_empty()) {
+            self.options.root = Some(resolve_device(root, self.options.rootwait)?);
+        }
+        if let Some(verity_root) = &self.options.verity_root {
+            self.options.verity_root = Some(resolve_device(verity_root, self.options.rootwait)?);
+        }
+        Ok(())
+    }
+
+    /// Resume from hibernation if `resume=` is set. Returns if there is no hibernation image.
+    /// A missing or invalid resume device is only logged, and it is waited for at most
+    /// [`RESUME_TIMEOUT`] even with `rootwait`.
+    #[cfg(feature = "resume")]
+    pub fn resume(self: &InitContext<'a>) -> Result<()> {
+        let (Some(device), false) = (&self.options.resume, self.options.noresume) else {
+            return Ok(());
+        };
+        let timeout = Some(
+            self.options
+                .rootwait
+                .map_or(RESUME_TIMEOUT, |timeout| timeout.min(RESUME_TIMEOUT)),
+        );
+        let result = resolve_device(device, timeout)
+            .and_then(|device| resume(&device, self.options.resume_offset, timeout));
+        if let Err(e) = result {
+            warn!("Not resuming from {device}: {e}");
+        }
+        Ok(())
+    }
+
+    /// Set up zram swap from `rsinit.zram=` and enable swap on the device from `rsinit.swap=`.
+    /// Failures only abort the boot with `rsinit.swap_required`.
+    #[cfg(feature = "swap")]
+    pub fn swapon(self: &InitContext<'a>) -> Result<()> {
+        #[cfg(feature = "zram")]
+        if let Some(zram) = &self.options.zram {
+            if let Err(e) = setup_zram(zram) {
+                error!("Failed to set up zram swap: {e}");
+            }
+        }
+
+        let Some(device) = &self.options.swap else {
+            return Ok(());
+        };
+        let result = resolve_device(device, self.options.rootwait)
+            .and_then(|device| swapon(&device, self.options.swap_priority, self.options.rootwait));
+        match result {
+            Err(e) if !self.options.swap_required => {
+                error!("{e}");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    #[cfg(any(
+        feature = "dmverity",
+        feature = "luks",
+        feature = "integrity",
+        feature = "usb9pfs"
+    ))]
     pub fn prepare_aux(self: &mut InitContext<'a>) -> Result<()> {
+        if dry_run() {
+            info!("Would set up the device-mapper targets and the USB gadget");
+            return Ok(());
+        }
+        #[cfg(feature = "integrity")]
+        prepare_integrity(&mut self.options)?;
+        #[cfg(feature = "luks")]
+        prepare_luks(&mut self.options)?;
         #[cfg(feature = "dmverity")]
         if prepare_dmverity(&mut self.options)? {
+            self.verity = true;
             return Ok(());
         }
         #[cfg(feature = "usb9pfs")]
@@ -202,29 +766,92 @@ impl<'a> InitContext<'a> {
     }
 
     pub fn switch_root(self: &mut InitContext<'a>) -> Result<()> {
+        if self.options.run {
+            mount_run()?;
+        }
         #[cfg(feature = "systemd")]
         mount_systemd(&mut self.options)?;
 
-        if self.options.cleanup {
+        if let Some(size) = &self.options.tmp {
+            mount_tmp(size)?;
+        }
+
+        if self.options.pivot_root && !dry_run() {
+            if let Err(e) = check_pivot_root() {
+                warn!("Ignoring rsinit.pivot_root: {e}");
+                self.options.pivot_root = false;
+            }
+        }
+        /* The initramfs stays accessible at /oldroot */
+        if self.options.pivot_root {
+            self.options.cleanup = false;
+        }
+
+        if self.options.cleanup && !dry_run() {
             let exe = current_exe().map_err(|e| format!("current_exe failed: {e}"))?;
             unlink(exe.as_path())?;
         }
 
         mount_move_special(self.options.cleanup)?;
 
+        if self.options.delete_initramfs && self.options.cleanup {
+            if let Err(e) = delete_initramfs() {
+                warn!("{e}");
+            }
+        }
+
+        /* Including the moved special filesystems */
+        if self.options.private_root {
+            set_propagation("/root", MsFlags::MS_PRIVATE | MsFlags::MS_REC)?;
+        }
+
+        if dry_run() {
+            info!("Would switch root to /root");
+            return Ok(());
+        }
+        if self.options.pivot_root {
+            match pivot_to_root() {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("Failed to pivot root, changing the root directory instead: {e}"),
+            }
+        }
         chdir("/root")?;
         chroot(".")?;
         chdir("/")?;
         Ok(())
     }
 
-    pub fn mount_root(self: &InitContext<'a>) -> Result<()> {
-        mount_root(
-            self.options.root.as_deref(),
-            self.options.rootfstype.as_deref(),
-            self.options.rootfsflags,
-            self.options.rootflags.as_deref(),
-        )?;
+    /// Mount the rootfs from `root=` with [`mount_root_from_options`]. `options.root` and
+    /// `options.rootfstype` are set to the device that was mounted and its filesystem.
+    pub fn mount_root(self: &mut InitContext<'a>) -> Result<()> {
+        #[cfg(feature = "net")]
+        self.prepare_network_root()?;
+
+        let mounted = mount_root_from_options(&self.options)?;
+        self.options.root = mounted.root;
+        self.options.rootfstype = mounted.fstype;
+        self.mount_retries += mounted.retries;
+        Ok(())
+    }
+
+    /// Wait for the link of the interface from `ip=` before mounting an NFS rootfs or a 9pfs
+    /// over TCP, so a missing link is reported as such instead of a failed mount. The 9pfs
+    /// server is resolved as well.
+    #[cfg(feature = "net")]
+    fn prepare_network_root(self: &mut InitContext<'a>) -> Result<()> {
+        let tcp_9p = is_9p_tcp(&self.options);
+        /* The network is not configured in a dry run */
+        if self.options.rootfstype.as_deref() != Some("nfs") && !tcp_9p || dry_run() {
+            return Ok(());
+        }
+        if let Some(ip) = &self.options.ip {
+            if let Some(iface) = IpConfig::parse(ip)?.iface {
+                wait_for_link(&iface, self.options.rootwait)?;
+            }
+        }
+        if tcp_9p {
+            resolve_9p_server(&mut self.options)?;
+        }
         Ok(())
     }
 
@@ -232,6 +859,16 @@ impl<'a> InitContext<'a> {
         mount_tmpfs_overlay(self.options.rootfsflags, "/", self.options.root.as_deref())
     }
 
+    /// Mount a writable overlay with a tmpfs as upper layer on each directory from
+    /// `rsinit.overlay_dir=`, so the rootfs itself can stay read-only.
+    pub fn mount_dir_overlays(self: &InitContext<'a>) -> Result<()> {
+        for dir in &self.options.overlay_dirs {
+            info!("Mounting a tmpfs overlay on {dir}");
+            mount_tmpfs_overlay(MsFlags::empty(), dir, Some("overlay"))?;
+        }
+        Ok(())
+    }
+
     pub fn mount_root_overlay(
         self: &InitContext<'a>,
         data: Option<&str>,
@@ -246,29 +883,101 @@ impl<'a> InitContext<'a> {
         )
     }
 
+    /// Start the next init. If `init=` does not exist or cannot be executed, the fallbacks
+    /// `/sbin/init`, `/etc/init`, `/bin/init` and `/bin/sh` are tried in order.
     pub fn start_init(self: &InitContext<'a>) -> Result<()> {
-        let mut args = Vec::new();
-        args.push(CString::new(self.options.init.as_str())?);
+        let mut extra_args = Vec::new();
+        let forwarded = init_args(env::args_os().skip(1));
+        for arg in merge_init_args(&self.options.init_args, forwarded) {
+            extra_args.push(CString::new(arg.as_bytes())?);
+        }
 
-        for arg in env::args_os().skip(1) {
-            let carg = CString::new(arg.as_bytes())?;
-            args.push(carg);
+        /* The environment from the kernel with the variables from the cmdline */
+        let mut vars: Vec<(OsString, OsString)> = env::vars_os().collect();
+        for (name, value) in &self.options.env {
+            vars.retain(|(n, _)| n.as_os_str() != OsStr::new(name));
+            vars.push((name.into(), value.into()));
         }
-        let mut buf = "Starting ".to_string();
-        for arg in &args {
-            write!(buf, "{} ", arg.to_bytes().escape_ascii())?;
+        let mut envp = Vec::new();
+        for (name, value) in vars {
+            envp.push(CString::new(
+                [name.as_bytes(), b"=", value.as_bytes()].concat(),
+            )?);
         }
-        writeln!(buf, "...")?;
-        info!("{}", &buf);
 
-        execv(&args[0], &args)?;
+        for init in init_candidates(&self.options.init) {
+            /* Without switching root in a dry run, the rootfs is still at /root */
+            let path = if dry_run() {
+                Path::new("/root").join(init.trim_start_matches('/'))
+            } else {
+                PathBuf::from(init)
+            };
+            if !path.exists() {
+                warn!("{init} does not exist in the rootfs");
+                continue;
+            }
 
-        Ok(())
+            let mut args = vec![CString::new(init)?];
+            args.extend(extra_args.iter().cloned());
+            let mut buf = if dry_run() {
+                "Would start "
+            } else {
+                "Starting "
+            }
+            .to_string();
+            for arg in &args {
+                write!(buf, "{} ", arg.to_bytes().escape_ascii())?;
+            }
+            writeln!(buf, "...")?;
+            info!("{}", &buf);
+
+            if dry_run() {
+                return Ok(());
+            }
+
+            /* The blocked signals would be inherited by the next init */
+            abort_signals().thread_unblock()?;
+            log::logger().flush();
+            /* The thread mirroring the output to multiple consoles does not survive execve() */
+            if let Err(e) = untee_stdio() {
+                warn!("Failed to restore the console: {e}");
+            }
+            let Err(e) = execve(&args[0], &args, &envp);
+            error!("Failed to start {init}: {e}");
+        }
+
+        Err(format!(
+            "No working init found, tried {}",
+            init_candidates(&self.options.init).join(", ")
+        )
+        .into())
+    }
+
+    /// Run the hooks in /etc/rsinit.d in the initramfs and /root/etc/rsinit.d in the root
+    /// filesystem.
+    pub fn run_hooks(self: &InitContext<'a>) -> Result<()> {
+        let cmdline = read_file("/proc/cmdline")?;
+        run_hooks("/etc/rsinit.d", cmdline.trim())?;
+        run_hooks("/root/etc/rsinit.d", cmdline.trim())
     }
 
     pub fn finish(self: &mut InitContext<'a>) -> Result<()> {
-        self.switch_root()?;
+        self.run_hooks()?;
+        self.timed("switch_root", Self::switch_root)?;
         self.run_callbacks(CallBack::PostSwitchRoot)?;
+        self.log_timings();
+        if let Some(path) = &self.options.report {
+            if let Err(e) = self.write_report(path) {
+                warn!("{e}");
+            }
+        }
+        #[cfg(feature = "bootchooser")]
+        if let (Some(_), Some(slot)) = (&self.options.bootchooser.device, self.options.slot) {
+            if let Err(e) = count_boot(&self.options.bootchooser, slot) {
+                error!("Failed to count the boot attempt: {e}");
+            }
+        }
+        check_abort()?;
         self.start_init()?;
 
         Ok(())
@@ -293,8 +1002,8 @@ impl<'a> InitContext<'a> {
     }
 
     pub fn run(self: &mut InitContext<'a>, cmd: &str) {
-        // log isn't setup at this point
-        println!("Running {cmd}...");
+        /* Logged once the logger is set up, so quiet boots stay quiet */
+        self.cmd = cmd.to_string();
         let result = match cmd {
             #[cfg(feature = "systemd")]
             "/shutdown" => shutdown(),
@@ -303,6 +1012,11 @@ impl<'a> InitContext<'a> {
 
         if let Err(e) = result {
             error!("{e}");
+            if self.options.shell {
+                if let Err(e) = run_shell() {
+                    error!("{e}");
+                }
+            }
         }
     }
 
@@ -320,15 +1034,106 @@ impl<'a> InitContext<'a> {
         Ok(())
     }
 
+    /// Start a shell if `rd.break=` requested it for `stage`. The boot continues when the
+    /// shell exits.
+    fn break_at(self: &InitContext<'a>, stage: BreakStage) -> Result<()> {
+        check_abort()?;
+        if self.options.breaks.contains(&stage) {
+            info!("Breaking at rd.break={}", stage.as_str());
+            run_shell()?;
+        }
+        Ok(())
+    }
+
+    /// Run `stage` and record how long it took.
+    fn timed<F>(self: &mut InitContext<'a>, name: &'static str, stage: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        check_abort()?;
+        let start = Instant::now();
+        let result = stage(self);
+        let elapsed = start.elapsed();
+        debug!("{name} took {elapsed:?}");
+        self.timings.push((name, elapsed));
+        result
+    }
+
+    /// Log a summary of the boot stage durations.
+    fn log_timings(self: &InitContext<'a>) {
+        let mut buf = String::new();
+        for (name, elapsed) in &self.timings {
+            let _ = write!(buf, " {name}={:.3}ms", elapsed.as_secs_f64() * 1000.0);
+        }
+        info!("Boot stages:{buf}");
+    }
+
+    /// Write the boot report to `path` in the rootfs for the next init.
+    fn write_report(self: &InitContext<'a>, path: &str) -> Result<()> {
+        let root = self.options.root.as_deref();
+        let report = BootReport {
+            root: root.map(str::to_string),
+            device: root
+                .and_then(|root| canonicalize(root).ok())
+                .map(|device| device.to_string_lossy().into_owned()),
+            rootfstype: self.options.rootfstype.clone(),
+            rootflags: self.options.rootflags.clone(),
+            slot: self.options.slot.map(|slot| slot.as_str()),
+            verity: self.verity,
+            mount_retries: self.mount_retries,
+            timings: self.timings.clone(),
+        };
+        if dry_run() {
+            info!("Would write the boot report to {path}");
+            return Ok(());
+        }
+        debug!("Writing the boot report to {path}");
+        write_report(path, &report, self.options.report_format)
+    }
+
     fn run_impl(self: &mut InitContext<'a>) -> Result<()> {
-        self.setup()?;
+        self.timed("setup", Self::setup)?;
+
+        #[cfg(feature = "debug")]
+        if let Some(path) = &self.options.reexec {
+            reexec(path)?;
+        }
 
         self.run_callbacks(CallBack::PostSetup)?;
 
-        #[cfg(any(feature = "dmverity", feature = "usb9pfs"))]
-        self.prepare_aux()?;
+        self.break_at(BreakStage::Cmdline)?;
+
+        self.select_slot()?;
+
+        self.timed("resolve_root", Self::resolve_root)?;
+
+        #[cfg(feature = "resume")]
+        self.resume()?;
+
+        #[cfg(feature = "swap")]
+        self.swapon()?;
 
-        self.mount_root()?;
+        #[cfg(any(
+            feature = "dmverity",
+            feature = "luks",
+            feature = "integrity",
+            feature = "usb9pfs"
+        ))]
+        self.timed("prepare_aux", Self::prepare_aux)?;
+
+        self.break_at(BreakStage::PreMount)?;
+
+        self.timed("mount_root", Self::mount_root)?;
+
+        if self.options.tmpfs_overlay {
+            self.mount_tmpfs_root_overlay()?;
+        }
+        self.mount_dir_overlays()?;
+
+        #[cfg(feature = "fstab")]
+        mount_fstab(self.options.rootwait)?;
+
+        self.break_at(BreakStage::Mount)?;
 
         self.run_callbacks(CallBack::PostRootMount)?;
 
@@ -336,6 +1141,8 @@ impl<'a> InitContext<'a> {
             mount_bind_kernel_modules()?;
         }
 
+        self.break_at(BreakStage::PrePivot)?;
+
         self.finish()
     }
 }
@@ -345,3 +1152,77 @@ impl Drop for InitContext<'_> {
         finalize();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_init_candidates() {
+        assert_eq!(
+            init_candidates("/usr/lib/systemd/systemd"),
+            [
+                "/usr/lib/systemd/systemd",
+                "/sbin/init",
+                "/etc/init",
+                "/bin/init",
+                "/bin/sh"
+            ]
+        );
+        assert_eq!(
+            init_candidates("/bin/init"),
+            ["/bin/init", "/sbin/init", "/etc/init", "/bin/sh"]
+        );
+    }
+
+    #[test]
+    fn test_init_args() {
+        assert_eq!(init_args(args(&["single"]).into_iter()), args(&["single"]));
+        assert_eq!(
+            init_args(args(&["rsinit-arg", "--", "--single", "--", "x"]).into_iter()),
+            args(&["--single", "--", "x"])
+        );
+        assert_eq!(init_args(args(&["--"]).into_iter()), args(&[]));
+        assert_eq!(init_args(args(&[]).into_iter()), args(&[]));
+    }
+
+    #[test]
+    fn test_merge_init_args() {
+        let cmdline = ["--foo".to_string(), "bar baz".to_string()];
+        assert_eq!(
+            merge_init_args(&cmdline, args(&["single"])),
+            args(&["--foo", "bar baz", "single"])
+        );
+        assert_eq!(
+            merge_init_args(&cmdline, args(&["single", "--foo", "bar baz"])),
+            args(&["--foo", "bar baz", "single"])
+        );
+        assert_eq!(merge_init_args(&[], args(&["single"])), args(&["single"]));
+    }
+
+    #[test]
+    fn test_console_devices() {
+        let console = |device: &str| Console {
+            device: device.into(),
+            options: None,
+        };
+
+        assert_eq!(
+            console_devices(&[
+                console("zero"),
+                console("null"),
+                console("rsinit-missing"),
+                console("zero"),
+            ]),
+            [PathBuf::from("/dev/zero"), PathBuf::from("/dev/null")]
+        );
+        assert_eq!(
+            console_devices(&[console("rsinit-missing")]),
+            Vec::<PathBuf>::new()
+        );
+    }
+}
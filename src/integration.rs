@@ -59,5 +59,7 @@ impl log::Log for IntegrationLogger {
             .write_all(format!("{}\0", msg.dump()).as_bytes());
         let _ = self.vport.borrow().flush();
     }
-    fn flush(&self) {}
+    fn flush(&self) {
+        self.next.flush();
+    }
 }
@@ -0,0 +1,235 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use log::{debug, info};
+
+use crate::blkid::resolve_device;
+use crate::cmdline::CmdlineOptions;
+use crate::dm::{create_device, DmIoctl, DmTableLoad};
+use crate::util::{read_file_limited, wait_for_device, Result, RsinitError, MAX_PARAMS_SIZE};
+
+/// The device-mapper name of the dm-integrity device.
+pub const DEFAULT_INTEGRITY_NAME: &str = "integrity";
+/// The prefix of the device-mapper UUID of the dm-integrity device.
+pub const DEFAULT_INTEGRITY_UUID_PREFIX: &str = "rsinit-integrity-";
+
+const INTEGRITY_MAGIC: &[u8] = b"integrt\0";
+const SUPERBLOCK_SIZE: usize = 29;
+
+/// The tag size and the provided data sectors from the superblock written by `integritysetup
+/// format`.
+fn read_superblock(device: &str, offset: u64) -> Result<(u16, u64)> {
+    let mut superblock = [0u8; SUPERBLOCK_SIZE];
+    let mut f = File::open(device).map_err(|e| format!("Failed to open {device}: {e}"))?;
+    f.seek(SeekFrom::Start(offset * 512))
+        .and_then(|_| f.read_exact(&mut superblock))
+        .map_err(|e| format!("Failed to read the dm-integrity superblock of {device}: {e}"))?;
+    parse_superblock(&superblock).map_err(|e| format!("{device}: {e}").into())
+}
+
+fn parse_superblock(superblock: &[u8]) -> Result<(u16, u64)> {
+    if superblock.len() < SUPERBLOCK_SIZE || !superblock.starts_with(INTEGRITY_MAGIC) {
+        return Err("No dm-integrity superblock".into());
+    }
+    let tag_size = u16::from_le_bytes(superblock[10..12].try_into().unwrap());
+    let provided_data_sectors = u64::from_le_bytes(superblock[16..24].try_into().unwrap());
+    Ok((tag_size, provided_data_sectors))
+}
+
+/// Parameters of the dm-integrity device from `/integrity-params`.
+#[derive(Debug, Default, PartialEq)]
+struct IntegrityParams<'a> {
+    tag_size: Option<u16>,
+    algorithm: Option<&'a str>,
+    provided_data_sectors: Option<u64>,
+    offset: u64,
+    mode: &'a str,
+    block_size: Option<&'a str>,
+    params: Option<&'a str>,
+}
+
+impl<'a> IntegrityParams<'a> {
+    fn from_string(params: &'a str) -> Result<IntegrityParams<'a>> {
+        let mut integrity_params = IntegrityParams {
+            mode: "J",
+            ..Default::default()
+        };
+        for line in params.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => continue,
+            };
+
+            let parse_error = |e| format!("Failed to parse '{key}={value}': {e}");
+            match key {
+                "INTEGRITY_TAG_SIZE" => {
+                    integrity_params.tag_size = Some(value.parse().map_err(parse_error)?)
+                }
+                "INTEGRITY_ALGORITHM" => integrity_params.algorithm = Some(value),
+                "INTEGRITY_PROVIDED_DATA_SECTORS" => {
+                    integrity_params.provided_data_sectors =
+                        Some(value.parse().map_err(parse_error)?)
+                }
+                "INTEGRITY_OFFSET" => {
+                    integrity_params.offset = value.parse().map_err(parse_error)?
+                }
+                "INTEGRITY_MODE" => match value {
+                    "J" | "B" | "D" | "R" => integrity_params.mode = value,
+                    _ => return Err(format!("Invalid INTEGRITY_MODE={value}").into()),
+                },
+                "INTEGRITY_BLOCK_SIZE" => integrity_params.block_size = Some(value),
+                "INTEGRITY_PARAMS" => integrity_params.params = Some(value),
+                _ => (),
+            }
+        }
+        Ok(integrity_params)
+    }
+
+    fn table(&self, device: &str, tag_size: u16) -> String {
+        let mut optional = Vec::new();
+        if let Some(algorithm) = self.algorithm {
+            optional.push(format!("internal_hash:{algorithm}"));
+        }
+        if let Some(block_size) = self.block_size {
+            optional.push(format!("block_size:{block_size}"));
+        }
+        if let Some(params) = self.params {
+            optional.extend(params.split_ascii_whitespace().map(str::to_string));
+        }
+        let mut table = format!(
+            "{device} {} {tag_size} {} {}",
+            self.offset,
+            self.mode,
+            optional.len()
+        );
+        for param in optional {
+            table.push(' ');
+            table.push_str(&param);
+        }
+        table
+    }
+}
+
+/// Activate the dm-integrity device `name` for `device` with the parameters from `param_data`
+/// (the format of `/integrity-params`). The tag size and the provided data sectors default to
+/// the values in the dm-integrity superblock.
+///
+/// Returns the path of the new dm device.
+pub fn activate_integrity(
+    name: &str,
+    uuid_prefix: &str,
+    device: &str,
+    param_data: &str,
+    timeout: Option<Duration>,
+) -> Result<String> {
+    wait_for_device(device, timeout)?;
+
+    let params = IntegrityParams::from_string(param_data)?;
+    let (tag_size, sectors) = match (params.tag_size, params.provided_data_sectors) {
+        (Some(tag_size), Some(sectors)) => (tag_size, sectors),
+        (tag_size, sectors) => {
+            let superblock = read_superblock(device, params.offset)?;
+            (
+                tag_size.unwrap_or(superblock.0),
+                sectors.unwrap_or(superblock.1),
+            )
+        }
+    };
+
+    info!("Configuring dm-integrity device {name} for {device}");
+    let table = params.table(device, tag_size);
+    debug!("dm-integrity table: {table}");
+
+    let uuid = DmIoctl::uuid(uuid_prefix, device);
+    let mut table_load_data = DmTableLoad::with_table("integrity", sectors, 0, &table, &uuid);
+    create_device(name, &uuid, &mut table_load_data, |e| {
+        format!("Failed to load dm table: {e}")
+    })
+}
+
+fn prepare_integrity_device(options: &mut CmdlineOptions) -> Result<()> {
+    let data_device = match &options.integrity {
+        Some(device) => resolve_device(device, options.rootwait)?,
+        None => return Ok(()),
+    };
+
+    /* Later lines override earlier ones, so the cmdline takes precedence */
+    let mut param_data = if Path::new("/integrity-params").exists() {
+        read_file_limited("/integrity-params", MAX_PARAMS_SIZE)?
+    } else {
+        String::new()
+    };
+    for (key, value) in &options.integrity_params {
+        param_data.push_str(&format!("\n{key}={value}"));
+    }
+
+    let device = activate_integrity(
+        DEFAULT_INTEGRITY_NAME,
+        DEFAULT_INTEGRITY_UUID_PREFIX,
+        &data_device,
+        &param_data,
+        options.rootwait,
+    )?;
+    if options.root.as_deref() == Some(data_device.as_str()) {
+        options.replace_root(device.clone());
+    }
+    options.integrity_device = Some(device);
+
+    Ok(())
+}
+
+/// Activate the dm-integrity device for `rsinit.integrity=`. The dm device is recorded in
+/// `options.integrity_device` and replaces `root` if it is the same device.
+pub fn prepare_integrity(options: &mut CmdlineOptions) -> Result<()> {
+    prepare_integrity_device(options).map_err(|e| e.or_kind(RsinitError::Integrity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let params = IntegrityParams::from_string(
+            "INTEGRITY_TAG_SIZE=4\nINTEGRITY_ALGORITHM=crc32c\nINTEGRITY_PROVIDED_DATA_SECTORS=1024\nINTEGRITY_BLOCK_SIZE=4096",
+        )
+        .unwrap();
+        assert_eq!(params.tag_size, Some(4));
+        assert_eq!(params.provided_data_sectors, Some(1024));
+        assert_eq!(
+            params.table("/dev/mmcblk0p3", 4),
+            "/dev/mmcblk0p3 0 4 J 2 internal_hash:crc32c block_size:4096"
+        );
+
+        let params = IntegrityParams::from_string(
+            "INTEGRITY_MODE=B\nINTEGRITY_OFFSET=8\nINTEGRITY_PARAMS=fix_padding allow_discards",
+        )
+        .unwrap();
+        assert_eq!(
+            params.table("/dev/sda2", 32),
+            "/dev/sda2 8 32 B 2 fix_padding allow_discards"
+        );
+
+        assert!(IntegrityParams::from_string("INTEGRITY_MODE=X").is_err());
+        assert!(IntegrityParams::from_string("INTEGRITY_TAG_SIZE=four").is_err());
+    }
+
+    #[test]
+    fn test_superblock() {
+        let mut superblock = INTEGRITY_MAGIC.to_vec();
+        superblock.extend_from_slice(&[5, 0]);
+        superblock.extend_from_slice(&4u16.to_le_bytes());
+        superblock.extend_from_slice(&32u32.to_le_bytes());
+        superblock.extend_from_slice(&204800u64.to_le_bytes());
+        superblock.extend_from_slice(&[0; 5]);
+        assert_eq!(parse_superblock(&superblock).unwrap(), (4, 204800));
+
+        superblock[0] = b'x';
+        assert!(parse_superblock(&superblock).is_err());
+    }
+}
@@ -3,14 +3,65 @@
 
 use std::borrow::Borrow;
 use std::fs::{File, OpenOptions};
+use std::io;
 use std::io::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 use log::{Level, LevelFilter, Metadata, Record};
 
 use crate::util::Result;
 
+/* Mirror all messages to the console, enabled once the cmdline is parsed */
+static LOG_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/* Identical messages within this many milliseconds are only counted, 0 disables it */
+static REPEAT_WINDOW_MS: AtomicU64 = AtomicU64::new(5000);
+
+/// Coalesces repeated identical messages, e.g. from retry loops, so they don't flood
+/// `/dev/kmsg` and trip the printk rate limit.
+#[derive(Default)]
+struct Repeats {
+    last: Option<(u8, String)>,
+    since: Option<Instant>,
+    count: u32,
+}
+
+impl Repeats {
+    /// The lines to log for `msg`: nothing if it repeats the last message within `window`,
+    /// otherwise the summary of the suppressed repetitions, if any, and the message itself.
+    fn push(&mut self, level: u8, msg: &str, now: Instant, window: Duration) -> Vec<(u8, String)> {
+        let repeated = self
+            .last
+            .as_ref()
+            .is_some_and(|(l, m)| *l == level && m == msg);
+        let within = self
+            .since
+            .is_some_and(|since| now.duration_since(since) < window);
+        if repeated && within {
+            self.count += 1;
+            return Vec::new();
+        }
+
+        let mut lines: Vec<_> = self.summary().into_iter().collect();
+        lines.push((level, msg.to_string()));
+        self.last = Some((level, msg.to_string()));
+        self.since = Some(now);
+        lines
+    }
+
+    /// The summary of the suppressed repetitions of the last message, if any.
+    fn summary(&mut self) -> Option<(u8, String)> {
+        let count = std::mem::take(&mut self.count);
+        let (level, _) = self.last.as_ref().filter(|_| count > 0)?;
+        Some((*level, format!("(previous message repeated {count} times)")))
+    }
+}
+
 pub struct KmsgLogger {
     kmsg: File,
+    repeats: Mutex<Repeats>,
 }
 
 impl log::Log for KmsgLogger {
@@ -25,22 +76,112 @@ impl log::Log for KmsgLogger {
             Level::Info => 6,
             Level::Debug | Level::Trace => 7,
         } | (1 << 3);
-        /* Format first to ensure that the whole message is written with
-         * one write() system-call */
-        let msg = format!("<{level}>rsinit: {}", record.args());
-        let _ = self.kmsg.borrow().write_all(msg.as_bytes());
+        let msg = record.args().to_string();
+
+        let window = Duration::from_millis(REPEAT_WINDOW_MS.load(Ordering::Relaxed));
+        /* Locked while writing to keep the summaries in order */
+        let mut repeats = self.repeats.lock().unwrap_or_else(PoisonError::into_inner);
+        for (level, line) in repeats.push(level, &msg, Instant::now(), window) {
+            self.write(level, &line);
+        }
+    }
+    fn flush(&self) {
+        let mut repeats = self.repeats.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((level, summary)) = repeats.summary() {
+            self.write(level, &summary);
+        }
     }
-    fn flush(&self) {}
 }
 
 impl KmsgLogger {
     pub fn new() -> Result<KmsgLogger> {
         let kmsg = OpenOptions::new().write(true).open("/dev/kmsg")?;
-        Ok(KmsgLogger { kmsg })
+        Ok(KmsgLogger {
+            kmsg,
+            repeats: Mutex::default(),
+        })
+    }
+    /// Also write all messages to the console.
+    pub fn log_to_console(enabled: bool) {
+        LOG_CONSOLE.store(enabled, Ordering::Relaxed);
+    }
+    /// Whether all messages are written to the console as well.
+    pub fn logs_to_console() -> bool {
+        LOG_CONSOLE.load(Ordering::Relaxed)
+    }
+    /// Only count identical messages within `window` after the first one and log how often they
+    /// were repeated afterwards. A zero `window` logs every message.
+    pub fn coalesce_repeats(window: Duration) {
+        let ms = u64::try_from(window.as_millis()).unwrap_or(u64::MAX);
+        REPEAT_WINDOW_MS.store(ms, Ordering::Relaxed);
     }
     pub fn enable() -> Result<()> {
         let logger = KmsgLogger::new()?;
         log::set_boxed_logger(Box::new(logger)).map(|()| log::set_max_level(LevelFilter::Trace))?;
         Ok(())
     }
+
+    fn write(&self, level: u8, msg: &str) {
+        /* Format first to ensure that the whole message is written with
+         * one write() system-call */
+        let line = format!("<{level}>rsinit: {msg}");
+        let _ = self.kmsg.borrow().write_all(line.as_bytes());
+
+        /* stdout is the console after setup_console(), which may switch it after parsing the
+         * cmdline */
+        if LOG_CONSOLE.load(Ordering::Relaxed) {
+            let line = format!("rsinit: {msg}\n");
+            let _ = io::stdout().lock().write_all(line.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats() {
+        let window = Duration::from_secs(5);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let line = |level, msg: &str| (level, msg.to_string());
+        let mut repeats = Repeats::default();
+
+        assert_eq!(
+            repeats.push(11, "retry", at(0), window),
+            [line(11, "retry")]
+        );
+        assert_eq!(repeats.push(11, "retry", at(1), window), []);
+        assert_eq!(repeats.push(11, "retry", at(2), window), []);
+        /* Not identical with a different level */
+        assert_eq!(
+            repeats.push(12, "retry", at(3), window),
+            [
+                line(11, "(previous message repeated 2 times)"),
+                line(12, "retry")
+            ]
+        );
+        assert_eq!(repeats.push(12, "retry", at(4), window), []);
+        /* Shown again after the window */
+        assert_eq!(
+            repeats.push(12, "retry", at(8), window),
+            [
+                line(12, "(previous message repeated 1 times)"),
+                line(12, "retry")
+            ]
+        );
+        assert_eq!(repeats.push(12, "retry", at(9), window), []);
+        assert_eq!(
+            repeats.summary(),
+            Some(line(12, "(previous message repeated 1 times)"))
+        );
+        assert_eq!(repeats.summary(), None);
+
+        /* A zero window disables coalescing */
+        assert_eq!(
+            repeats.push(12, "retry", at(9), Duration::ZERO),
+            [line(12, "retry")]
+        );
+    }
 }
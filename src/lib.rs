@@ -1,16 +1,77 @@
 // SPDX-FileCopyrightText: 2025 The rsinit Authors
 // SPDX-License-Identifier: GPL-2.0-only
 
+#![cfg_attr(feature = "no_std", no_std)]
+
+extern crate alloc;
+
+#[cfg(not(feature = "no_std"))]
+pub mod blkid;
+#[cfg(all(feature = "bootchooser", not(feature = "no_std")))]
+pub mod bootchooser;
+#[cfg(not(feature = "no_std"))]
+pub mod bootconfig;
+#[cfg(not(feature = "no_std"))]
 pub mod cmdline;
-#[cfg(feature = "dmverity")]
+/* Only the part of the cmdline parser that uses `core` and `alloc` */
+#[cfg(feature = "no_std")]
+pub mod cmdline {
+    pub mod parse;
+}
+#[cfg(not(feature = "no_std"))]
+pub mod console;
+#[cfg(all(
+    any(feature = "dmverity", feature = "luks", feature = "integrity"),
+    not(feature = "no_std")
+))]
+mod dm;
+#[cfg(all(feature = "dmverity", not(feature = "no_std")))]
 pub mod dmverity;
+#[cfg(not(feature = "no_std"))]
+pub mod error;
+#[cfg(all(feature = "firmware", not(feature = "no_std")))]
+pub mod firmware;
+#[cfg(all(feature = "fstab", not(feature = "no_std")))]
+pub mod fstab;
+#[cfg(not(feature = "no_std"))]
+pub mod hooks;
+#[cfg(not(feature = "no_std"))]
 pub mod init;
-#[cfg(feature = "integration-test")]
+#[cfg(all(feature = "integration-test", not(feature = "no_std")))]
 pub mod integration;
+#[cfg(all(feature = "integrity", not(feature = "no_std")))]
+pub mod integrity;
+#[cfg(not(feature = "no_std"))]
 pub mod kmsg;
+#[cfg(all(feature = "loop", not(feature = "no_std")))]
+pub mod loopdev;
+#[cfg(all(feature = "luks", not(feature = "no_std")))]
+pub mod luks;
+#[cfg(all(feature = "modules", not(feature = "no_std")))]
+pub mod modules;
+#[cfg(not(feature = "no_std"))]
 pub mod mount;
-#[cfg(feature = "systemd")]
+#[cfg(not(feature = "no_std"))]
+pub mod mountargs;
+#[cfg(all(feature = "net", not(feature = "no_std")))]
+pub mod net;
+#[cfg(not(feature = "no_std"))]
+pub mod report;
+#[cfg(all(feature = "resume", not(feature = "no_std")))]
+pub mod resume;
+#[cfg(not(feature = "no_std"))]
+pub mod slot;
+#[cfg(all(feature = "swap", not(feature = "no_std")))]
+pub mod swap;
+#[cfg(all(feature = "systemd", not(feature = "no_std")))]
 pub mod systemd;
-#[cfg(feature = "usb9pfs")]
+#[cfg(all(feature = "tpm", not(feature = "no_std")))]
+pub mod tpm;
+#[cfg(not(feature = "no_std"))]
+pub mod uevent;
+#[cfg(all(feature = "usb9pfs", not(feature = "no_std")))]
 pub mod usbg_9pfs;
+#[cfg(not(feature = "no_std"))]
 pub mod util;
+#[cfg(all(feature = "zram", not(feature = "no_std")))]
+pub mod zram;
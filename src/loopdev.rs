@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Loop devices for rootfs image files.
+
+use std::fs::OpenOptions;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use log::info;
+use nix::mount::MsFlags;
+use nix::{ioctl_none_bad, ioctl_write_int_bad};
+
+use crate::mount::mount_regular;
+use crate::util::{wait_for_device, Result};
+
+const LOOP_SET_FD: u16 = 0x4c00;
+const LOOP_CLR_FD: u16 = 0x4c01;
+const LOOP_CTL_GET_FREE: u16 = 0x4c82;
+
+ioctl_write_int_bad!(loop_set_fd, LOOP_SET_FD);
+ioctl_none_bad!(loop_clr_fd, LOOP_CLR_FD);
+ioctl_none_bad!(loop_ctl_get_free, LOOP_CTL_GET_FREE);
+
+/// Mount the image `file` with a loop device. The loop device is read-only if `flags` contains
+/// `MS_RDONLY`.
+pub fn mount_loop(
+    file: &str,
+    dst: &str,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+) -> Result<()> {
+    let read_only = flags.contains(MsFlags::MS_RDONLY);
+
+    let control = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/loop-control")
+        .map_err(|e| format!("Failed to open /dev/loop-control: {e}"))?;
+    let nr = unsafe { loop_ctl_get_free(control.as_raw_fd()) }
+        .map_err(|e| format!("Failed to get a free loop device: {e}"))?;
+    let device = format!("/dev/loop{nr}");
+    wait_for_device(&device, Some(Duration::from_secs(1)))?;
+
+    let backing = OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(file)
+        .map_err(|e| format!("Failed to open {file}: {e}"))?;
+    let loopdev = OpenOptions::new()
+        .read(true)
+        .write(!read_only)
+        .open(&device)
+        .map_err(|e| format!("Failed to open {device}: {e}"))?;
+    unsafe { loop_set_fd(loopdev.as_raw_fd(), backing.as_raw_fd()) }
+        .map_err(|e| format!("Failed to attach {file} to {device}: {e}"))?;
+
+    info!("Attached {file} to {device}");
+
+    if let Err(e) = mount_regular(Some(&device), dst, fstype, flags, data) {
+        let _ = unsafe { loop_clr_fd(loopdev.as_raw_fd()) };
+        return Err(e);
+    }
+    Ok(())
+}
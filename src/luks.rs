@@ -0,0 +1,1287 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::{canonicalize, File};
+use std::io::Read;
+use std::mem::take;
+use std::path::Path;
+use std::time::Duration;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64ct::{Base64, Encoding};
+use log::{debug, info, warn};
+use nix::mount::{umount2, MntFlags, MsFlags};
+use pbkdf2::pbkdf2_hmac;
+use sha1::Sha1;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+
+use crate::blkid::resolve_device;
+use crate::cmdline::{CmdlineOptions, PlainCryptOptions};
+use crate::dm::{create_device, remove_device, DmIoctl, DmTableLoad, DM_READONLY_FLAG};
+use crate::mount::mount_regular;
+use crate::tpm::{parse_pcrs, pcr_bank, Tpm, SRK_HANDLE};
+use crate::util::{
+    read_file, read_file_limited, read_limited, wait_for_device, Result, RsinitError,
+    MAX_PARAMS_SIZE,
+};
+
+/// The device-mapper name of the unlocked rootfs.
+pub const DEFAULT_LUKS_NAME: &str = "luks-rootfs";
+/// The prefix of the device-mapper UUID of the unlocked rootfs.
+pub const DEFAULT_LUKS_UUID_PREFIX: &str = "rsinit-luks-root-";
+
+/// The device-mapper name of the plain dm-crypt rootfs.
+pub const DEFAULT_PLAIN_CRYPT_NAME: &str = "crypt-rootfs";
+/// The prefix of the device-mapper UUID of the plain dm-crypt rootfs.
+pub const DEFAULT_PLAIN_CRYPT_UUID_PREFIX: &str = "rsinit-crypt-root-";
+
+/// The maximum size of key files and sealed keys.
+const MAX_KEY_FILE_SIZE: u64 = 8192;
+
+/// The temporary mount point of the device with the key file.
+const KEYFILE_MOUNTPOINT: &str = "/luks-key";
+
+/// The device-mapper name of the temporary device that decrypts a keyslot.
+const KEYSLOT_NAME: &str = "luks-keyslot";
+/// The prefix of the device-mapper UUID of the temporary keyslot device.
+const KEYSLOT_UUID_PREFIX: &str = "rsinit-luks-keyslot-";
+/// How long to wait for the device node of the temporary keyslot device.
+const KEYSLOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The LUKS2 token type of `systemd-cryptenroll --tpm2-device`.
+const TOKEN_TYPE: &str = "systemd-tpm2";
+
+const LUKS_MAGIC: &[u8] = b"LUKS\xba\xbe";
+const LUKS2_BINARY_HEADER_SIZE: usize = 4096;
+
+const LUKS1_KEYSLOTS: usize = 8;
+const LUKS1_KEYSLOT_OFFSET: usize = 208;
+const LUKS1_KEYSLOT_SIZE: usize = 48;
+const LUKS1_KEY_ENABLED: u32 = 0x00ac_71f3;
+
+/// The TPM2 object with a sealed keyslot passphrase.
+#[derive(Debug, PartialEq)]
+struct SealedKey {
+    blob: Vec<u8>,
+    /// Sealed below the persistent storage root key instead of a transient one.
+    srk: bool,
+    bank: String,
+    pcrs: Vec<u32>,
+    /// The keyslots that the passphrase unlocks. Empty to try all of them.
+    keyslots: Vec<String>,
+    /// The passphrase is the base64 encoded secret, like systemd-cryptenroll enrolls it.
+    base64: bool,
+}
+
+/// How the key that decrypts a keyslot is derived from the passphrase.
+#[derive(Debug, PartialEq)]
+enum Kdf {
+    Pbkdf2 {
+        hash: String,
+        iterations: u32,
+    },
+    Argon2 {
+        algorithm: Algorithm,
+        time: u32,
+        /// The memory cost in KiB.
+        memory: u32,
+        cpus: u32,
+    },
+}
+
+/// A keyslot with a copy of the volume key. The key is split into `stripes` with the
+/// anti-forensic splitter and encrypted with a key derived from a passphrase.
+#[derive(Debug, PartialEq)]
+struct Keyslot {
+    id: String,
+    kdf: Kdf,
+    salt: Vec<u8>,
+    /// The dm-crypt cipher of the key material.
+    cipher: String,
+    /// The size of the derived key for `cipher`.
+    cipher_key_size: usize,
+    /// The start of the key material in bytes.
+    offset: u64,
+    af_hash: String,
+    stripes: usize,
+    /// The size of the volume key.
+    key_size: usize,
+}
+
+/// The PBKDF2 digest of the volume key in the LUKS header.
+#[derive(Debug, PartialEq)]
+struct KeyDigest {
+    hash: String,
+    iterations: u32,
+    salt: Vec<u8>,
+    digest: Vec<u8>,
+}
+
+impl KeyDigest {
+    /// Check that `key` is the volume key before it is used to map the device.
+    fn verify(&self, key: &[u8]) -> Result<()> {
+        let mut digest = vec![0; self.digest.len()];
+        pbkdf2(&self.hash, key, &self.salt, self.iterations, &mut digest)?;
+        if digest != self.digest {
+            return Err("The volume key does not match the digest in the LUKS header".into());
+        }
+        Ok(())
+    }
+}
+
+impl Keyslot {
+    /// Derive the key that decrypts the key material from `passphrase`.
+    fn derive_key(&self, passphrase: &[u8]) -> Result<Vec<u8>> {
+        let mut key = vec![0; self.cipher_key_size];
+        match &self.kdf {
+            Kdf::Pbkdf2 { hash, iterations } => {
+                pbkdf2(hash, passphrase, &self.salt, *iterations, &mut key)?
+            }
+            Kdf::Argon2 {
+                algorithm,
+                time,
+                memory,
+                cpus,
+            } => {
+                let params = Params::new(*memory, *time, *cpus, Some(key.len()))
+                    .map_err(|e| format!("Invalid argon2 parameters: {e}"))?;
+                Argon2::new(*algorithm, Version::V0x13, params)
+                    .hash_password_into(passphrase, &self.salt, &mut key)
+                    .map_err(|e| format!("Failed to derive the keyslot key: {e}"))?;
+            }
+        }
+        Ok(key)
+    }
+
+    /// Decrypt the keyslot of `device` with `passphrase` and return the volume key. The key
+    /// still has to be checked with the digest, a wrong passphrase just returns another key.
+    fn unlock(&self, device: &str, passphrase: &[u8]) -> Result<Vec<u8>> {
+        let mut key = self.derive_key(passphrase)?;
+        let material = read_key_material(device, self, &key);
+        key.fill(0);
+        let mut material = material?;
+        let volume_key = af_merge(&material, self.key_size, self.stripes, &self.af_hash);
+        material.fill(0);
+        volume_key
+    }
+}
+
+/// The parts of the LUKS header needed to unlock a keyslot and map the data segment.
+#[derive(Debug, Default, PartialEq)]
+struct LuksHeader {
+    cipher: String,
+    /// The start of the data segment in 512 byte sectors.
+    offset: u64,
+    sector_size: u64,
+    key_size: Option<usize>,
+    digest: Option<KeyDigest>,
+    /// The keyslots with the volume key of the data segment.
+    keyslots: Vec<Keyslot>,
+    /// The usable `systemd-tpm2` tokens.
+    tokens: Vec<SealedKey>,
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn c_str(data: &[u8]) -> String {
+    let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+    Base64::decode_vec(data).map_err(|_| "Invalid base64 data".into())
+}
+
+/// A number in the LUKS2 JSON metadata. Offsets and sizes are strings to avoid precision issues.
+fn json_u64(value: &json::JsonValue) -> Option<u64> {
+    value
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .or(value.as_u64())
+}
+
+/// Parse a `systemd-tpm2` token. Tokens with a PIN, a signed PCR policy or a pcrlock policy
+/// and tokens sealed below an RSA storage root key are not supported.
+fn parse_token(token: &json::JsonValue) -> Result<SealedKey> {
+    if token["tpm2-pin"].as_bool() == Some(true) {
+        return Err("TPM2 PINs are not supported".into());
+    }
+    if !token["tpm2-pubkey"].is_null() || !token["tpm2-pubkey-pcrs"].is_empty() {
+        return Err("Signed PCR policies are not supported".into());
+    }
+    if token["tpm2_pcrlock"].as_bool() == Some(true) {
+        return Err("pcrlock policies are not supported".into());
+    }
+    if !matches!(token["tpm2-primary-alg"].as_str(), None | Some("ecc")) {
+        return Err(format!(
+            "Unsupported storage root key algorithm {}",
+            token["tpm2-primary-alg"]
+        )
+        .into());
+    }
+    let blob = token["tpm2-blob"]
+        .as_str()
+        .ok_or("Invalid tpm2-blob in the LUKS2 token")?;
+    let pcrs = token["tpm2-pcrs"]
+        .members()
+        .map(|pcr| pcr.as_u32().map(|pcr| pcr.to_string()))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("Invalid tpm2-pcrs in the LUKS2 token")?;
+    let keyslots = token["keyslots"]
+        .members()
+        .map(|keyslot| keyslot.as_str().map(str::to_string))
+        .collect::<Option<Vec<_>>>()
+        .ok_or("Invalid keyslots in the LUKS2 token")?;
+    Ok(SealedKey {
+        blob: decode_base64(blob)?,
+        srk: !token["tpm2_srk"].is_null(),
+        bank: token["tpm2-pcr-bank"]
+            .as_str()
+            .unwrap_or("sha256")
+            .to_string(),
+        pcrs: parse_pcrs(&pcrs.join(","))?,
+        keyslots,
+        base64: true,
+    })
+}
+
+fn parse_keyslot(id: &str, keyslot: &json::JsonValue) -> Result<Keyslot> {
+    let (kdf, af, area) = (&keyslot["kdf"], &keyslot["af"], &keyslot["area"]);
+    if keyslot["type"] != "luks2" || af["type"] != "luks1" || area["type"] != "raw" {
+        return Err(format!("Unsupported keyslot type {}", keyslot["type"]).into());
+    }
+    let kdf_u32 = |key: &str| {
+        kdf[key]
+            .as_u32()
+            .ok_or(format!("Invalid {key} of the keyslot kdf"))
+    };
+    let algorithm = match kdf["type"].as_str() {
+        Some("pbkdf2") => None,
+        Some("argon2i") => Some(Algorithm::Argon2i),
+        Some("argon2id") => Some(Algorithm::Argon2id),
+        _ => return Err(format!("Unsupported keyslot kdf {}", kdf["type"]).into()),
+    };
+    let kdf_params = match algorithm {
+        None => Kdf::Pbkdf2 {
+            hash: kdf["hash"]
+                .as_str()
+                .ok_or("Invalid hash of the keyslot kdf")?
+                .to_string(),
+            iterations: kdf_u32("iterations")?,
+        },
+        Some(algorithm) => Kdf::Argon2 {
+            algorithm,
+            time: kdf_u32("time")?,
+            memory: kdf_u32("memory")?,
+            cpus: kdf_u32("cpus")?,
+        },
+    };
+
+    Ok(Keyslot {
+        id: id.to_string(),
+        kdf: kdf_params,
+        salt: decode_base64(
+            kdf["salt"]
+                .as_str()
+                .ok_or("Invalid salt of the keyslot kdf")?,
+        )?,
+        cipher: area["encryption"]
+            .as_str()
+            .ok_or("Invalid keyslot encryption")?
+            .to_string(),
+        cipher_key_size: area["key_size"]
+            .as_usize()
+            .ok_or("Invalid keyslot area key size")?,
+        offset: json_u64(&area["offset"]).ok_or("Invalid keyslot offset")?,
+        af_hash: af["hash"]
+            .as_str()
+            .ok_or("Invalid hash of the keyslot af")?
+            .to_string(),
+        stripes: af["stripes"].as_usize().ok_or("Invalid keyslot stripes")?,
+        key_size: keyslot["key_size"]
+            .as_usize()
+            .ok_or("Invalid keyslot key size")?,
+    })
+}
+
+fn parse_digest(digest: &json::JsonValue) -> Result<KeyDigest> {
+    if digest["type"] != "pbkdf2" {
+        return Err(format!("Unsupported LUKS2 digest type {}", digest["type"]).into());
+    }
+    Ok(KeyDigest {
+        hash: digest["hash"]
+            .as_str()
+            .ok_or("Invalid LUKS2 digest hash")?
+            .to_string(),
+        iterations: digest["iterations"]
+            .as_u32()
+            .ok_or("Invalid LUKS2 digest iterations")?,
+        salt: decode_base64(digest["salt"].as_str().ok_or("Invalid LUKS2 digest salt")?)?,
+        digest: decode_base64(digest["digest"].as_str().ok_or("Invalid LUKS2 digest")?)?,
+    })
+}
+
+fn parse_luks2_metadata(metadata: &str) -> Result<LuksHeader> {
+    let metadata =
+        json::parse(metadata).map_err(|e| format!("Failed to parse the LUKS2 metadata: {e}"))?;
+
+    let (id, segment) = metadata["segments"]
+        .entries()
+        .find(|(_, segment)| segment["type"] == "crypt")
+        .ok_or("No crypt segment in the LUKS2 metadata")?;
+    let offset = json_u64(&segment["offset"]).ok_or("Invalid LUKS2 segment offset")?;
+    let key_size = metadata["keyslots"]
+        .entries()
+        .find_map(|(_, keyslot)| keyslot["key_size"].as_usize());
+    let digest = metadata["digests"]
+        .entries()
+        .find(|(_, digest)| digest["segments"].members().any(|segment| segment == id));
+    let keyslots = metadata["keyslots"]
+        .entries()
+        .filter(|(id, _)| {
+            digest.is_some_and(|(_, digest)| digest["keyslots"].members().any(|slot| slot == *id))
+        })
+        .filter_map(|(id, keyslot)| {
+            parse_keyslot(id, keyslot)
+                .map_err(|e| debug!("Skipping LUKS2 keyslot {id}: {e}"))
+                .ok()
+        })
+        .collect();
+    let tokens = metadata["tokens"]
+        .entries()
+        .filter(|(_, token)| token["type"] == TOKEN_TYPE)
+        .filter_map(|(id, token)| {
+            parse_token(token)
+                .map_err(|e| warn!("Skipping LUKS2 token {id}: {e}"))
+                .ok()
+        })
+        .collect();
+
+    Ok(LuksHeader {
+        cipher: segment["encryption"]
+            .as_str()
+            .ok_or("Invalid LUKS2 segment encryption")?
+            .to_string(),
+        offset: offset / 512,
+        sector_size: json_u64(&segment["sector_size"]).unwrap_or(512),
+        key_size,
+        digest: digest.map(|(_, digest)| parse_digest(digest)).transpose()?,
+        keyslots,
+        tokens,
+    })
+}
+
+/// The active keyslots of a LUKS1 header. They use the cipher and hash of the header.
+fn luks1_keyslots(header: &[u8], cipher: &str, hash: &str, key_size: usize) -> Vec<Keyslot> {
+    (0..LUKS1_KEYSLOTS)
+        .map(|i| (i, &header[LUKS1_KEYSLOT_OFFSET + i * LUKS1_KEYSLOT_SIZE..]))
+        .filter(|(_, keyslot)| be_u32(keyslot, 0) == LUKS1_KEY_ENABLED)
+        .map(|(i, keyslot)| Keyslot {
+            id: i.to_string(),
+            kdf: Kdf::Pbkdf2 {
+                hash: hash.to_string(),
+                iterations: be_u32(keyslot, 4),
+            },
+            salt: keyslot[8..40].to_vec(),
+            cipher: cipher.to_string(),
+            cipher_key_size: key_size,
+            offset: u64::from(be_u32(keyslot, 40)) * 512,
+            af_hash: hash.to_string(),
+            stripes: be_u32(keyslot, 44) as usize,
+            key_size,
+        })
+        .collect()
+}
+
+impl LuksHeader {
+    fn parse(header: &[u8]) -> Result<LuksHeader> {
+        if header.len() < 6 || &header[..6] != LUKS_MAGIC {
+            return Err("No LUKS header found".into());
+        }
+        match u16::from_be_bytes([header[6], header[7]]) {
+            1 if header.len() >= LUKS1_KEYSLOT_OFFSET + LUKS1_KEYSLOTS * LUKS1_KEYSLOT_SIZE => {
+                let cipher = format!("{}-{}", c_str(&header[8..40]), c_str(&header[40..72]));
+                let hash = c_str(&header[72..104]);
+                let key_size = be_u32(header, 108) as usize;
+                Ok(LuksHeader {
+                    offset: be_u32(header, 104).into(),
+                    sector_size: 512,
+                    key_size: Some(key_size),
+                    digest: Some(KeyDigest {
+                        hash: hash.clone(),
+                        iterations: be_u32(header, 164),
+                        salt: header[132..164].to_vec(),
+                        digest: header[112..132].to_vec(),
+                    }),
+                    keyslots: luks1_keyslots(header, &cipher, &hash, key_size),
+                    tokens: Vec::new(),
+                    cipher,
+                })
+            }
+            2 if header.len() > LUKS2_BINARY_HEADER_SIZE => {
+                parse_luks2_metadata(&c_str(&header[LUKS2_BINARY_HEADER_SIZE..]))
+            }
+            _ => Err("Unsupported LUKS header".into()),
+        }
+    }
+
+    fn read(device: &str) -> Result<LuksHeader> {
+        let mut f = File::open(device).map_err(|e| format!("Failed to open {device}: {e}"))?;
+        let mut header = vec![0; LUKS2_BINARY_HEADER_SIZE];
+        f.read_exact(&mut header)
+            .map_err(|e| format!("Failed to read the LUKS header of {device}: {e}"))?;
+
+        if header[6..8] == [0, 2] {
+            let size = u64::from_be_bytes(header[8..16].try_into().unwrap()) as usize;
+            if !(LUKS2_BINARY_HEADER_SIZE..=4 << 20).contains(&size) {
+                return Err(format!("Invalid LUKS2 header size {size}").into());
+            }
+            header.resize(size, 0);
+            f.read_exact(&mut header[LUKS2_BINARY_HEADER_SIZE..])
+                .map_err(|e| format!("Failed to read the LUKS2 metadata of {device}: {e}"))?;
+        }
+        LuksHeader::parse(&header).map_err(|e| format!("{device}: {e}").into())
+    }
+}
+
+/// Parameters from `/luks-params` that complement or override the LUKS header.
+#[derive(Debug, Default, PartialEq)]
+struct LuksParams<'a> {
+    tpm2_blob: Option<&'a str>,
+    tpm2_pcrs: Option<&'a str>,
+    tpm2_pcr_bank: Option<&'a str>,
+    cipher: Option<&'a str>,
+    offset: Option<u64>,
+}
+
+impl<'a> LuksParams<'a> {
+    fn from_string(params: &'a str) -> Result<LuksParams<'a>> {
+        let mut luks_params = LuksParams::default();
+        for line in params.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => continue,
+            };
+
+            match key {
+                "LUKS_TPM2_BLOB" => luks_params.tpm2_blob = Some(value),
+                "LUKS_TPM2_PCRS" => luks_params.tpm2_pcrs = Some(value),
+                "LUKS_TPM2_PCR_BANK" => luks_params.tpm2_pcr_bank = Some(value),
+                "LUKS_CIPHER" => luks_params.cipher = Some(value),
+                "LUKS_OFFSET" => {
+                    luks_params.offset = Some(
+                        value
+                            .parse()
+                            .map_err(|e| format!("Failed to parse 'LUKS_OFFSET={value}': {e}"))?,
+                    )
+                }
+                _ => (),
+            }
+        }
+        Ok(luks_params)
+    }
+
+    /// The header of `device` with the cipher and offset from the parameters.
+    fn header(&self, device: &str) -> Result<LuksHeader> {
+        let mut header = LuksHeader::read(device)?;
+        if let Some(cipher) = self.cipher {
+            header.cipher = cipher.to_string();
+        }
+        if let Some(offset) = self.offset {
+            header.offset = offset;
+        }
+        Ok(header)
+    }
+
+    /// The sealed key from the parameters, which takes precedence over the LUKS2 tokens. The
+    /// unsealed data is the passphrase of any keyslot.
+    fn sealed_key(&self) -> Result<Option<SealedKey>> {
+        let Some(path) = self.tpm2_blob else {
+            return Ok(None);
+        };
+        Ok(Some(SealedKey {
+            blob: read_limited(Path::new(path), MAX_KEY_FILE_SIZE)?,
+            srk: false,
+            bank: self.tpm2_pcr_bank.unwrap_or("sha256").to_string(),
+            pcrs: parse_pcrs(self.tpm2_pcrs.unwrap_or_default())?,
+            keyslots: Vec::new(),
+            base64: false,
+        }))
+    }
+}
+
+/// The size of the block device `device` in 512 byte sectors.
+fn device_sectors(device: &str) -> Result<u64> {
+    let path = canonicalize(device).map_err(|e| format!("Failed to resolve {device}: {e}"))?;
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(format!("Invalid device {device}"))?;
+    let size = read_file(&format!("/sys/class/block/{name}/size"))?;
+    Ok(size
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid size of {device}: {e}"))?)
+}
+
+/// The dm-crypt table with the hex encoded `key`. The buffer is allocated with its final size,
+/// so zeroing it leaves no copy of the key behind.
+fn crypt_table(header: &LuksHeader, key: &[u8], iv_offset: u64, device: &str) -> Vec<u8> {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut args = format!(" {iv_offset} {device} {}", header.offset);
+    if header.sector_size != 512 {
+        args.push_str(&format!(" 1 sector_size:{}", header.sector_size));
+    }
+    let mut table = Vec::with_capacity(header.cipher.len() + 1 + 2 * key.len() + args.len());
+    table.extend_from_slice(header.cipher.as_bytes());
+    table.push(b' ');
+    for byte in key {
+        table.push(DIGITS[(byte >> 4) as usize]);
+        table.push(DIGITS[(byte & 0xf) as usize]);
+    }
+    table.extend_from_slice(args.as_bytes());
+    table
+}
+
+/// Derive `key.len()` bytes from `password` and `salt` with PBKDF2 and the HMAC of `hash`.
+fn pbkdf2(hash: &str, password: &[u8], salt: &[u8], iterations: u32, key: &mut [u8]) -> Result<()> {
+    if iterations == 0 {
+        return Err("Invalid PBKDF2 iteration count 0".into());
+    }
+    match hash {
+        "sha1" => pbkdf2_hmac::<Sha1>(password, salt, iterations, key),
+        "sha224" => pbkdf2_hmac::<Sha224>(password, salt, iterations, key),
+        "sha256" => pbkdf2_hmac::<Sha256>(password, salt, iterations, key),
+        "sha384" => pbkdf2_hmac::<Sha384>(password, salt, iterations, key),
+        "sha512" => pbkdf2_hmac::<Sha512>(password, salt, iterations, key),
+        _ => return Err(format!("Unsupported hash {hash}").into()),
+    }
+    Ok(())
+}
+
+/// The digest of the concatenated `data` with `hash`, e.g. `sha256`.
+fn digest(hash: &str, data: &[&[u8]]) -> Result<Vec<u8>> {
+    fn digest_with<D: Digest>(data: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = D::new();
+        data.iter().for_each(|data| hasher.update(data));
+        hasher.finalize().to_vec()
+    }
+    match hash {
+        "sha1" => Ok(digest_with::<Sha1>(data)),
+        "sha224" => Ok(digest_with::<Sha224>(data)),
+        "sha256" => Ok(digest_with::<Sha256>(data)),
+        "sha384" => Ok(digest_with::<Sha384>(data)),
+        "sha512" => Ok(digest_with::<Sha512>(data)),
+        _ => Err(format!("Unsupported hash {hash}").into()),
+    }
+}
+
+/// The diffusion of the anti-forensic splitter: Each digest sized block of `data` is replaced
+/// by the digest of its big endian index and itself. The last block may be shorter.
+fn diffuse(data: &mut [u8], hash: &str) -> Result<()> {
+    let digest_size = digest(hash, &[])?.len();
+    for (i, block) in data.chunks_mut(digest_size).enumerate() {
+        let mut diffused = digest(hash, &[&(i as u32).to_be_bytes(), block])?;
+        let len = block.len();
+        block.copy_from_slice(&diffused[..len]);
+        diffused.fill(0);
+    }
+    Ok(())
+}
+
+/// Merge the `stripes` of the anti-forensic splitter in `material` to the `key_size` bytes that
+/// were split, like `AF_merge()` of cryptsetup.
+fn af_merge(material: &[u8], key_size: usize, stripes: usize, hash: &str) -> Result<Vec<u8>> {
+    if key_size == 0 || stripes == 0 || material.len() < key_size * stripes {
+        return Err("Invalid keyslot key material".into());
+    }
+    let mut key = vec![0; key_size];
+    let mut stripes = material.chunks_exact(key_size).take(stripes);
+    let last = stripes.next_back().unwrap();
+    for stripe in stripes {
+        key.iter_mut().zip(stripe).for_each(|(k, s)| *k ^= s);
+        if let Err(e) = diffuse(&mut key, hash) {
+            key.fill(0);
+            return Err(e);
+        }
+    }
+    key.iter_mut().zip(last).for_each(|(k, s)| *k ^= s);
+    Ok(key)
+}
+
+/// Decrypt the key material of `keyslot` with `key` through a temporary read-only dm-crypt
+/// device, like cryptsetup does when the kernel crypto API is not available to userspace.
+fn read_key_material(device: &str, keyslot: &Keyslot, key: &[u8]) -> Result<Vec<u8>> {
+    /* The mapping covers the rest of the device, only the key material is read */
+    let header = LuksHeader {
+        cipher: keyslot.cipher.clone(),
+        offset: keyslot.offset / 512,
+        sector_size: 512,
+        ..Default::default()
+    };
+    let path = match map_crypt(
+        KEYSLOT_NAME,
+        KEYSLOT_UUID_PREFIX,
+        device,
+        &header,
+        0,
+        key,
+        DM_READONLY_FLAG,
+    ) {
+        Ok(path) => path,
+        Err(e) => {
+            /* The device may have been created before the table was rejected */
+            let _ = remove_device(KEYSLOT_NAME);
+            return Err(e);
+        }
+    };
+    let mut material = vec![0; keyslot.key_size * keyslot.stripes];
+    let result = wait_for_device(&path, Some(KEYSLOT_TIMEOUT)).and_then(|_| {
+        File::open(&path)
+            .and_then(|mut f| f.read_exact(&mut material))
+            .map_err(|e| format!("Failed to read keyslot {} of {device}: {e}", keyslot.id).into())
+    });
+    match result.and(remove_device(KEYSLOT_NAME)) {
+        Ok(()) => Ok(material),
+        Err(e) => {
+            material.fill(0);
+            Err(e)
+        }
+    }
+}
+
+/// Try to unlock the keyslots of `device` with the ids `ids`, or all keyslots if `ids` is
+/// empty, with `passphrase`.
+///
+/// Returns the volume key of the first keyslot that matches the digest.
+fn unlock_keyslots(
+    device: &str,
+    header: &LuksHeader,
+    ids: &[String],
+    passphrase: &[u8],
+) -> Result<Vec<u8>> {
+    let digest = header
+        .digest
+        .as_ref()
+        .ok_or("No volume key digest in the LUKS header")?;
+    let mut error = RsinitError::from(format!("No keyslot of {device} can be unlocked"));
+    for keyslot in header
+        .keyslots
+        .iter()
+        .filter(|keyslot| ids.is_empty() || ids.contains(&keyslot.id))
+    {
+        debug!("Trying keyslot {} of {device}", keyslot.id);
+        let result = keyslot.unlock(device, passphrase).and_then(|mut key| {
+            let verified = digest.verify(&key);
+            if verified.is_err() {
+                key.fill(0);
+            }
+            verified.map(|_| key)
+        });
+        match result {
+            Ok(key) => return Ok(key),
+            Err(e) => {
+                debug!("Keyslot {} of {device}: {e}", keyslot.id);
+                error = format!("Failed to unlock keyslot {} of {device}: {e}", keyslot.id).into();
+            }
+        }
+    }
+    Err(error)
+}
+
+/// Unseal the passphrase in `sealed_key` with the TPM2.
+fn unseal_passphrase(tpm: &mut Tpm, sealed_key: &SealedKey) -> Result<Vec<u8>> {
+    let mut secret = tpm.unseal_blob(
+        &sealed_key.blob,
+        sealed_key.srk.then_some(SRK_HANDLE),
+        pcr_bank(&sealed_key.bank)?,
+        &sealed_key.pcrs,
+    )?;
+    if !sealed_key.base64 {
+        return Ok(secret);
+    }
+    let passphrase = Base64::encode_string(&secret).into_bytes();
+    secret.fill(0);
+    Ok(passphrase)
+}
+
+/// Unlock a keyslot of the LUKS device `device` with a passphrase that is sealed in the TPM2 and
+/// map it as `name`. The sealed passphrase and other parameters are taken from `param_data`
+/// (the format of `/luks-params`) or a `systemd-tpm2` LUKS2 token.
+///
+/// Returns the path of the new dm device.
+pub fn activate_luks(
+    name: &str,
+    uuid_prefix: &str,
+    device: &str,
+    param_data: &str,
+) -> Result<String> {
+    let params = LuksParams::from_string(param_data)?;
+    let mut header = params.header(device)?;
+    let sealed_keys = match params.sealed_key()? {
+        Some(sealed_key) => vec![sealed_key],
+        None => take(&mut header.tokens),
+    };
+    if sealed_keys.is_empty() {
+        return Err(format!("No sealed passphrase for {device}").into());
+    }
+
+    let mut tpm = Tpm::open()?;
+    let mut result = Err(RsinitError::from("No sealed passphrase could be used"));
+    for sealed_key in &sealed_keys {
+        info!(
+            "Unsealing the passphrase of {device} with PCRs {:?} ({})",
+            sealed_key.pcrs, sealed_key.bank
+        );
+        result = unseal_passphrase(&mut tpm, sealed_key).and_then(|mut passphrase| {
+            let key = unlock_keyslots(device, &header, &sealed_key.keyslots, &passphrase);
+            passphrase.fill(0);
+            key
+        });
+        match &result {
+            Ok(_) => break,
+            Err(e) => warn!("{e}"),
+        }
+    }
+    let mut key = result?;
+    let result = map_luks(name, uuid_prefix, device, &header, &key);
+    key.fill(0);
+    result
+}
+
+/// Unlock a keyslot of the LUKS device `device` with `passphrase` and map it as `name`. The
+/// passphrase is used as is, like a `cryptsetup --key-file`. Other parameters are taken from
+/// `param_data` like for [`activate_luks`].
+///
+/// Returns the path of the new dm device.
+pub fn activate_luks_with_passphrase(
+    name: &str,
+    uuid_prefix: &str,
+    device: &str,
+    param_data: &str,
+    passphrase: &[u8],
+) -> Result<String> {
+    let params = LuksParams::from_string(param_data)?;
+    let header = params.header(device)?;
+    let mut key = unlock_keyslots(device, &header, &[], passphrase)?;
+    let result = map_luks(name, uuid_prefix, device, &header, &key);
+    key.fill(0);
+    result
+}
+
+fn map_luks(
+    name: &str,
+    uuid_prefix: &str,
+    device: &str,
+    header: &LuksHeader,
+    key: &[u8],
+) -> Result<String> {
+    if let Some(key_size) = header.key_size.filter(|&size| size != key.len()) {
+        return Err(format!(
+            "The volume key has {} bytes instead of {key_size}",
+            key.len()
+        )
+        .into());
+    }
+    map_crypt(name, uuid_prefix, device, header, 0, key, 0)
+}
+
+/// Map the data segment described by `header` of `device` as `name` with `key` and the dm
+/// `flags`. The table containing the key is overwritten once the device is created.
+fn map_crypt(
+    name: &str,
+    uuid_prefix: &str,
+    device: &str,
+    header: &LuksHeader,
+    iv_offset: u64,
+    key: &[u8],
+    flags: u32,
+) -> Result<String> {
+    let sectors = device_sectors(device)?
+        .checked_sub(header.offset)
+        .ok_or(format!("The encrypted data is outside of {device}"))?;
+
+    debug!(
+        "Configuring dm-crypt with cipher = {}, offset = {}",
+        header.cipher, header.offset
+    );
+    let uuid = DmIoctl::uuid(uuid_prefix, device);
+    let mut table = crypt_table(header, key, iv_offset, device);
+    let mut table_load_data = DmTableLoad::with_table(
+        "crypt",
+        sectors,
+        flags,
+        std::str::from_utf8(&table).unwrap(),
+        &uuid,
+    );
+    table.fill(0);
+
+    let result = create_device(name, &uuid, &mut table_load_data, |e| {
+        format!("Failed to load dm table: {e}")
+    });
+    table_load_data.params.fill(0);
+    result
+}
+
+/// Derive a key of `key_size` bytes from `passphrase` like cryptsetup in plain mode: The
+/// digests of the passphrase prefixed with 0, 1, 2, ... `A` characters are concatenated. With
+/// the hash `plain`, the zero padded passphrase is the key.
+fn plain_key(
+    passphrase: &[u8],
+    key_size: usize,
+    hash: &str,
+    digest: impl Fn(&str, &[u8]) -> Result<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    let mut key = Vec::with_capacity(key_size);
+    if hash == "plain" {
+        key.extend_from_slice(&passphrase[..passphrase.len().min(key_size)]);
+        key.resize(key_size, 0);
+        return Ok(key);
+    }
+
+    let mut input = Vec::with_capacity(passphrase.len() + key_size);
+    let mut pad = 0;
+    let result = loop {
+        if key.len() >= key_size {
+            break Ok(());
+        }
+        input.fill(0);
+        input.clear();
+        input.resize(pad, b'A');
+        input.extend_from_slice(passphrase);
+        let mut digest = match digest(hash, &input) {
+            Ok(digest) if !digest.is_empty() => digest,
+            Ok(_) => break Err(format!("Hash {hash} returned no digest").into()),
+            Err(e) => break Err(e),
+        };
+        let len = digest.len().min(key_size - key.len());
+        key.extend_from_slice(&digest[..len]);
+        digest.fill(0);
+        pad += 1;
+    };
+    input.fill(0);
+    match result {
+        Ok(()) => Ok(key),
+        Err(e) => {
+            key.fill(0);
+            Err(e)
+        }
+    }
+}
+
+/// Read the plain dm-crypt key from the raw key file or derive it from the passphrase file.
+fn plain_crypt_key(options: &PlainCryptOptions) -> Result<Vec<u8>> {
+    let key_size = options.key_size as usize / 8;
+    let (path, hashed) = match (&options.key_file, &options.passphrase_file) {
+        (Some(path), _) => (path, false),
+        (None, Some(path)) => (path, true),
+        (None, None) => return Err("No key file or passphrase file for plain dm-crypt".into()),
+    };
+    let mut data = read_limited(Path::new(path), MAX_KEY_FILE_SIZE)?;
+    let key = if hashed {
+        let passphrase = data.strip_suffix(b"\n").unwrap_or(&data);
+        plain_key(passphrase, key_size, &options.hash, |hash, data| {
+            digest(hash, &[data])
+        })
+    } else {
+        data.get(..key_size)
+            .map(<[u8]>::to_vec)
+            .ok_or(format!("{path} is shorter than {key_size} bytes").into())
+    };
+    data.fill(0);
+    key
+}
+
+/// Map `device` as `name` with plain dm-crypt, i.e. without LUKS header, with the cipher and
+/// key from `options`.
+///
+/// Returns the path of the new dm device.
+pub fn activate_plain_crypt(
+    name: &str,
+    uuid_prefix: &str,
+    device: &str,
+    options: &PlainCryptOptions,
+) -> Result<String> {
+    let header = LuksHeader {
+        cipher: options.cipher.clone(),
+        offset: options.offset,
+        sector_size: 512,
+        key_size: Some(options.key_size as usize / 8),
+        ..Default::default()
+    };
+    info!(
+        "Configuring plain dm-crypt device {name} for {device} with cipher = {}",
+        header.cipher
+    );
+    let mut key = plain_crypt_key(options)?;
+    let result = map_crypt(name, uuid_prefix, device, &header, options.skip, &key, 0);
+    key.fill(0);
+    result
+}
+
+fn prepare_plain_crypt_root(options: &mut CmdlineOptions) -> Result<bool> {
+    let device = match &options.plain_crypt.device {
+        Some(device) => resolve_device(device, options.rootwait)?,
+        None => return Ok(false),
+    };
+    wait_for_device(&device, options.rootwait)?;
+
+    let device = activate_plain_crypt(
+        DEFAULT_PLAIN_CRYPT_NAME,
+        DEFAULT_PLAIN_CRYPT_UUID_PREFIX,
+        &device,
+        &options.plain_crypt,
+    )?;
+    options.replace_root(device);
+
+    Ok(true)
+}
+
+/// Read the key file `path` from the filesystem on `device`, e.g. a USB stick. The device is
+/// mounted read-only while reading the file and unmounted again afterwards.
+fn read_keyfile(device: &str, path: &str, timeout: Option<Duration>) -> Result<Vec<u8>> {
+    info!("Waiting for the key device {device}");
+    let resolved = resolve_device(device, timeout)
+        .and_then(|resolved| wait_for_device(&resolved, timeout).map(|_| resolved))
+        .map_err(|e| format!("The key device {device} is missing: {e}"))?;
+
+    mount_regular(
+        Some(&resolved),
+        KEYFILE_MOUNTPOINT,
+        None,
+        MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        None,
+    )?;
+    let file = Path::new(KEYFILE_MOUNTPOINT).join(path.trim_start_matches('/'));
+    let passphrase = read_limited(&file, MAX_KEY_FILE_SIZE)
+        .map_err(|e| format!("Failed to read the key file on {device}: {e}"))
+        .and_then(|passphrase| {
+            if passphrase.is_empty() {
+                return Err(format!("The key file on {device} is empty"));
+            }
+            Ok(passphrase)
+        });
+    if let Err(e) = umount2(KEYFILE_MOUNTPOINT, MntFlags::empty()) {
+        warn!("Failed to unmount {KEYFILE_MOUNTPOINT}: {e}, detaching it");
+        umount2(KEYFILE_MOUNTPOINT, MntFlags::MNT_DETACH)
+            .map_err(|e| format!("Failed to detach {KEYFILE_MOUNTPOINT}: {e}"))?;
+    }
+    Ok(passphrase?)
+}
+
+fn prepare_luks_root(options: &mut CmdlineOptions) -> Result<bool> {
+    let device = match &options.luks {
+        Some(device) => resolve_device(device, options.rootwait)?,
+        None => return Ok(false),
+    };
+    wait_for_device(&device, options.rootwait)?;
+
+    let param_data = if Path::new("/luks-params").exists() {
+        read_file_limited("/luks-params", MAX_PARAMS_SIZE)?
+    } else {
+        String::new()
+    };
+    let device = match &options.luks_keyfile {
+        Some((key_device, path)) => {
+            let mut passphrase = read_keyfile(key_device, path, options.rootwait)?;
+            let result = activate_luks_with_passphrase(
+                DEFAULT_LUKS_NAME,
+                DEFAULT_LUKS_UUID_PREFIX,
+                &device,
+                &param_data,
+                &passphrase,
+            );
+            passphrase.fill(0);
+            result?
+        }
+        None => activate_luks(
+            DEFAULT_LUKS_NAME,
+            DEFAULT_LUKS_UUID_PREFIX,
+            &device,
+            &param_data,
+        )?,
+    };
+    options.replace_root(device);
+
+    Ok(true)
+}
+
+pub fn prepare_luks(options: &mut CmdlineOptions) -> Result<bool> {
+    prepare_luks_root(options)
+        .and_then(|have_root| Ok(have_root || prepare_plain_crypt_root(options)?))
+        .map_err(|e| e.or_kind(RsinitError::Luks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpm::hex;
+
+    const METADATA: &str = r#"{
+        "keyslots": {
+            "0": {"type": "luks2", "key_size": 64,
+                  "area": {"type": "raw", "offset": "32768", "size": "258048",
+                           "encryption": "aes-xts-plain64", "key_size": 64},
+                  "kdf": {"type": "argon2id", "time": 4, "memory": 1048576, "cpus": 4,
+                          "salt": "cnNpbml0LWtleXNsb3Qtc2FsdA=="},
+                  "af": {"type": "luks1", "stripes": 4000, "hash": "sha256"}},
+            "1": {"type": "luks2", "key_size": 64,
+                  "area": {"type": "raw", "offset": "290816", "size": "258048",
+                           "encryption": "aes-xts-plain64", "key_size": 64},
+                  "kdf": {"type": "pbkdf2", "hash": "sha512", "iterations": 1000,
+                          "salt": "cnNpbml0"},
+                  "af": {"type": "luks1", "stripes": 4000, "hash": "sha512"}}
+        },
+        "tokens": {
+            "0": {"type": "systemd-fido2", "keyslots": ["0"]},
+            "1": {"type": "systemd-tpm2", "keyslots": ["0"], "tpm2-blob": "AAKqu////w==",
+                  "tpm2-pcrs": [0, 7], "tpm2-pcr-bank": "sha256", "tpm2-primary-alg": "ecc",
+                  "tpm2-pin": false, "tpm2_srk": "AAE="},
+            "2": {"type": "systemd-tpm2", "keyslots": ["0"], "tpm2-blob": "AAKqu////w==",
+                  "tpm2-pcrs": [7], "tpm2-pin": true}
+        },
+        "segments": {
+            "0": {"type": "crypt", "offset": "16777216", "size": "dynamic", "iv_tweak": "0",
+                  "encryption": "aes-xts-plain64", "sector_size": 4096}
+        },
+        "digests": {
+            "0": {"type": "pbkdf2", "keyslots": ["0"], "segments": ["0"], "hash": "sha256",
+                  "iterations": 1000, "salt": "cnNpbml0LXNhbHQ=",
+                  "digest": "+EZMu9xO55zvPAQjW1fbmZXVhBuTYO0dt4p2HScDfU0="}
+        },
+        "config": {"json_size": "12288", "keyslots_size": "16744448"}
+    }"#;
+
+    #[test]
+    fn test_luks2() {
+        let mut header = b"LUKS\xba\xbe\x00\x02".to_vec();
+        header.resize(LUKS2_BINARY_HEADER_SIZE, 0);
+        header.extend_from_slice(METADATA.as_bytes());
+        header.resize(LUKS2_BINARY_HEADER_SIZE + 12288, 0);
+
+        let header = LuksHeader::parse(&header).unwrap();
+        assert_eq!(
+            header,
+            LuksHeader {
+                cipher: "aes-xts-plain64".into(),
+                offset: 32768,
+                sector_size: 4096,
+                key_size: Some(64),
+                digest: Some(KeyDigest {
+                    hash: "sha256".into(),
+                    iterations: 1000,
+                    salt: b"rsinit-salt".to_vec(),
+                    digest: decode_base64("+EZMu9xO55zvPAQjW1fbmZXVhBuTYO0dt4p2HScDfU0=").unwrap(),
+                }),
+                /* Keyslot 1 is not linked to the digest of the segment */
+                keyslots: vec![Keyslot {
+                    id: "0".into(),
+                    kdf: Kdf::Argon2 {
+                        algorithm: Algorithm::Argon2id,
+                        time: 4,
+                        memory: 1048576,
+                        cpus: 4,
+                    },
+                    salt: b"rsinit-keyslot-salt".to_vec(),
+                    cipher: "aes-xts-plain64".into(),
+                    cipher_key_size: 64,
+                    offset: 32768,
+                    af_hash: "sha256".into(),
+                    stripes: 4000,
+                    key_size: 64,
+                }],
+                /* Token 2 needs a PIN */
+                tokens: vec![SealedKey {
+                    blob: vec![0, 2, 0xaa, 0xbb, 0xff, 0xff, 0xff],
+                    srk: true,
+                    bank: "sha256".into(),
+                    pcrs: vec![0, 7],
+                    keyslots: vec!["0".into()],
+                    base64: true,
+                }],
+            }
+        );
+        assert_eq!(
+            crypt_table(&header, &[0x01, 0xab], 0, "/dev/sda2"),
+            b"aes-xts-plain64 01ab 0 /dev/sda2 32768 1 sector_size:4096"
+        );
+
+        let digest = header.digest.unwrap();
+        digest.verify(&[0x01, 0xab]).unwrap();
+        assert!(digest.verify(&[0x01, 0xac]).is_err());
+    }
+
+    #[test]
+    fn test_luks1() {
+        let mut header = b"LUKS\xba\xbe\x00\x01aes".to_vec();
+        header.resize(40, 0);
+        header.extend_from_slice(b"xts-plain64");
+        header.resize(72, 0);
+        header.extend_from_slice(b"sha256");
+        header.resize(104, 0);
+        header.extend_from_slice(&[0, 0, 0x10, 0, 0, 0, 0, 64]);
+        header.extend_from_slice(&[0xdd; 20]);
+        header.extend_from_slice(&[0xcc; 32]);
+        header.extend_from_slice(&1000u32.to_be_bytes());
+        header.resize(LUKS1_KEYSLOT_OFFSET + LUKS1_KEYSLOT_SIZE, 0);
+        header.extend_from_slice(&LUKS1_KEY_ENABLED.to_be_bytes());
+        header.extend_from_slice(&2000u32.to_be_bytes());
+        header.extend_from_slice(&[0xbb; 32]);
+        header.extend_from_slice(&8u32.to_be_bytes());
+        header.extend_from_slice(&4000u32.to_be_bytes());
+        header.resize(592, 0);
+
+        let header = LuksHeader::parse(&header).unwrap();
+        assert_eq!(header.cipher, "aes-xts-plain64");
+        assert_eq!(header.offset, 4096);
+        assert_eq!(header.key_size, Some(64));
+        assert_eq!(
+            header.digest,
+            Some(KeyDigest {
+                hash: "sha256".into(),
+                iterations: 1000,
+                salt: vec![0xcc; 32],
+                digest: vec![0xdd; 20],
+            })
+        );
+        assert_eq!(
+            header.keyslots,
+            [Keyslot {
+                id: "1".into(),
+                kdf: Kdf::Pbkdf2 {
+                    hash: "sha256".into(),
+                    iterations: 2000,
+                },
+                salt: vec![0xbb; 32],
+                cipher: "aes-xts-plain64".into(),
+                cipher_key_size: 64,
+                offset: 4096,
+                af_hash: "sha256".into(),
+                stripes: 4000,
+                key_size: 64,
+            }]
+        );
+        assert_eq!(
+            crypt_table(&header, &[0xff], 0, "/dev/mmcblk0p3"),
+            b"aes-xts-plain64 ff 0 /dev/mmcblk0p3 4096"
+        );
+
+        assert!(LuksHeader::parse(b"hsqs").is_err());
+        assert!(LuksHeader::parse(&b"LUKS\xba\xbe\x00\x01"[..]).is_err());
+    }
+
+    #[test]
+    fn test_derive_key() {
+        let mut keyslot = Keyslot {
+            id: "0".into(),
+            kdf: Kdf::Argon2 {
+                algorithm: Algorithm::Argon2id,
+                time: 2,
+                memory: 64,
+                cpus: 2,
+            },
+            salt: b"rsinit-keyslot-salt".to_vec(),
+            cipher: "aes-xts-plain64".into(),
+            cipher_key_size: 32,
+            offset: 32768,
+            af_hash: "sha256".into(),
+            stripes: 4000,
+            key_size: 64,
+        };
+        assert_eq!(
+            hex(&keyslot.derive_key(b"passphrase").unwrap()),
+            "20408e1db4455a8ce27c4b66542f9a4b0df679c12bd435ae3183b6133f4f4c8f"
+        );
+
+        /* RFC 6070 */
+        keyslot.kdf = Kdf::Pbkdf2 {
+            hash: "sha1".into(),
+            iterations: 2,
+        };
+        keyslot.salt = b"salt".to_vec();
+        keyslot.cipher_key_size = 20;
+        assert_eq!(
+            hex(&keyslot.derive_key(b"password").unwrap()),
+            "ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957"
+        );
+
+        keyslot.kdf = Kdf::Pbkdf2 {
+            hash: "md4".into(),
+            iterations: 2,
+        };
+        assert!(keyslot.derive_key(b"password").is_err());
+        keyslot.kdf = Kdf::Pbkdf2 {
+            hash: "sha1".into(),
+            iterations: 0,
+        };
+        assert!(keyslot.derive_key(b"password").is_err());
+    }
+
+    #[test]
+    fn test_af_merge() {
+        let material = (0..120).collect::<Vec<u8>>();
+        assert_eq!(
+            hex(&af_merge(&material, 40, 3, "sha256").unwrap()),
+            "512bb18e314d508ff96bf0476fe763960d7fee88a203f1a8f60786529f022801fa236647e64a8536"
+        );
+        assert_eq!(
+            hex(&af_merge(&material, 40, 3, "sha1").unwrap()),
+            "169d7f78640209a14fd711be19dd01b47f66219749ebee789b467493e1d737942025decab3de5166"
+        );
+        assert_eq!(
+            af_merge(&material, 40, 1, "sha256").unwrap(),
+            material[..40]
+        );
+        assert!(af_merge(&material, 40, 4, "sha256").is_err());
+        assert!(af_merge(&material, 40, 3, "md4").is_err());
+    }
+
+    #[test]
+    fn test_plain_key() {
+        let digest = |hash: &str, input: &[u8]| {
+            assert_eq!(hash, "sha256");
+            Ok(vec![input.len() as u8, input[0]])
+        };
+        assert_eq!(
+            plain_key(b"pw", 5, "sha256", digest).unwrap(),
+            [2, b'p', 3, b'A', 4]
+        );
+        assert_eq!(
+            plain_key(b"pw", 4, "plain", digest).unwrap(),
+            [b'p', b'w', 0, 0]
+        );
+        assert!(plain_key(b"pw", 4, "sha256", |_, _| Ok(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        let params = LuksParams::from_string(
+            "LUKS_TPM2_BLOB = /luks.tpm2\nLUKS_TPM2_PCRS=0+7\nLUKS_OFFSET=4096\n",
+        )
+        .unwrap();
+        assert_eq!(
+            params,
+            LuksParams {
+                tpm2_blob: Some("/luks.tpm2"),
+                tpm2_pcrs: Some("0+7"),
+                offset: Some(4096),
+                ..Default::default()
+            }
+        );
+        assert!(LuksParams::from_string("LUKS_OFFSET=x").is_err());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(
+            decode_base64("AAKqu////w==").unwrap(),
+            [0, 2, 0xaa, 0xbb, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(decode_base64("cnNpbml0").unwrap(), b"rsinit");
+        assert_eq!(Base64::encode_string(b"rsinit"), "cnNpbml0");
+        assert!(decode_base64("a-b").is_err());
+    }
+}
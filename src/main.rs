@@ -3,9 +3,11 @@
 
 extern crate rsinit;
 
-use rsinit::init::InitContext;
-use rsinit::util::Result;
-
-fn main() -> Result<()> {
-    InitContext::new()?.run_from_env()
+#[cfg(not(feature = "no_std"))]
+fn main() -> rsinit::util::Result<()> {
+    rsinit::init::InitContext::new()?.run_from_env()
 }
+
+/* The no_std feature only builds the cmdline parser of the library */
+#[cfg(feature = "no_std")]
+fn main() {}
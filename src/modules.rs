@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{read_dir, File};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use nix::errno::Errno;
+use nix::kmod::{finit_module, ModuleInitFlags};
+use nix::sys::utsname::uname;
+
+use crate::util::{dry_run, read_file, Result};
+
+/// Module names use '-' and '_' interchangeably, the kernel uses '_'.
+fn normalize(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Find all uncompressed modules below `dir`.
+fn find_modules(dir: &Path, modules: &mut HashMap<String, PathBuf>) -> Result<()> {
+    let entries = read_dir(dir).map_err(|e| format!("Failed to list {}: {e}", dir.display()))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_modules(&path, modules)?;
+        } else if let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".ko"))
+        {
+            modules.insert(normalize(name), path);
+        }
+    }
+    Ok(())
+}
+
+/// Parse the module names from `/etc/modules`, one per line. Empty lines and comments are
+/// ignored.
+fn parse_modules_file(data: &str) -> Vec<String> {
+    data.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter_map(|line| line.split_ascii_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Load the modules from `/etc/modules` followed by `names` in the given order. Modules that
+/// are already loaded are skipped.
+pub fn load_modules(names: &[String]) -> Result<()> {
+    let mut all_names = if Path::new("/etc/modules").exists() {
+        parse_modules_file(&read_file("/etc/modules")?)
+    } else {
+        Vec::new()
+    };
+    all_names.extend_from_slice(names);
+    if all_names.is_empty() {
+        return Ok(());
+    }
+
+    let kernel_release = uname()?.release().to_string_lossy().to_string();
+    let mut modules = HashMap::new();
+    find_modules(
+        Path::new(&format!("/lib/modules/{kernel_release}")),
+        &mut modules,
+    )?;
+
+    for name in all_names {
+        let name = normalize(&name);
+        if Path::new(&format!("/sys/module/{name}")).exists() {
+            debug!("Module {name} is already loaded");
+            continue;
+        }
+        let path = modules.get(&name).ok_or(format!(
+            "Module {name} not found for kernel {kernel_release}"
+        ))?;
+
+        if dry_run() {
+            info!("Would load module {}", path.display());
+            continue;
+        }
+        info!("Loading module {}", path.display());
+        let file =
+            File::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+        match finit_module(&file, &CString::default(), ModuleInitFlags::empty()) {
+            Ok(()) | Err(Errno::EEXIST) => (),
+            Err(e) => return Err(format!("Failed to load module {name}: {e}").into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_modules_file() {
+        let data = "
+# storage
+mmci
+ext4 # rootfs
+
+sdhci-pltfm
+";
+        assert_eq!(
+            parse_modules_file(data),
+            vec!["mmci", "ext4", "sdhci-pltfm"]
+        );
+        assert_eq!(normalize("sdhci-pltfm"), "sdhci_pltfm");
+    }
+}
@@ -1,16 +1,27 @@
 // SPDX-FileCopyrightText: 2024 The rsinit Authors
 // SPDX-License-Identifier: GPL-2.0-only
 
-use std::fs::{self, remove_dir};
+use std::fs::{self, read_dir, remove_dir, remove_file, symlink_metadata, File};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
 
-use log::{info, warn};
+use log::{debug, info, warn};
 use nix::{
+    errno::Errno,
     mount::{mount, umount, MsFlags},
+    sys::statfs::{statfs, TMPFS_MAGIC},
     sys::utsname::uname,
 };
 
-use crate::util::{mkdir, wait_for_device, Result};
+use crate::blkid::resolve_device;
+use crate::cmdline::{CmdlineOptions, FsckRepair};
+#[cfg(feature = "loop")]
+use crate::loopdev::mount_loop;
+use crate::util::{dry_run, mkdir, mkdir_p, read_file, wait_for_device, Result, RsinitError};
 
 pub fn do_mount(
     src: Option<&str>,
@@ -19,27 +30,123 @@ pub fn do_mount(
     flags: MsFlags,
     data: Option<&str>,
 ) -> Result<()> {
-    mkdir(dst)?;
+    if dry_run() {
+        info!(
+            "Would mount {} -> {} as '{}' with flags = {:#x}, data = '{}'",
+            src.unwrap_or_default(),
+            dst,
+            fstype.unwrap_or_default(),
+            flags.bits(),
+            data.unwrap_or_default(),
+        );
+        return Ok(());
+    }
+    mkdir_p(dst)?;
 
-    mount(src, dst, fstype, flags, data).map_err(|e| {
-        format!(
-            "Failed to mount {} -> {} as '{}' with flags = {:#x}, data = '{}'): {e}",
+    mount(src, dst, fstype, flags, data).map_err(|errno| RsinitError::Mount {
+        errno,
+        message: format!(
+            "Failed to mount {} -> {} as '{}' with flags = {:#x}, data = '{}'): {errno}",
             src.unwrap_or_default(),
             dst,
             fstype.unwrap_or_default(),
             flags.bits(),
             data.unwrap_or_default(),
-        )
+        ),
     })?;
 
     Ok(())
 }
 
+/// Whether the mount failed with an error that may go away when it is retried, e.g. for
+/// storage that is not quite ready yet.
+fn is_transient(e: &RsinitError) -> bool {
+    matches!(
+        e,
+        RsinitError::Mount {
+            errno: Errno::EIO | Errno::ENODEV | Errno::EBUSY,
+            ..
+        }
+    )
+}
+
+/// The mount flags that change the propagation type of a mount.
+pub const PROPAGATION_FLAGS: MsFlags = MsFlags::MS_SHARED
+    .union(MsFlags::MS_PRIVATE)
+    .union(MsFlags::MS_SLAVE)
+    .union(MsFlags::MS_UNBINDABLE);
+
+/// Change the propagation type of the mount at `dst` to one of [`PROPAGATION_FLAGS`], for all
+/// submounts as well with `MS_REC`. The kernel does not allow this together with other changes.
+pub fn set_propagation(dst: &str, flags: MsFlags) -> Result<()> {
+    if (flags & PROPAGATION_FLAGS).bits().count_ones() != 1
+        || !flags
+            .difference(PROPAGATION_FLAGS | MsFlags::MS_REC)
+            .is_empty()
+    {
+        return Err(format!("Invalid propagation flags {:#x}", flags.bits()).into());
+    }
+    do_mount(None, dst, None, flags, None)
+}
+
+/// Whether `path` is on a different filesystem than its parent directory. This works without
+/// /proc, so it can be used before /proc/self/mountinfo is available.
+fn is_mountpoint(path: &Path) -> bool {
+    let parent = path.parent().unwrap_or(path);
+    match (symlink_metadata(path), symlink_metadata(parent)) {
+        (Ok(metadata), Ok(parent)) => metadata.is_dir() && metadata.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+/// Mount the API filesystem `fstype` at `dst`. Nothing is done if something is already mounted
+/// there, e.g. if rsinit runs again after `rsinit.reexec` or in a test harness.
 pub fn mount_apivfs(dst: &str, fstype: &str, flags: MsFlags, data: Option<&str>) -> Result<()> {
+    if is_mountpoint(Path::new(dst)) {
+        debug!("{dst} is already mounted, not mounting {fstype}");
+        return Ok(());
+    }
     do_mount(Some(fstype), dst, Some(fstype), flags, data)?;
     Ok(())
 }
 
+/// Identify the filesystem from the magic numbers in its `superblock`, which must contain
+/// the start of the device.
+fn sniff_fstype(superblock: &[u8]) -> Option<&'static str> {
+    let magic =
+        |offset: usize, magic: &[u8]| superblock.get(offset..offset + magic.len()) == Some(magic);
+
+    if magic(0, b"hsqs") {
+        Some("squashfs")
+    } else if magic(0, b"XFSB") {
+        Some("xfs")
+    } else if magic(1024, &0xe0f5e1e2u32.to_le_bytes()) {
+        Some("erofs")
+    } else if magic(1024, &0xf2f52010u32.to_le_bytes()) {
+        Some("f2fs")
+    } else if magic(1080, &0xef53u16.to_le_bytes()) {
+        Some("ext4")
+    } else if magic(0x10040, b"_BHRfS_M") {
+        Some("btrfs")
+    } else if magic(510, &[0x55, 0xaa]) && (magic(0x36, b"FAT") || magic(0x52, b"FAT")) {
+        Some("vfat")
+    } else {
+        None
+    }
+}
+
+/// Detect the filesystem on `device` from its superblock.
+pub fn detect_fstype(device: &str) -> Option<&'static str> {
+    let mut superblock = Vec::new();
+    File::open(device)
+        .and_then(|file| file.take(0x10048).read_to_end(&mut superblock))
+        .map_err(|e| debug!("Failed to read the superblock of {device}: {e}"))
+        .ok()?;
+    sniff_fstype(&superblock)
+}
+
+/// Mount `src` at `dst`. Without `fstype`, the filesystem is detected from the superblock or
+/// each filesystem from /proc/filesystems is tried.
 pub fn mount_regular(
     src: Option<&str>,
     dst: &str,
@@ -48,46 +155,234 @@ pub fn mount_regular(
     data: Option<&str>,
 ) -> Result<()> {
     if fstype.is_some() {
-        do_mount(src, dst, fstype, flags, data)
-    } else {
-        let mut result = Ok(());
-        for fstype in ["ext4", "erofs", "squashfs", "f2fs", "btrfs"] {
-            result = do_mount(src, dst, Some(fstype), flags, data);
-            if result.is_ok() {
-                return Ok(());
-            }
+        return do_mount(src, dst, fstype, flags, data);
+    }
+    if let Some(fstype) = src.and_then(detect_fstype) {
+        info!("Detected {fstype} on {}", src.unwrap_or_default());
+        return do_mount(src, dst, Some(fstype), flags, data);
+    }
+
+    let filesystems = read_file("/proc/filesystems")?;
+    let mut result = Err(format!("No filesystem found for {}", src.unwrap_or_default()).into());
+    for fstype in filesystems
+        .lines()
+        .filter(|line| !line.starts_with("nodev"))
+        .map(str::trim)
+    {
+        result = do_mount(src, dst, Some(fstype), flags, data);
+        if result.is_ok() {
+            return Ok(());
         }
-        result
     }
+    result
+}
+
+/* fsck exit code bits that indicate a failure */
+const FSCK_ERRORS_UNCORRECTED: i32 = 4;
+const FSCK_OPERATIONAL_ERROR: i32 = 8;
+const FSCK_USAGE_ERROR: i32 = 16;
+
+/// Check the filesystem on `device` with `/sbin/fsck.<fstype>` or `/sbin/fsck` once the device
+/// exists.
+///
+/// Network and RAM filesystems are skipped. Corrected errors are not fatal.
+pub fn fsck(
+    device: &str,
+    fstype: Option<&str>,
+    force: bool,
+    repair: FsckRepair,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    if let Some("nfs") | Some("9p") | Some("tmpfs") | Some("ramfs") = fstype {
+        return Ok(());
+    }
+    wait_for_device(device, timeout)?;
+
+    let fsck = fstype
+        .map(|fstype| format!("/sbin/fsck.{fstype}"))
+        .filter(|fsck| Path::new(fsck).exists())
+        .unwrap_or("/sbin/fsck".to_string());
+    if !Path::new(&fsck).exists() {
+        warn!("Can't check {device} as {fsck} doesn't exist. Continue without checking.");
+        return Ok(());
+    }
+
+    /* A dry run only checks the filesystem without changing it */
+    let repair = if dry_run() { FsckRepair::No } else { repair };
+    let mut cmd = Command::new(&fsck);
+    cmd.arg(match repair {
+        FsckRepair::Preen => "-a",
+        FsckRepair::Yes => "-y",
+        FsckRepair::No => "-n",
+    });
+    if force {
+        cmd.arg("-f");
+    }
+    cmd.arg(device);
+
+    info!("Checking {device} with {fsck}");
+    let status = cmd
+        .status()
+        .map_err(|e| format!("Failed to run {fsck}: {e}"))?;
+    let code = status
+        .code()
+        .ok_or(format!("{fsck} was terminated by a signal"))?;
+
+    if code & (FSCK_ERRORS_UNCORRECTED | FSCK_OPERATIONAL_ERROR | FSCK_USAGE_ERROR) != 0 {
+        return Err(format!("{fsck} failed for {device} with exit code {code}").into());
+    }
+    if code != 0 {
+        warn!("{fsck} corrected errors on {device}");
+    }
+
+    Ok(())
 }
 
+/// Mount the rootfs at /root. Returns how often mounting was retried after transient errors.
 pub fn mount_root(
     device: Option<&str>,
     fstype: Option<&str>,
     fsflags: MsFlags,
     flags: Option<&str>,
-) -> Result<()> {
-    let root = device.as_ref().ok_or("root= not found in /proc/cmdline")?;
-
-    match fstype {
-        Some("nfs") | Some("9p") => (),
-        _ => wait_for_device(root)?,
+    timeout: Option<Duration>,
+    retries: u32,
+) -> Result<u32> {
+    if let Some("tmpfs") | Some("ramfs") = fstype {
+        info!(
+            "Mounting {} as rootfs with flags = {:#x}, data = '{}'",
+            fstype.unwrap_or_default(),
+            fsflags.bits(),
+            flags.unwrap_or_default()
+        );
+        return do_mount(None, "/root", fstype, fsflags, flags).map(|()| 0);
     }
-    mkdir("/root")?;
+
+    let root = device.ok_or("root= not found in /proc/cmdline")?;
+
+    /* Mount the device a symlink like /dev/disk/by-label/root points to */
+    let root = match fstype {
+        Some("nfs") | Some("9p") => root.to_string(),
+        _ => wait_for_device(root, timeout)?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    mkdir_p("/root")?;
 
     info!(
-        "Mounting rootfs {} -> /root as '{}' with flags = {:#x}, data = '{}'",
-        device.ok_or("No root device argument")?,
+        "Mounting rootfs {root} -> /root as '{}' with flags = {:#x}, data = '{}'",
         fstype.unwrap_or_default(),
         fsflags.bits(),
         flags.unwrap_or_default()
     );
-    mount_regular(device, "/root", fstype, fsflags, flags)?;
+    let mut attempt = 0;
+    loop {
+        let result = if fs::metadata(&root).is_ok_and(|m| m.is_file()) {
+            #[cfg(feature = "loop")]
+            let result = mount_loop(&root, "/root", fstype, fsflags, flags);
+            #[cfg(not(feature = "loop"))]
+            let result = Err(format!("Mounting the image {root} requires the loop feature").into());
+            result
+        } else {
+            mount_regular(Some(&root), "/root", fstype, fsflags, flags)
+        };
+        match result {
+            Err(e) if attempt < retries && is_transient(&e) => {
+                attempt += 1;
+                warn!("{e}, retrying ({attempt}/{retries})");
+                thread::sleep(Duration::from_millis(100 * u64::from(attempt)));
+            }
+            result => return result.map(|()| attempt),
+        }
+    }
+}
 
-    Ok(())
+/// How long to wait for each root device but the last one from `root=` with `rootwait`.
+pub const ROOT_FALLBACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The rootfs mounted by [`mount_root_from_options`].
+#[derive(Debug, Default, PartialEq)]
+pub struct MountedRoot {
+    /// The resolved root device that was mounted.
+    pub root: Option<String>,
+    /// The filesystem type, detected from the superblock without `rootfstype=`.
+    pub fstype: Option<String>,
+    /// How often mounting was retried after transient errors.
+    pub retries: u32,
+}
+
+/// Mount the rootfs from `options` at /root: Resolve and wait for the root device, detect the
+/// filesystem, check it with `rsinit.fsck` and mount it. With fallbacks in `root=`, each device
+/// is tried in order until one can be mounted. With `rootwait`, only the last device is waited
+/// for forever and the others for [`ROOT_FALLBACK_TIMEOUT`].
+///
+/// Unlike [`crate::init::InitContext::mount_root`], this does not set up the network first, so
+/// it can be used without the rest of the boot, e.g. by a recovery tool.
+pub fn mount_root_from_options(options: &CmdlineOptions) -> Result<MountedRoot> {
+    if options.root_fallbacks.is_empty() {
+        return mount_root_device(options, options.root.as_deref(), options.rootwait);
+    }
+    let last = options.root_fallbacks.last();
+    for candidate in options.root.iter().chain(&options.root_fallbacks) {
+        let timeout = match options.rootwait {
+            None if Some(candidate) != last => Some(ROOT_FALLBACK_TIMEOUT),
+            timeout => timeout,
+        };
+        match mount_root_device(options, Some(candidate), timeout) {
+            Ok(mounted) => {
+                info!("Mounted the rootfs from {candidate}");
+                return Ok(mounted);
+            }
+            Err(e) => warn!("{e}, trying the next root device"),
+        }
+    }
+    Err("Failed to mount any of the root devices".into())
+}
+
+fn mount_root_device(
+    options: &CmdlineOptions,
+    root: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<MountedRoot> {
+    let root = root.map(|root| resolve_device(root, timeout)).transpose()?;
+
+    let mut fstype = options.rootfstype.clone();
+    if let (None, Some(root)) = (&fstype, root.as_deref()) {
+        wait_for_device(root, timeout)?;
+        fstype = detect_fstype(root).map(str::to_string);
+        if let Some(fstype) = &fstype {
+            info!("Detected {fstype} on {root}");
+        }
+    }
+
+    if let (true, Some(root)) = (options.fsck, root.as_deref()) {
+        fsck(
+            root,
+            fstype.as_deref(),
+            options.fsck_force,
+            options.fsck_repair,
+            timeout,
+        )?;
+    }
+    let retries = mount_root(
+        root.as_deref(),
+        fstype.as_deref(),
+        options.rootfsflags,
+        options.rootflags.as_deref(),
+        timeout,
+        options.root_retries,
+    )?;
+    Ok(MountedRoot {
+        root,
+        fstype,
+        retries,
+    })
 }
 
 fn mount_move(src: &str, dst: &str, cleanup: bool) -> Result<()> {
+    if dry_run() {
+        info!("Would move mount {src} -> {dst}");
+        return Ok(());
+    }
     mount(
         Some(Path::new(src)),
         dst,
@@ -111,6 +406,29 @@ pub fn mount_special() -> Result<()> {
         MsFlags::MS_NOSUID | MsFlags::MS_STRICTATIME,
         Some("mode=0755,size=4m"),
     )?;
+    /*
+     * For shells and hooks in the initramfs. They are moved to the rootfs along with /dev.
+     * Logging is not available yet, so failures are ignored silently. Both are not needed to
+     * mount the rootfs.
+     */
+    for (dst, fstype, flags, data) in [
+        (
+            "/dev/pts",
+            "devpts",
+            MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            "gid=5,mode=620,ptmxmode=666",
+        ),
+        (
+            "/dev/shm",
+            "tmpfs",
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            "mode=1777",
+        ),
+    ] {
+        if mkdir(dst).is_ok() {
+            let _ = mount_apivfs(dst, fstype, flags, Some(data));
+        }
+    }
     mount_apivfs(
         "/sys",
         "sysfs",
@@ -126,6 +444,43 @@ pub fn mount_special() -> Result<()> {
     Ok(())
 }
 
+/// Mount the cgroup2 hierarchy at /sys/fs/cgroup or, with `legacy`, a tmpfs with one cgroup v1
+/// hierarchy for each enabled controller. The mounts are moved to the rootfs along with /sys.
+pub fn mount_cgroup(legacy: bool) -> Result<()> {
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC | MsFlags::MS_NODEV;
+    if !legacy {
+        return mount_apivfs("/sys/fs/cgroup", "cgroup2", flags, None);
+    }
+
+    mount_apivfs("/sys/fs/cgroup", "tmpfs", flags, Some("mode=0755"))?;
+    /* The first column of /proc/cgroups is the controller name, the last one whether it is
+     * enabled */
+    let cgroups = read_file("/proc/cgroups")?;
+    for line in cgroups.lines().filter(|line| !line.starts_with('#')) {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if let (Some(controller), Some(&"1")) = (fields.first(), fields.last()) {
+            do_mount(
+                Some("cgroup"),
+                &format!("/sys/fs/cgroup/{controller}"),
+                Some("cgroup"),
+                flags,
+                Some(controller),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `/` is the initial rootfs of the kernel according to `mountinfo`, the content of
+/// /proc/self/mountinfo. The kernel refuses to pivot_root() away from it.
+pub fn is_initial_rootfs(mountinfo: &str) -> bool {
+    mountinfo
+        .lines()
+        .rfind(|line| line.split(' ').nth(4) == Some("/"))
+        .and_then(|line| line.split_once(" - "))
+        .is_some_and(|(_, fs)| fs.starts_with("rootfs "))
+}
+
 pub fn mount_move_special(cleanup: bool) -> Result<()> {
     mount_move("/dev", "/root/dev", cleanup)?;
     mount_move("/sys", "/root/sys", cleanup)?;
@@ -133,6 +488,90 @@ pub fn mount_move_special(cleanup: bool) -> Result<()> {
     Ok(())
 }
 
+const RAMFS_MAGIC: u32 = 0x858458f6;
+
+/// Delete the files of the initramfs to free its memory, like `switch_root` from util-linux.
+/// Other filesystems, like the rootfs at /root, are not touched.
+pub fn delete_initramfs() -> Result<()> {
+    let fs = statfs("/").map_err(|e| format!("Failed to stat the initramfs: {e}"))?;
+    #[allow(clippy::unnecessary_cast)]
+    if fs.filesystem_type() != TMPFS_MAGIC && fs.filesystem_type().0 as u32 != RAMFS_MAGIC {
+        return Err("The initramfs is neither a ramfs nor a tmpfs, not deleting it".into());
+    }
+    if dry_run() {
+        info!("Would delete the initramfs");
+        return Ok(());
+    }
+    let dev = symlink_metadata("/")?.dev();
+    delete_tree(Path::new("/"), dev, Path::new("/root"));
+    Ok(())
+}
+
+/* Delete everything below `dir` on the device `dev` except `keep`. Mountpoints are skipped
+ * because they are on a different device. Errors are ignored, deleting is best effort. */
+fn delete_tree(dir: &Path, dev: u64, keep: &Path) {
+    let Ok(entries) = read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.dev() != dev || path == keep {
+            continue;
+        }
+        if metadata.is_dir() {
+            delete_tree(&path, dev, keep);
+            let _ = remove_dir(&path);
+        } else {
+            let _ = remove_file(&path);
+        }
+    }
+}
+
+/// Mount the tmpfs at `/root/run` that most inits expect to be writable early. Skipped if the
+/// rootfs has no `/run` directory or something is already mounted there.
+pub fn mount_run() -> Result<()> {
+    let run = Path::new("/root/run");
+    if !symlink_metadata(run).is_ok_and(|run| run.is_dir()) || is_mountpoint(run) {
+        debug!("/run in the rootfs is missing or already mounted, not mounting a tmpfs");
+        return Ok(());
+    }
+    do_mount(
+        Option::<&str>::None,
+        "/root/run",
+        Some("tmpfs"),
+        MsFlags::MS_NODEV
+            .union(MsFlags::MS_NOSUID)
+            .union(MsFlags::MS_STRICTATIME),
+        Some("mode=0755"),
+    )
+}
+
+/// The size of the tmpfs at `/tmp` for `rsinit.tmp` without a value.
+pub const DEFAULT_TMP_SIZE: &str = "50%";
+
+/// Mount a tmpfs of `size` at `/root/tmp` for inits that expect it. Skipped if the rootfs has
+/// no `/tmp` or something is already mounted there.
+pub fn mount_tmp(size: &str) -> Result<()> {
+    let (Ok(tmp), Ok(root)) = (symlink_metadata("/root/tmp"), symlink_metadata("/root")) else {
+        debug!("No /tmp in the rootfs, not mounting a tmpfs");
+        return Ok(());
+    };
+    if !tmp.is_dir() || tmp.dev() != root.dev() {
+        debug!("/tmp in the rootfs is no directory or already mounted, not mounting a tmpfs");
+        return Ok(());
+    }
+    do_mount(
+        Option::<&str>::None,
+        "/root/tmp",
+        Some("tmpfs"),
+        MsFlags::MS_NODEV | MsFlags::MS_NOSUID,
+        Some(format!("mode=1777,size={size}").as_str()),
+    )
+}
+
 pub fn mount_overlay(
     flags: MsFlags,
     data: Option<&str>,
@@ -187,6 +626,9 @@ pub fn mount_tmpfs_overlay(
         mountpoint,
         name,
     )?;
+    if dry_run() {
+        return Ok(());
+    }
     umount(dir).map_err(|e| format!("Failed to unmount {dir}: {e}"))?;
     remove_dir(dir)?;
 
@@ -239,3 +681,143 @@ pub fn mount_bind_kernel_modules() -> Result<()> {
         None,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_mountpoint() {
+        let dir = std::env::temp_dir().join(format!("rsinit-mountpoint-{}", std::process::id()));
+        fs::create_dir(&dir).expect("create_dir failed");
+        assert!(!is_mountpoint(&dir));
+        let _ = remove_dir(&dir);
+
+        assert!(!is_mountpoint(Path::new("/")));
+        assert!(!is_mountpoint(Path::new("/nonexistent")));
+        if Path::new("/proc/self").exists() {
+            assert!(is_mountpoint(Path::new("/proc")));
+        }
+    }
+
+    fn superblock(offset: usize, magic: &[u8]) -> Vec<u8> {
+        let mut superblock = vec![0; 0x10048];
+        superblock[offset..offset + magic.len()].copy_from_slice(magic);
+        superblock
+    }
+
+    #[test]
+    fn test_sniff_fstype() {
+        assert_eq!(sniff_fstype(&superblock(0, b"hsqs")), Some("squashfs"));
+        assert_eq!(sniff_fstype(&superblock(0, b"XFSB")), Some("xfs"));
+        assert_eq!(
+            sniff_fstype(&superblock(1024, &[0xe2, 0xe1, 0xf5, 0xe0])),
+            Some("erofs")
+        );
+        assert_eq!(
+            sniff_fstype(&superblock(1024, &[0x10, 0x20, 0xf5, 0xf2])),
+            Some("f2fs")
+        );
+        assert_eq!(sniff_fstype(&superblock(1080, &[0x53, 0xef])), Some("ext4"));
+        assert_eq!(
+            sniff_fstype(&superblock(0x10040, b"_BHRfS_M")),
+            Some("btrfs")
+        );
+
+        let mut vfat = superblock(510, &[0x55, 0xaa]);
+        assert_eq!(sniff_fstype(&vfat), None);
+        vfat[0x52..0x57].copy_from_slice(b"FAT32");
+        assert_eq!(sniff_fstype(&vfat), Some("vfat"));
+
+        assert_eq!(sniff_fstype(&[0; 4096]), None);
+        assert_eq!(sniff_fstype(b"hsq"), None);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let error = |errno| RsinitError::Mount {
+            errno,
+            message: String::new(),
+        };
+        assert!(is_transient(&error(Errno::EIO)));
+        assert!(is_transient(&error(Errno::EBUSY)));
+        assert!(!is_transient(&error(Errno::EINVAL)));
+        assert!(!is_transient(&RsinitError::Errno(Errno::EIO)));
+    }
+
+    #[test]
+    fn test_mount_root_from_options() {
+        let options = CmdlineOptions {
+            root: Some("/nonexistent/rsinit-root".into()),
+            root_fallbacks: vec!["/nonexistent/rsinit-fallback".into()],
+            rootfstype: Some("ext4".into()),
+            rootwait: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let error = mount_root_from_options(&options).expect_err("mounting succeeded");
+        assert_eq!(error.to_string(), "Failed to mount any of the root devices");
+
+        let options = CmdlineOptions {
+            root_fallbacks: Vec::new(),
+            ..options
+        };
+        let error = mount_root_from_options(&options).expect_err("mounting succeeded");
+        assert!(error.to_string().contains("/nonexistent/rsinit-root"));
+        assert!(!Path::new("/nonexistent").exists());
+    }
+
+    #[test]
+    fn test_is_initial_rootfs() {
+        let rootfs = "1 1 0:2 / / rw - rootfs rootfs rw,size=1000k\n\
+                      20 1 0:19 / /proc rw,nosuid - proc proc rw\n";
+        assert!(is_initial_rootfs(rootfs));
+        assert!(!is_initial_rootfs(&format!(
+            "{rootfs}30 1 0:25 / / rw - tmpfs none rw\n"
+        )));
+        assert!(!is_initial_rootfs(
+            "25 1 8:2 / / ro,relatime - ext4 /dev/sda2 ro\n"
+        ));
+        assert!(!is_initial_rootfs(""));
+    }
+
+    #[test]
+    fn test_dry_run() {
+        crate::util::set_dry_run(true);
+        let result = do_mount(
+            Some("/dev/rsinit-test"),
+            "/nonexistent/rsinit-test",
+            Some("ext4"),
+            MsFlags::MS_RDONLY,
+            None,
+        );
+        let moved = mount_move("/nonexistent/rsinit-test", "/nonexistent/rsinit-dst", true);
+        crate::util::set_dry_run(false);
+
+        assert!(result.is_ok());
+        assert!(moved.is_ok());
+        assert!(!Path::new("/nonexistent").exists());
+    }
+
+    #[test]
+    fn test_delete_tree() {
+        let dir = std::env::temp_dir().join(format!("rsinit-delete-{}", std::process::id()));
+        let keep = dir.join("root");
+        fs::create_dir_all(dir.join("lib/modules")).unwrap();
+        fs::create_dir_all(keep.join("etc")).unwrap();
+        fs::write(dir.join("lib/modules/test.ko"), b"").unwrap();
+        fs::write(dir.join("init"), b"").unwrap();
+        std::os::unix::fs::symlink("/nonexistent", dir.join("link")).unwrap();
+
+        let dev = symlink_metadata(&dir).unwrap().dev();
+        delete_tree(&dir, dev, &keep);
+
+        let left: Vec<_> = read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(left, [keep.as_path()]);
+        assert!(keep.join("etc").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
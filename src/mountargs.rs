@@ -0,0 +1,532 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::net::{IpAddr, ToSocketAddrs};
+
+use log::{error, info};
+use nix::mount::MsFlags;
+
+use crate::cmdline::ensure_value;
+use crate::mount::{do_mount, set_propagation, PROPAGATION_FLAGS};
+use crate::util::{dry_run, ensure_resolv_conf, Result};
+
+/// A bind mount from `rsinit.bind=` or an NFS mount from `rsinit.nfs=`.
+#[derive(Debug, PartialEq)]
+pub struct MountOption {
+    pub source: String,
+    pub destination: String,
+    /// The NFS mount options.
+    pub options: String,
+    /// The bind mount flags.
+    pub flags: MsFlags,
+}
+
+/// An arbitrary mount from `rsinit.mount=`. Empty fields on the cmdline are `None`.
+#[derive(Debug, PartialEq)]
+pub struct GenericMount {
+    pub source: Option<String>,
+    pub destination: String,
+    pub fstype: Option<String>,
+    pub flags: MsFlags,
+    pub data: Option<String>,
+}
+
+/// The additional mounts from `rsinit.bind=`, `rsinit.nfs=` and `rsinit.mount=`.
+///
+/// [`MountArgs::parse_cmdline`] is meant to be used as cmdline callback and
+/// [`MountArgs::do_mounts`] as [`crate::init::CallBack::PostRootMount`] callback.
+#[derive(Debug, Default)]
+pub struct MountArgs {
+    pub bind: Vec<MountOption>,
+    pub nfs: Vec<MountOption>,
+    pub mount: Vec<GenericMount>,
+}
+
+impl MountArgs {
+    /// Record the mount for a `rsinit.bind=`, `rsinit.nfs=` or `rsinit.mount=` option. Other
+    /// options are ignored.
+    pub fn parse_cmdline(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        match key {
+            "rsinit.bind" => {
+                let val = ensure_value(key, value)?;
+
+                let (src, rest) = val.split_once(',').ok_or(format!(
+                    "Bind mount option must be in the format '<source>,<destination>[,<options>]', got: {val}"
+                ))?;
+                let (dst, options) = rest.split_once(',').unwrap_or((rest, ""));
+
+                self.bind.push(MountOption {
+                    source: src.to_string(),
+                    destination: dst.to_string(),
+                    options: String::new(),
+                    flags: parse_bind_flags(options)?,
+                });
+            }
+            "rsinit.nfs" => {
+                let val = ensure_value(key, value)?;
+
+                let (src, rest) = val.split_once(',').ok_or(format!(
+                    "NFS mount option must be in the format '<host>:<source>,<destination>[,<options>]', got: {val}"
+                ))?;
+                let (dst, options) = rest.split_once(',').unwrap_or((rest, ""));
+
+                if !src.contains(':') {
+                    return Err("NFS source must be in the format '<host>:<path>'".into());
+                }
+
+                /* The host is resolved when mounting, the network is not set up yet */
+                self.nfs.push(MountOption {
+                    source: src.to_string(),
+                    destination: dst.to_string(),
+                    options: options.to_string(),
+                    flags: MsFlags::empty(),
+                });
+            }
+            "rsinit.mount" => {
+                let val = ensure_value(key, value)?;
+
+                let mut fields = val.splitn(5, ',');
+                let field = |field: Option<&str>| {
+                    field.filter(|field| !field.is_empty()).map(str::to_string)
+                };
+                let source = field(fields.next());
+                let destination = field(fields.next()).ok_or(format!(
+                    "Mount option must be in the format '<source>,<destination>[,<fstype>[,<flags>[,<data>]]]', got: {val}"
+                ))?;
+                let fstype = field(fields.next());
+                let flags = parse_mount_flags(fields.next().unwrap_or_default())?;
+                let data = field(fields.next());
+
+                self.mount.push(GenericMount {
+                    source,
+                    destination,
+                    fstype,
+                    flags,
+                    data,
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Mount the NFS, bind and generic mounts in this order.
+    pub fn do_mounts(&self) -> Result<()> {
+        for MountOption {
+            source,
+            destination,
+            options,
+            flags: _,
+        } in &self.nfs
+        {
+            let (source, options) = if dry_run() {
+                (source.clone(), options.clone())
+            } else {
+                resolve_nfs(source, options)?
+            };
+            info!("NFS mounting {source} to {destination} with options {options}");
+
+            let ret = do_mount(
+                Some(&source),
+                destination,
+                Some("nfs"),
+                MsFlags::empty(),
+                Some(&options),
+            );
+
+            if ret.is_err() {
+                error!("NFS mounting {source} to {destination} failed!");
+                error!("In case of ENETUNREACH or ENETDOWN ensure that an IP address is assigned to the network interface.");
+                error!("Via DHCP this can be done by adding 'ip=:::::<interface>:dhcp' e.g. 'ip=:::::eth0:dhcp' to the kernel command-line.");
+                error!("In case of EHOSTUNREACH check dhcp configuration and that your firewall allows nfs, rpcbind and mountd.");
+                error!("Good luck next time!");
+            };
+            ret?
+        }
+
+        for MountOption {
+            source,
+            destination,
+            options: _,
+            flags,
+        } in &self.bind
+        {
+            info!("Bind mounting {source} to {destination}");
+
+            let recursive = *flags & MsFlags::MS_REC;
+            let propagation = *flags & PROPAGATION_FLAGS;
+            let bind = MsFlags::MS_BIND | recursive;
+            do_mount(Some(source), destination, None, bind, None)?;
+
+            // The kernel ignores the other flags for the initial bind mount
+            let remount = flags.difference(MsFlags::MS_REC | PROPAGATION_FLAGS);
+            if !remount.is_empty() {
+                do_mount(
+                    None,
+                    destination,
+                    None,
+                    MsFlags::MS_BIND | MsFlags::MS_REMOUNT | recursive | remount,
+                    None,
+                )?;
+            }
+            if !propagation.is_empty() {
+                set_propagation(destination, propagation | recursive)?;
+            }
+        }
+
+        for GenericMount {
+            source,
+            destination,
+            fstype,
+            flags,
+            data,
+        } in &self.mount
+        {
+            info!(
+                "Mounting {} to {destination} as '{}' with data '{}'",
+                source.as_deref().unwrap_or_default(),
+                fstype.as_deref().unwrap_or_default(),
+                data.as_deref().unwrap_or_default()
+            );
+
+            // Changing the propagation is a separate mount call, which is all that is needed
+            // without a source or filesystem type
+            let propagation = *flags & PROPAGATION_FLAGS;
+            if source.is_some() || fstype.is_some() || propagation.is_empty() {
+                do_mount(
+                    source.as_deref(),
+                    destination,
+                    fstype.as_deref(),
+                    flags.difference(PROPAGATION_FLAGS),
+                    data.as_deref(),
+                )?;
+            }
+            if !propagation.is_empty() {
+                set_propagation(destination, propagation | (*flags & MsFlags::MS_REC))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Translate the colon separated `rsinit.mount` flags to mount flags.
+fn parse_mount_flags(flags: &str) -> Result<MsFlags> {
+    let mut result = MsFlags::empty();
+    for flag in flags.split(':').filter(|flag| !flag.is_empty()) {
+        result |= match flag {
+            "ro" => MsFlags::MS_RDONLY,
+            "rw" => MsFlags::empty(),
+            "nosuid" => MsFlags::MS_NOSUID,
+            "nodev" => MsFlags::MS_NODEV,
+            "noexec" => MsFlags::MS_NOEXEC,
+            "sync" => MsFlags::MS_SYNCHRONOUS,
+            "dirsync" => MsFlags::MS_DIRSYNC,
+            "noatime" => MsFlags::MS_NOATIME,
+            "nodiratime" => MsFlags::MS_NODIRATIME,
+            "relatime" => MsFlags::MS_RELATIME,
+            "bind" => MsFlags::MS_BIND,
+            "rbind" => MsFlags::MS_BIND | MsFlags::MS_REC,
+            "rec" => MsFlags::MS_REC,
+            flag => parse_propagation(flag).ok_or(format!("Unsupported mount flag '{flag}'"))?,
+        };
+    }
+    Ok(result)
+}
+
+/// Translate the comma or colon separated `rsinit.bind` options to mount flags.
+fn parse_bind_flags(options: &str) -> Result<MsFlags> {
+    let mut flags = MsFlags::empty();
+    for option in options
+        .split([',', ':'])
+        .filter(|option| !option.is_empty())
+    {
+        flags |= match option {
+            "ro" => MsFlags::MS_RDONLY,
+            "nosuid" => MsFlags::MS_NOSUID,
+            "nodev" => MsFlags::MS_NODEV,
+            "rec" | "rbind" => MsFlags::MS_REC,
+            option => parse_propagation(option)
+                .ok_or(format!("Unsupported bind mount option '{option}'"))?,
+        };
+    }
+    Ok(flags)
+}
+
+/// Translate a propagation type like `private` or the recursive `rprivate` to mount flags.
+fn parse_propagation(option: &str) -> Option<MsFlags> {
+    let (recursive, name) = match option.strip_prefix('r') {
+        Some(name @ ("shared" | "private" | "slave" | "unbindable")) => (MsFlags::MS_REC, name),
+        _ => (MsFlags::empty(), option),
+    };
+    let flag = match name {
+        "shared" => MsFlags::MS_SHARED,
+        "private" => MsFlags::MS_PRIVATE,
+        "slave" => MsFlags::MS_SLAVE,
+        "unbindable" => MsFlags::MS_UNBINDABLE,
+        _ => return None,
+    };
+    Some(flag | recursive)
+}
+
+/// Combine the NFS mount options given by the user with `addr=` and the NFSv3 defaults that
+/// the user did not override.
+fn nfs_options(addr: IpAddr, options: &str) -> String {
+    let options = options
+        .split(',')
+        .filter(|option| !option.is_empty() && !option.starts_with("addr="))
+        .collect::<Vec<_>>();
+    let overridden = |key: &str| {
+        options.iter().any(|option| {
+            let name = option.split_once('=').map_or(*option, |(name, _)| name);
+            match key {
+                "vers" => matches!(name, "vers" | "nfsvers"),
+                "nolock" => matches!(name, "lock" | "nolock" | "local_lock"),
+                _ => name == key,
+            }
+        })
+    };
+
+    let mut result = vec![format!("addr={addr}")];
+    for (key, default) in [
+        ("vers", "vers=3"),
+        ("proto", "proto=tcp"),
+        ("nolock", "nolock"),
+    ] {
+        if !overridden(key) {
+            result.push(default.to_string());
+        }
+    }
+    result.extend(options.iter().map(|option| option.to_string()));
+    result.join(",")
+}
+
+fn resolve_host(host: &str) -> Result<IpAddr> {
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(addr);
+    }
+
+    ensure_resolv_conf()?;
+
+    (host, 0)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve NFS host '{host}': {e}"))?
+        .next()
+        .map(|addr| addr.ip())
+        .ok_or(format!("Resolving NFS host '{host}' returned no addresses").into())
+}
+
+/// Resolve the host of the NFS `source` and add `addr=` and the defaults to the `options`, the
+/// kernel only accepts addresses.
+fn resolve_nfs(source: &str, options: &str) -> Result<(String, String)> {
+    let (host, path) = source
+        .split_once(':')
+        .ok_or("NFS source must be in the format '<host>:<path>'")?;
+
+    let addr = resolve_host(host)?;
+    let source = match addr {
+        IpAddr::V4(_) => format!("{addr}:{path}"),
+        IpAddr::V6(_) => format!("[{addr}]:{path}"),
+    };
+    Ok((source, nfs_options(addr, options)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bind_args() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline("rsinit.bind", Some("/lib/modules,/root/lib/modules"))
+            .unwrap();
+
+        assert_eq!(
+            args.bind,
+            &[MountOption {
+                source: "/lib/modules".to_string(),
+                destination: "/root/lib/modules".to_string(),
+                options: String::new(),
+                flags: MsFlags::empty(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_bind_args_flags() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline("rsinit.bind", Some("/data,/root/data,ro:nodev,rec"))
+            .unwrap();
+
+        assert_eq!(
+            args.bind,
+            &[MountOption {
+                source: "/data".to_string(),
+                destination: "/root/data".to_string(),
+                options: String::new(),
+                flags: MsFlags::MS_RDONLY | MsFlags::MS_NODEV | MsFlags::MS_REC,
+            }]
+        );
+
+        assert!(args
+            .parse_cmdline("rsinit.bind", Some("/data,/root/data,noexec"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_bind_args_recursive() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline("rsinit.bind", Some("/dev,/root/container/dev,rbind,slave"))
+            .unwrap();
+        args.parse_cmdline("rsinit.mount", Some(",/root/mnt,,rprivate"))
+            .unwrap();
+        args.parse_cmdline("rsinit.mount", Some("/sys,/root/sys,,rbind:ro"))
+            .unwrap();
+
+        assert_eq!(args.bind[0].flags, MsFlags::MS_REC | MsFlags::MS_SLAVE);
+        assert_eq!(args.mount[0].flags, MsFlags::MS_PRIVATE | MsFlags::MS_REC);
+        assert_eq!(
+            args.mount[1].flags,
+            MsFlags::MS_BIND | MsFlags::MS_REC | MsFlags::MS_RDONLY
+        );
+        assert!(args
+            .parse_cmdline("rsinit.bind", Some("/data,/root/data,rnodev"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_mount_args() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline(
+            "rsinit.mount",
+            Some("/dev/mmcblk0p3,/root/data,ext4,ro:nodev,commit=30,errors=remount-ro"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            args.mount,
+            &[GenericMount {
+                source: Some("/dev/mmcblk0p3".to_string()),
+                destination: "/root/data".to_string(),
+                fstype: Some("ext4".to_string()),
+                flags: MsFlags::MS_RDONLY | MsFlags::MS_NODEV,
+                data: Some("commit=30,errors=remount-ro".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_mount_args_empty_fields() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline("rsinit.mount", Some(",/root/tmp,tmpfs,,"))
+            .unwrap();
+        args.parse_cmdline("rsinit.mount", Some("/dev/sda1,/root/mnt"))
+            .unwrap();
+
+        assert_eq!(
+            args.mount,
+            &[
+                GenericMount {
+                    source: None,
+                    destination: "/root/tmp".to_string(),
+                    fstype: Some("tmpfs".to_string()),
+                    flags: MsFlags::empty(),
+                    data: None,
+                },
+                GenericMount {
+                    source: Some("/dev/sda1".to_string()),
+                    destination: "/root/mnt".to_string(),
+                    fstype: None,
+                    flags: MsFlags::empty(),
+                    data: None,
+                },
+            ]
+        );
+
+        assert!(args
+            .parse_cmdline("rsinit.mount", Some("/dev/sda1"))
+            .is_err());
+        assert!(args
+            .parse_cmdline("rsinit.mount", Some("/dev/sda1,"))
+            .is_err());
+        assert!(args
+            .parse_cmdline("rsinit.mount", Some("/dev/sda1,/root/mnt,ext4,bogus"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_nfs_args() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline(
+            "rsinit.nfs",
+            Some("192.168.0.1:/full/path/to/lib/modules,/root/lib/modules"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            args.nfs[0],
+            MountOption {
+                source: "192.168.0.1:/full/path/to/lib/modules".to_string(),
+                destination: "/root/lib/modules".to_string(),
+                options: String::new(),
+                flags: MsFlags::empty(),
+            }
+        );
+        assert_eq!(
+            resolve_nfs(&args.nfs[0].source, &args.nfs[0].options).unwrap(),
+            (
+                "192.168.0.1:/full/path/to/lib/modules".to_string(),
+                "addr=192.168.0.1,vers=3,proto=tcp,nolock".to_string()
+            )
+        );
+        assert!(args
+            .parse_cmdline("rsinit.nfs", Some("/srv/data,/root/data"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_nfs_args_options() {
+        let mut args = MountArgs::default();
+
+        args.parse_cmdline(
+            "rsinit.nfs",
+            Some("192.168.0.1:/srv/data,/root/data,vers=4.2,proto=tcp6,soft"),
+        )
+        .unwrap();
+
+        assert_eq!(args.nfs[0].destination, "/root/data");
+        assert_eq!(args.nfs[0].options, "vers=4.2,proto=tcp6,soft");
+        assert_eq!(
+            resolve_nfs(&args.nfs[0].source, &args.nfs[0].options)
+                .unwrap()
+                .1,
+            "addr=192.168.0.1,nolock,vers=4.2,proto=tcp6,soft"
+        );
+    }
+
+    #[test]
+    fn test_nfs_args_hostname() {
+        let mut args = MountArgs::default();
+
+        /* Resolving waits for the mount, the network may not be up while parsing */
+        args.parse_cmdline(
+            "rsinit.nfs",
+            Some("does-not-exist.invalid:/srv/modules,/root/lib/modules"),
+        )
+        .unwrap();
+        assert_eq!(args.nfs[0].source, "does-not-exist.invalid:/srv/modules");
+        assert!(resolve_nfs(&args.nfs[0].source, &args.nfs[0].options).is_err());
+
+        let (source, options) = resolve_nfs("localhost:/srv/modules", "").unwrap();
+        assert!(resolve_host("localhost").unwrap().is_loopback());
+        assert!(source.ends_with(":/srv/modules"));
+        assert!(!source.starts_with("localhost"));
+        assert!(options.starts_with("addr="));
+    }
+}
@@ -0,0 +1,884 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::write;
+use std::io;
+use std::mem::size_of;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use getrandom::getrandom;
+use log::{debug, info};
+use nix::errno::Errno;
+use nix::libc;
+
+use crate::cmdline::CmdlineOptions;
+use crate::util::{
+    dry_run, ensure_resolv_conf, mkdir_p, read_file, wait_for_device, Result, PNP_PATH,
+};
+
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+const DHCP_RETRANSMIT: Duration = Duration::from_secs(2);
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/* Ask the server to broadcast the replies, there is no address to receive them on yet */
+const BOOTP_FLAG_BROADCAST: u16 = 0x8000;
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+/* The fixed BOOTP fields including the magic cookie */
+const DHCP_HEADER_LEN: usize = 240;
+/* Some servers ignore requests shorter than a BOOTP message */
+const BOOTP_MIN_LEN: usize = 300;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_DOMAIN: u8 = 15;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMS: u8 = 55;
+const OPT_END: u8 = 255;
+
+const NLMSG_HDRLEN: usize = 16;
+const RTA_HDRLEN: usize = 4;
+
+/// The network configuration received from a DHCP server.
+#[derive(Debug, PartialEq)]
+pub struct Lease {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Option<Ipv4Addr>,
+    pub nameservers: Vec<Ipv4Addr>,
+    pub domain: Option<String>,
+    /// The DHCP server that handed out the lease.
+    pub server: Ipv4Addr,
+    /// The next server from the BOOTP header, or the DHCP server if there is none.
+    pub bootserver: Ipv4Addr,
+}
+
+impl Lease {
+    fn prefix_len(&self) -> u8 {
+        u32::from(self.netmask).leading_ones() as u8
+    }
+
+    /// Format the lease like the kernel IP autoconfiguration does in /proc/net/pnp. The
+    /// address and gateway are added as comments.
+    pub fn to_pnp(&self) -> String {
+        let mut pnp = format!(
+            "#PROTO: DHCP\n#ADDRESS: {}/{}\n",
+            self.address,
+            self.prefix_len()
+        );
+        if let Some(gateway) = self.gateway {
+            pnp.push_str(&format!("#GATEWAY: {gateway}\n"));
+        }
+        if let Some(domain) = &self.domain {
+            pnp.push_str(&format!("domain {domain}\n"));
+        }
+        for nameserver in &self.nameservers {
+            pnp.push_str(&format!("nameserver {nameserver}\n"));
+        }
+        pnp.push_str(&format!("bootserver {}\n", self.bootserver));
+        pnp
+    }
+}
+
+/// The network configuration from `ip=<client>:<server>:<gw>:<netmask>:<hostname>:<iface>:<autoconf>`.
+#[derive(Debug, Default, PartialEq)]
+pub struct IpConfig {
+    pub client: Option<Ipv4Addr>,
+    pub server: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    pub hostname: Option<String>,
+    pub iface: Option<String>,
+    /// The autoconfiguration method, e.g. `dhcp` or `off`. Empty if not specified.
+    pub autoconf: String,
+}
+
+fn parse_field(field: Option<&str>) -> Option<String> {
+    field.filter(|field| !field.is_empty()).map(str::to_string)
+}
+
+fn parse_addr(field: Option<&str>, name: &str) -> Result<Option<Ipv4Addr>> {
+    match field {
+        None | Some("") => Ok(None),
+        Some(addr) => {
+            Ok(Some(addr.parse().map_err(|e| {
+                format!("Invalid {name} address '{addr}' in 'ip=': {e}")
+            })?))
+        }
+    }
+}
+
+impl IpConfig {
+    /// Parse the value of `ip=`. A value without `:` is only the autoconfiguration method,
+    /// e.g. `ip=dhcp`.
+    pub fn parse(ip: &str) -> Result<IpConfig> {
+        if !ip.contains(':') {
+            return Ok(IpConfig {
+                autoconf: ip.to_string(),
+                ..Default::default()
+            });
+        }
+        let mut fields = ip.split(':');
+        Ok(IpConfig {
+            client: parse_addr(fields.next(), "client")?,
+            server: parse_addr(fields.next(), "server")?,
+            gateway: parse_addr(fields.next(), "gateway")?,
+            netmask: parse_addr(fields.next(), "netmask")?,
+            hostname: parse_field(fields.next()),
+            iface: parse_field(fields.next()),
+            autoconf: fields.next().unwrap_or_default().to_string(),
+        })
+    }
+
+    /// The interface to configure with DHCP in rsinit. Without an interface, DHCP is left to
+    /// the kernel IP autoconfiguration.
+    pub fn dhcp_interface(&self) -> Option<&str> {
+        match self.autoconf.as_str() {
+            "dhcp" => self.iface.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The interface and address for a static configuration without autoconfiguration.
+    pub fn static_interface(&self) -> Option<(&str, Ipv4Addr)> {
+        match self.autoconf.as_str() {
+            "" | "off" | "none" => Some((self.iface.as_deref()?, self.client?)),
+            _ => None,
+        }
+    }
+}
+
+fn ipv4(data: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(data[0], data[1], data[2], data[3])
+}
+
+/// The netmask of the address class, used if no netmask is specified, like the kernel does.
+fn classful_netmask(address: Ipv4Addr) -> Ipv4Addr {
+    match address.octets()[0] {
+        0..=127 => Ipv4Addr::new(255, 0, 0, 0),
+        128..=191 => Ipv4Addr::new(255, 255, 0, 0),
+        _ => Ipv4Addr::new(255, 255, 255, 0),
+    }
+}
+
+fn dhcp_message(msg_type: u8, xid: u32, mac: &[u8; 6], offer: Option<&Lease>) -> Vec<u8> {
+    let mut msg = vec![0u8; DHCP_HEADER_LEN];
+    msg[0] = BOOTREQUEST;
+    msg[1] = HTYPE_ETHERNET;
+    msg[2] = mac.len() as u8;
+    msg[4..8].copy_from_slice(&xid.to_be_bytes());
+    msg[10..12].copy_from_slice(&BOOTP_FLAG_BROADCAST.to_be_bytes());
+    msg[28..34].copy_from_slice(mac);
+    msg[236..240].copy_from_slice(&DHCP_MAGIC);
+
+    msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, msg_type]);
+    if let Some(offer) = offer {
+        msg.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+        msg.extend_from_slice(&offer.address.octets());
+        msg.extend_from_slice(&[OPT_SERVER_ID, 4]);
+        msg.extend_from_slice(&offer.server.octets());
+    }
+    msg.extend_from_slice(&[
+        OPT_PARAMS,
+        4,
+        OPT_SUBNET_MASK,
+        OPT_ROUTER,
+        OPT_DNS,
+        OPT_DOMAIN,
+    ]);
+    msg.push(OPT_END);
+    msg.resize(msg.len().max(BOOTP_MIN_LEN), OPT_PAD);
+    msg
+}
+
+/// Parse a reply to the request with `xid`. Returns the DHCP message type and the lease.
+fn parse_reply(msg: &[u8], xid: u32, mac: &[u8; 6]) -> Option<(u8, Lease)> {
+    if msg.len() < DHCP_HEADER_LEN
+        || msg[0] != BOOTREPLY
+        || msg[4..8] != xid.to_be_bytes()
+        || msg[28..34] != mac[..]
+        || msg[236..240] != DHCP_MAGIC
+    {
+        return None;
+    }
+    let address = ipv4(&msg[16..20]);
+    let next_server = ipv4(&msg[20..24]);
+
+    let mut msg_type = None;
+    let mut netmask = None;
+    let mut gateway = None;
+    let mut nameservers = Vec::new();
+    let mut domain = None;
+    let mut server = None;
+
+    let mut options = &msg[DHCP_HEADER_LEN..];
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => (),
+        }
+        let (&len, rest) = rest.split_first()?;
+        if rest.len() < len as usize {
+            return None;
+        }
+        let (data, rest) = rest.split_at(len as usize);
+        options = rest;
+
+        match (code, data.len()) {
+            (OPT_MESSAGE_TYPE, 1) => msg_type = Some(data[0]),
+            (OPT_SUBNET_MASK, 4) => netmask = Some(ipv4(data)),
+            (OPT_ROUTER, 4..) => gateway = Some(ipv4(data)),
+            (OPT_DNS, _) => nameservers.extend(data.chunks_exact(4).map(ipv4)),
+            (OPT_DOMAIN, _) => {
+                domain = Some(
+                    String::from_utf8_lossy(data)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                )
+            }
+            (OPT_SERVER_ID, 4) => server = Some(ipv4(data)),
+            _ => (),
+        }
+    }
+
+    let server = server?;
+    let netmask = netmask.unwrap_or_else(|| classful_netmask(address));
+    let bootserver = if next_server.is_unspecified() {
+        server
+    } else {
+        next_server
+    };
+    Some((
+        msg_type?,
+        Lease {
+            address,
+            netmask,
+            gateway,
+            nameservers,
+            domain,
+            server,
+            bootserver,
+        },
+    ))
+}
+
+fn dhcp_socket(iface: &str) -> Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DHCP_CLIENT_PORT))
+        .map_err(|e| format!("Failed to open DHCP socket: {e}"))?;
+    socket
+        .set_broadcast(true)
+        .map_err(|e| format!("Failed to enable broadcasts on DHCP socket: {e}"))?;
+    Errno::result(unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            iface.as_ptr() as *const libc::c_void,
+            iface.len() as libc::socklen_t,
+        )
+    })
+    .map_err(|e| format!("Failed to bind DHCP socket to {iface}: {e}"))?;
+    Ok(socket)
+}
+
+/// Broadcast `request` until a reply with one of the `accepted` message types arrives.
+fn transact(
+    socket: &UdpSocket,
+    request: &[u8],
+    xid: u32,
+    mac: &[u8; 6],
+    accepted: &[u8],
+    deadline: Instant,
+) -> Result<(u8, Lease)> {
+    let server = SocketAddrV4::new(Ipv4Addr::BROADCAST, DHCP_SERVER_PORT);
+    let mut buf = [0u8; 1500];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err("Timeout waiting for a DHCP server".into());
+        }
+        socket
+            .send_to(request, server)
+            .map_err(|e| format!("Failed to send DHCP request: {e}"))?;
+
+        let retransmit = (now + DHCP_RETRANSMIT).min(deadline);
+        loop {
+            let remaining = retransmit.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            match socket.recv(&mut buf) {
+                Ok(len) => match parse_reply(&buf[..len], xid, mac) {
+                    Some((msg_type, lease)) if accepted.contains(&msg_type) => {
+                        return Ok((msg_type, lease))
+                    }
+                    _ => (),
+                },
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(e) => return Err(format!("Failed to receive DHCP reply: {e}").into()),
+            }
+        }
+    }
+}
+
+fn request_lease(socket: &UdpSocket, xid: u32, mac: &[u8; 6], deadline: Instant) -> Result<Lease> {
+    loop {
+        let discover = dhcp_message(DHCP_DISCOVER, xid, mac, None);
+        let (_, offer) = transact(socket, &discover, xid, mac, &[DHCP_OFFER], deadline)?;
+        debug!("DHCP offer of {} from {}", offer.address, offer.server);
+
+        let request = dhcp_message(DHCP_REQUEST, xid, mac, Some(&offer));
+        match transact(socket, &request, xid, mac, &[DHCP_ACK, DHCP_NAK], deadline)? {
+            (DHCP_ACK, lease) => return Ok(lease),
+            _ => info!(
+                "DHCP server {} declined the request, retrying",
+                offer.server
+            ),
+        }
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn push_attr(payload: &mut Vec<u8>, attr_type: u16, data: &[u8]) {
+    payload.extend_from_slice(&((RTA_HDRLEN + data.len()) as u16).to_ne_bytes());
+    payload.extend_from_slice(&attr_type.to_ne_bytes());
+    payload.extend_from_slice(data);
+    payload.resize(align(payload.len()), 0);
+}
+
+fn netlink_message(msg_type: u16, flags: u16, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(NLMSG_HDRLEN + payload.len());
+    msg.extend_from_slice(&((NLMSG_HDRLEN + payload.len()) as u32).to_ne_bytes());
+    msg.extend_from_slice(&msg_type.to_ne_bytes());
+    msg.extend_from_slice(&flags.to_ne_bytes());
+    msg.extend_from_slice(&seq.to_ne_bytes());
+    /* The port ID of the kernel */
+    msg.extend_from_slice(&0u32.to_ne_bytes());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+fn ne_u32(data: &[u8]) -> u32 {
+    u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// A rtnetlink socket to configure network interfaces.
+struct Rtnetlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl Rtnetlink {
+    fn open() -> Result<Rtnetlink> {
+        let fd = Errno::result(unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        })
+        .map_err(|e| format!("Failed to open rtnetlink socket: {e}"))?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Ok(Rtnetlink { fd, seq: 0 })
+    }
+
+    /// Send a request and wait for the kernel to acknowledge it.
+    fn request(&mut self, msg_type: u16, flags: libc::c_int, payload: &[u8]) -> Result<()> {
+        self.seq += 1;
+        let flags = (libc::NLM_F_REQUEST | libc::NLM_F_ACK | flags) as u16;
+        let msg = netlink_message(msg_type, flags, self.seq, payload);
+        Errno::result(unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                msg.as_ptr() as *const libc::c_void,
+                msg.len(),
+                0,
+            )
+        })?;
+
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = Errno::result(unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            })?;
+            let mut msgs = &buf[..len as usize];
+            while msgs.len() >= NLMSG_HDRLEN {
+                let msg_len = ne_u32(&msgs[0..4]) as usize;
+                if msg_len < NLMSG_HDRLEN || msg_len > msgs.len() {
+                    break;
+                }
+                let msg_type = u16::from_ne_bytes([msgs[4], msgs[5]]);
+                let seq = ne_u32(&msgs[8..12]);
+                if msg_type == libc::NLMSG_ERROR as u16
+                    && seq == self.seq
+                    && msg_len >= NLMSG_HDRLEN + size_of::<i32>()
+                {
+                    return match ne_u32(&msgs[16..20]) as i32 {
+                        0 => Ok(()),
+                        error => Err(Errno::from_raw(-error).into()),
+                    };
+                }
+                msgs = &msgs[align(msg_len).min(msgs.len())..];
+            }
+        }
+    }
+
+    fn set_link_up(&mut self, index: u32) -> Result<()> {
+        /* struct ifinfomsg */
+        let mut payload = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+        payload.extend_from_slice(&index.to_ne_bytes());
+        payload.extend_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+        payload.extend_from_slice(&(libc::IFF_UP as u32).to_ne_bytes());
+        self.request(libc::RTM_NEWLINK, 0, &payload)
+    }
+
+    fn add_address(&mut self, index: u32, address: Ipv4Addr, netmask: Ipv4Addr) -> Result<()> {
+        let prefix_len = u32::from(netmask).leading_ones() as u8;
+        let broadcast = Ipv4Addr::from(u32::from(address) | !u32::from(netmask));
+        /* struct ifaddrmsg */
+        let mut payload = vec![libc::AF_INET as u8, prefix_len, 0, libc::RT_SCOPE_UNIVERSE];
+        payload.extend_from_slice(&index.to_ne_bytes());
+        push_attr(&mut payload, libc::IFA_LOCAL, &address.octets());
+        push_attr(&mut payload, libc::IFA_ADDRESS, &address.octets());
+        push_attr(&mut payload, libc::IFA_BROADCAST, &broadcast.octets());
+        self.request(
+            libc::RTM_NEWADDR,
+            libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+            &payload,
+        )
+    }
+
+    fn add_default_route(&mut self, index: u32, gateway: Ipv4Addr) -> Result<()> {
+        /* struct rtmsg */
+        let mut payload = vec![
+            libc::AF_INET as u8,
+            0,
+            0,
+            0,
+            libc::RT_TABLE_MAIN,
+            libc::RTPROT_BOOT,
+            libc::RT_SCOPE_UNIVERSE,
+            libc::RTN_UNICAST,
+        ];
+        payload.extend_from_slice(&0u32.to_ne_bytes());
+        push_attr(&mut payload, libc::RTA_GATEWAY, &gateway.octets());
+        push_attr(&mut payload, libc::RTA_OIF, &index.to_ne_bytes());
+        self.request(
+            libc::RTM_NEWROUTE,
+            libc::NLM_F_CREATE | libc::NLM_F_REPLACE,
+            &payload,
+        )
+    }
+}
+
+fn interface_index(iface: &str) -> Result<u32> {
+    let index = read_file(&format!("/sys/class/net/{iface}/ifindex"))?;
+    Ok(index
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid interface index '{}': {e}", index.trim()))?)
+}
+
+fn interface_mac(iface: &str) -> Result<[u8; 6]> {
+    let address = read_file(&format!("/sys/class/net/{iface}/address"))?;
+    let mut mac = [0u8; 6];
+    let mut octets = address.trim().split(':');
+    for byte in mac.iter_mut() {
+        *byte = octets
+            .next()
+            .and_then(|octet| u8::from_str_radix(octet, 16).ok())
+            .ok_or(format!(
+                "Invalid MAC address '{}' of {iface}",
+                address.trim()
+            ))?;
+    }
+    Ok(mac)
+}
+
+/// Wait up to `timeout` for `iface` to appear and bring it up. Returns the interface index.
+fn link_up(rtnl: &mut Rtnetlink, iface: &str, timeout: Duration) -> Result<u32> {
+    wait_for_device(&format!("/sys/class/net/{iface}"), Some(timeout))
+        .map_err(|e| format!("Network interface {iface} not found: {e}"))?;
+    let index = interface_index(iface)?;
+    rtnl.set_link_up(index)
+        .map_err(|e| format!("Failed to bring up {iface}: {e}"))?;
+    Ok(index)
+}
+
+/// Wait until `iface` is up and has a carrier. With a `timeout` of `None` wait forever.
+pub fn wait_for_link(iface: &str, timeout: Option<Duration>) -> Result<()> {
+    let start = Instant::now();
+    let operstate = format!("/sys/class/net/{iface}/operstate");
+    let carrier = format!("/sys/class/net/{iface}/carrier");
+
+    /* Some drivers report the operstate as "unknown", but their carrier is reliable. The
+     * interface may also not have appeared yet, so keep waiting without the operstate. */
+    while !read_file(&operstate).is_ok_and(|state| state.trim() == "up")
+        && !read_file(&carrier).is_ok_and(|carrier| carrier.trim() == "1")
+    {
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            if !Path::new(&operstate).exists() {
+                return Err(format!("Network interface {iface} not found").into());
+            }
+            return Err(format!("Timeout reached while waiting for the link on {iface}").into());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    debug!("Link on {iface} is up after {:?}", start.elapsed());
+    Ok(())
+}
+
+fn add_address(
+    rtnl: &mut Rtnetlink,
+    iface: &str,
+    index: u32,
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+    gateway: Option<Ipv4Addr>,
+) -> Result<()> {
+    rtnl.add_address(index, address, netmask)
+        .map_err(|e| format!("Failed to set address of {iface}: {e}"))?;
+    if let Some(gateway) = gateway {
+        rtnl.add_default_route(index, gateway)
+            .map_err(|e| format!("Failed to add default route via {gateway}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Configure `iface` with DHCP and write the lease to [`PNP_PATH`]. The lease is not renewed,
+/// so the next init must take over the network configuration.
+fn setup_dhcp(iface: &str) -> Result<Lease> {
+    let deadline = Instant::now() + DHCP_TIMEOUT;
+    let mut rtnl = Rtnetlink::open()?;
+    let index = link_up(&mut rtnl, iface, DHCP_TIMEOUT)?;
+    let mac = interface_mac(iface)?;
+
+    let socket = dhcp_socket(iface)?;
+    let mut xid = [0u8; 4];
+    getrandom(&mut xid).map_err(|_| "Getrandom failed")?;
+
+    info!("Requesting DHCP lease on {iface}");
+    let lease = request_lease(&socket, u32::from_ne_bytes(xid), &mac, deadline)?;
+    info!(
+        "Got {}/{} from DHCP server {}",
+        lease.address,
+        lease.prefix_len(),
+        lease.server
+    );
+    add_address(
+        &mut rtnl,
+        iface,
+        index,
+        lease.address,
+        lease.netmask,
+        lease.gateway,
+    )?;
+
+    mkdir_p("/run")?;
+    write(PNP_PATH, lease.to_pnp()).map_err(|e| format!("Failed to write {PNP_PATH}: {e}"))?;
+
+    Ok(lease)
+}
+
+fn setup_static(config: &IpConfig, iface: &str, address: Ipv4Addr) -> Result<()> {
+    let netmask = config.netmask.unwrap_or_else(|| classful_netmask(address));
+    info!("Configuring {address}/{netmask} on {iface}");
+
+    let mut rtnl = Rtnetlink::open()?;
+    let index = link_up(&mut rtnl, iface, DHCP_TIMEOUT)?;
+    add_address(&mut rtnl, iface, index, address, netmask, config.gateway)?;
+
+    if let Some(hostname) = &config.hostname {
+        write("/proc/sys/kernel/hostname", hostname)
+            .map_err(|e| format!("Failed to set hostname {hostname}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// Whether the rootfs is a 9pfs over TCP (`rootfstype=9p` with `trans=tcp` in `rootflags=`).
+pub fn is_9p_tcp(options: &CmdlineOptions) -> bool {
+    options.rootfstype.as_deref() == Some("9p")
+        && options
+            .rootflags
+            .as_deref()
+            .is_some_and(|flags| flags.split(',').any(|flag| flag == "trans=tcp"))
+}
+
+/// Resolve the 9pfs server in `root=` to an IP address for a 9pfs over TCP, the kernel only
+/// accepts addresses.
+pub fn resolve_9p_server(options: &mut CmdlineOptions) -> Result<()> {
+    let host = options
+        .root
+        .as_deref()
+        .ok_or("root=<host> is required for 9p with trans=tcp")?;
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(());
+    }
+
+    ensure_resolv_conf()?;
+    let addrs = (host, 0)
+        .to_socket_addrs()
+        .map_err(|e| format!("Failed to resolve 9p server '{host}': {e}"))?
+        .map(|addr| addr.ip())
+        .collect::<Vec<_>>();
+    let addr = addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or(addrs.first())
+        .ok_or(format!(
+            "Resolving 9p server '{host}' returned no addresses"
+        ))?;
+    info!("Resolved 9p server {host} to {addr}");
+    options.root = Some(addr.to_string());
+    Ok(())
+}
+
+/// Configure the network from `ip=` if it requests DHCP or a static configuration for an
+/// interface. Everything else is left to the kernel IP autoconfiguration.
+///
+/// The bootserver is stored in `options` for `nfsroot=` without a server.
+pub fn setup_network(options: &mut CmdlineOptions) -> Result<()> {
+    let Some(ip) = &options.ip else {
+        return Ok(());
+    };
+    let config = IpConfig::parse(ip)?;
+    if dry_run() {
+        info!("Would configure the network from ip={ip}");
+        return Ok(());
+    }
+
+    if let Some(iface) = config.dhcp_interface() {
+        let lease = setup_dhcp(iface)?;
+        options.bootserver = Some(config.server.unwrap_or(lease.bootserver).to_string());
+        /* The bootserver was not known yet when the cmdline was parsed */
+        options.parse_nfsroot()?;
+    } else if let Some((iface, address)) = config.static_interface() {
+        setup_static(&config, iface, address)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+
+    fn lease() -> Lease {
+        Lease {
+            address: Ipv4Addr::new(192, 168, 42, 100),
+            netmask: Ipv4Addr::new(255, 255, 255, 0),
+            gateway: Some(Ipv4Addr::new(192, 168, 42, 1)),
+            nameservers: vec![
+                Ipv4Addr::new(192, 168, 42, 2),
+                Ipv4Addr::new(192, 168, 42, 3),
+            ],
+            domain: Some("example.com".into()),
+            server: Ipv4Addr::new(192, 168, 42, 2),
+            bootserver: Ipv4Addr::new(192, 168, 42, 23),
+        }
+    }
+
+    fn reply(msg_type: u8, xid: u32) -> Vec<u8> {
+        let mut msg = vec![0u8; DHCP_HEADER_LEN];
+        msg[0] = BOOTREPLY;
+        msg[4..8].copy_from_slice(&xid.to_be_bytes());
+        msg[16..20].copy_from_slice(&[192, 168, 42, 100]);
+        msg[20..24].copy_from_slice(&[192, 168, 42, 23]);
+        msg[28..34].copy_from_slice(&MAC);
+        msg[236..240].copy_from_slice(&DHCP_MAGIC);
+        msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, msg_type, OPT_PAD]);
+        msg.extend_from_slice(&[OPT_SUBNET_MASK, 4, 255, 255, 255, 0]);
+        msg.extend_from_slice(&[OPT_ROUTER, 4, 192, 168, 42, 1]);
+        msg.extend_from_slice(&[OPT_DNS, 8, 192, 168, 42, 2, 192, 168, 42, 3]);
+        msg.extend_from_slice(&[OPT_DOMAIN, 12]);
+        msg.extend_from_slice(b"example.com\0");
+        msg.extend_from_slice(&[OPT_SERVER_ID, 4, 192, 168, 42, 2]);
+        msg.push(OPT_END);
+        msg
+    }
+
+    #[test]
+    fn test_ip_config() {
+        let config = IpConfig::parse(
+            "192.168.42.100:192.168.42.23:192.168.42.1:255.255.255.0:board:eth0:off",
+        )
+        .expect("failed");
+        assert_eq!(
+            config,
+            IpConfig {
+                client: Some(Ipv4Addr::new(192, 168, 42, 100)),
+                server: Some(Ipv4Addr::new(192, 168, 42, 23)),
+                gateway: Some(Ipv4Addr::new(192, 168, 42, 1)),
+                netmask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+                hostname: Some("board".into()),
+                iface: Some("eth0".into()),
+                autoconf: "off".into(),
+            }
+        );
+        assert_eq!(
+            config.static_interface(),
+            Some(("eth0", Ipv4Addr::new(192, 168, 42, 100)))
+        );
+        assert_eq!(config.dhcp_interface(), None);
+
+        let config = IpConfig::parse("192.168.42.100:::::eth0").expect("failed");
+        assert_eq!(config.netmask, None);
+        assert_eq!(
+            config.static_interface(),
+            Some(("eth0", Ipv4Addr::new(192, 168, 42, 100)))
+        );
+
+        let config = IpConfig::parse(":::::eth0:dhcp:192.168.42.2").expect("failed");
+        assert_eq!(config.dhcp_interface(), Some("eth0"));
+        assert_eq!(config.static_interface(), None);
+
+        let config = IpConfig::parse("dhcp").expect("failed");
+        assert_eq!(config.autoconf, "dhcp");
+        assert_eq!(config.dhcp_interface(), None);
+
+        assert_eq!(
+            IpConfig::parse(":::::eth0:off")
+                .expect("failed")
+                .static_interface(),
+            None
+        );
+        assert!(IpConfig::parse("192.168.42.300:::::eth0:off").is_err());
+        assert_eq!(
+            classful_netmask(Ipv4Addr::new(10, 0, 0, 1)),
+            Ipv4Addr::new(255, 0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_is_9p_tcp() {
+        let mut options = CmdlineOptions {
+            root: Some("192.168.42.23".into()),
+            rootfstype: Some("9p".into()),
+            rootflags: Some("trans=tcp,port=564,aname=/srv/rootfs".into()),
+            ..Default::default()
+        };
+        assert!(is_9p_tcp(&options));
+        resolve_9p_server(&mut options).expect("failed");
+        assert_eq!(options.root.as_deref(), Some("192.168.42.23"));
+
+        options.rootflags = Some("trans=virtio".into());
+        assert!(!is_9p_tcp(&options));
+    }
+
+    #[test]
+    fn test_dhcp_message() {
+        let msg = dhcp_message(DHCP_REQUEST, 0x12345678, &MAC, Some(&lease()));
+        assert_eq!(msg.len(), BOOTP_MIN_LEN);
+        assert_eq!(msg[0], BOOTREQUEST);
+        assert_eq!(msg[4..8], [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(msg[28..34], MAC);
+        assert_eq!(
+            msg[DHCP_HEADER_LEN..DHCP_HEADER_LEN + 15],
+            [
+                OPT_MESSAGE_TYPE,
+                1,
+                DHCP_REQUEST,
+                OPT_REQUESTED_IP,
+                4,
+                192,
+                168,
+                42,
+                100,
+                OPT_SERVER_ID,
+                4,
+                192,
+                168,
+                42,
+                2
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(
+            parse_reply(&reply(DHCP_ACK, 42), 42, &MAC),
+            Some((DHCP_ACK, lease()))
+        );
+        assert_eq!(parse_reply(&reply(DHCP_ACK, 42), 23, &MAC), None);
+        assert_eq!(parse_reply(&reply(DHCP_ACK, 42)[..100], 42, &MAC), None);
+    }
+
+    #[test]
+    fn test_to_pnp() {
+        assert_eq!(
+            lease().to_pnp(),
+            "#PROTO: DHCP
+#ADDRESS: 192.168.42.100/24
+#GATEWAY: 192.168.42.1
+domain example.com
+nameserver 192.168.42.2
+nameserver 192.168.42.3
+bootserver 192.168.42.23
+"
+        );
+    }
+
+    #[test]
+    fn test_wait_for_link_missing() {
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        let err = wait_for_link("rsinit-missing0", Some(timeout)).unwrap_err();
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(
+            err.to_string(),
+            "Network interface rsinit-missing0 not found"
+        );
+    }
+
+    #[test]
+    fn test_netlink_message() {
+        let mut payload = vec![0u8; 4];
+        push_attr(&mut payload, 1, &[10, 0, 0]);
+        assert_eq!(payload.len(), 12);
+        assert_eq!(payload[4..6], 7u16.to_ne_bytes());
+
+        let msg = netlink_message(libc::RTM_NEWADDR, 5, 3, &payload);
+        assert_eq!(msg.len(), NLMSG_HDRLEN + 12);
+        assert_eq!(ne_u32(&msg[0..4]), 28);
+        assert_eq!(ne_u32(&msg[8..12]), 3);
+    }
+}
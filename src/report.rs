@@ -0,0 +1,160 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fmt::Write as _;
+use std::fs::write;
+use std::time::Duration;
+
+use crate::cmdline::ReportFormat;
+use crate::util::Result;
+
+/// The default path of the boot report in the rootfs (`rsinit.report` without a value).
+pub const DEFAULT_REPORT_PATH: &str = "/run/rsinit-report.json";
+
+/// A summary of the boot for the next init (`rsinit.report`).
+#[derive(Debug, Default, PartialEq)]
+pub struct BootReport {
+    /// The root device as specified, after selecting slots and fallbacks.
+    pub root: Option<String>,
+    /// The device node `root` resolved to.
+    pub device: Option<String>,
+    pub rootfstype: Option<String>,
+    pub rootflags: Option<String>,
+    pub slot: Option<&'static str>,
+    /// Whether the rootfs is protected with dm-verity.
+    pub verity: bool,
+    /// How often mounting the rootfs was retried after transient errors.
+    pub mount_retries: u32,
+    /// The duration of the boot stages in boot order.
+    pub timings: Vec<(&'static str, Duration)>,
+}
+
+enum Value<'a> {
+    Str(Option<&'a str>),
+    Bool(bool),
+    Int(u32),
+    Millis(Duration),
+}
+
+impl BootReport {
+    fn entries(&self) -> Vec<(String, Value<'_>)> {
+        let mut entries = vec![
+            ("root".to_string(), Value::Str(self.root.as_deref())),
+            ("device".to_string(), Value::Str(self.device.as_deref())),
+            (
+                "rootfstype".to_string(),
+                Value::Str(self.rootfstype.as_deref()),
+            ),
+            (
+                "rootflags".to_string(),
+                Value::Str(self.rootflags.as_deref()),
+            ),
+            ("slot".to_string(), Value::Str(self.slot)),
+            ("verity".to_string(), Value::Bool(self.verity)),
+            ("mount_retries".to_string(), Value::Int(self.mount_retries)),
+        ];
+        for (name, elapsed) in &self.timings {
+            entries.push((format!("{name}_ms"), Value::Millis(*elapsed)));
+        }
+        entries
+    }
+
+    /// Format the report as JSON object or as `key=value` lines. Missing values are `null` in
+    /// JSON and empty otherwise.
+    pub fn format(&self, format: ReportFormat) -> String {
+        let mut buf = String::new();
+        match format {
+            ReportFormat::Json => {
+                buf.push('{');
+                for (i, (key, value)) in self.entries().iter().enumerate() {
+                    let sep = if i == 0 { "" } else { "," };
+                    let value = match value {
+                        Value::Str(Some(s)) => json_string(s),
+                        Value::Str(None) => "null".to_string(),
+                        Value::Bool(b) => b.to_string(),
+                        Value::Int(n) => n.to_string(),
+                        Value::Millis(d) => format!("{:.3}", d.as_secs_f64() * 1000.0),
+                    };
+                    let _ = write!(buf, "{sep}{}:{value}", json_string(key));
+                }
+                buf.push_str("}\n");
+            }
+            ReportFormat::Env => {
+                for (key, value) in self.entries() {
+                    let value = match value {
+                        Value::Str(s) => s.unwrap_or_default().to_string(),
+                        Value::Bool(b) => u8::from(b).to_string(),
+                        Value::Int(n) => n.to_string(),
+                        Value::Millis(d) => format!("{:.3}", d.as_secs_f64() * 1000.0),
+                    };
+                    let _ = writeln!(buf, "{key}={value}");
+                }
+            }
+        }
+        buf
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut buf = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(buf, "\\u{:04x}", u32::from(c));
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+    buf
+}
+
+/// Write `report` to `path`.
+pub fn write_report(path: &str, report: &BootReport, format: ReportFormat) -> Result<()> {
+    write(path, report.format(format))
+        .map_err(|e| format!("Failed to write the boot report to {path}: {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> BootReport {
+        BootReport {
+            root: Some("/dev/dm-0".into()),
+            device: Some("/dev/dm-0".into()),
+            rootfstype: Some("squashfs".into()),
+            rootflags: Some("a=\"b\"\\".into()),
+            slot: Some("a"),
+            verity: true,
+            mount_retries: 2,
+            timings: vec![("setup", Duration::from_micros(1500))],
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            report().format(ReportFormat::Json),
+            r#"{"root":"/dev/dm-0","device":"/dev/dm-0","rootfstype":"squashfs","rootflags":"a=\"b\"\\","slot":"a","verity":true,"mount_retries":2,"setup_ms":1.500}
+"#
+        );
+        assert_eq!(
+            BootReport::default().format(ReportFormat::Json),
+            r#"{"root":null,"device":null,"rootfstype":null,"rootflags":null,"slot":null,"verity":false,"mount_retries":0}
+"#
+        );
+        assert_eq!(json_string("a\tb"), r#""a\u0009b""#);
+    }
+
+    #[test]
+    fn test_env() {
+        assert_eq!(
+            report().format(ReportFormat::Env),
+            "root=/dev/dm-0\ndevice=/dev/dm-0\nrootfstype=squashfs\nrootflags=a=\"b\"\\\nslot=a\nverity=1\nmount_retries=2\nsetup_ms=1.500\n"
+        );
+    }
+}
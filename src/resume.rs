@@ -0,0 +1,43 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::write;
+use std::time::Duration;
+
+use log::info;
+use nix::sys::stat::{major, minor, stat, SFlag};
+
+use crate::util::{dry_run, wait_for_device, Result};
+
+/// The maximum time to wait for the resume device. Booting continues without it, so it is not
+/// waited for forever with `rootwait`.
+pub const RESUME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resume from the hibernation image on `device`, with the image `offset` in pages for swap
+/// files. If there is no valid image, the kernel returns and booting continues normally.
+pub fn resume(device: &str, offset: Option<u64>, timeout: Option<Duration>) -> Result<()> {
+    wait_for_device(device, timeout)
+        .map_err(|e| format!("Resume device {device} not found: {e}"))?;
+    let st = stat(device).map_err(|e| format!("Failed to stat {device}: {e}"))?;
+    if SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+        return Err(format!("Resume device {device} is not a block device").into());
+    }
+
+    if dry_run() {
+        info!("Would try to resume from {device}");
+        return Ok(());
+    }
+
+    if let Some(offset) = offset {
+        write("/sys/power/resume_offset", offset.to_string())
+            .map_err(|e| format!("Failed to write /sys/power/resume_offset: {e}"))?;
+    }
+
+    info!("Trying to resume from {device}");
+    let dev = format!("{}:{}", major(st.st_rdev), minor(st.st_rdev));
+    write("/sys/power/resume", &dev)
+        .map_err(|e| format!("Failed to write {dev} to /sys/power/resume: {e}"))?;
+    info!("No hibernation image found on {device}");
+
+    Ok(())
+}
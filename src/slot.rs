@@ -0,0 +1,71 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use log::info;
+
+use crate::cmdline::Slot;
+use crate::util::{dry_run, Result};
+
+/// Parse the slot state byte: `a` or `b` boot that slot, `A` or `B` try that slot once.
+fn parse_state(state: u8) -> Result<(Slot, bool)> {
+    let slot = Slot::parse(
+        std::str::from_utf8(&[state]).map_err(|_| format!("Invalid slot state {state:#x}"))?,
+    )
+    .map_err(|_| format!("Invalid slot state {state:#x}"))?;
+    Ok((slot, state.is_ascii_uppercase()))
+}
+
+/// Read the slot state byte at `offset` of `device`. Returns the slot and whether it is tried
+/// for the first time.
+pub fn read_slot_state(device: &str, offset: u64) -> Result<(Slot, bool)> {
+    let mut state = [0u8];
+    File::open(device)
+        .and_then(|mut f| {
+            f.seek(SeekFrom::Start(offset))?;
+            f.read_exact(&mut state)
+        })
+        .map_err(|e| format!("Failed to read the slot state from {device}: {e}"))?;
+    parse_state(state[0])
+}
+
+/// Write `slot` as the slot to boot to the state byte at `offset` of `device`.
+pub fn write_slot_state(device: &str, offset: u64, slot: Slot) -> Result<()> {
+    if dry_run() {
+        info!("Would set the slot state on {device} to {}", slot.as_str());
+        return Ok(());
+    }
+    OpenOptions::new()
+        .write(true)
+        .open(device)
+        .and_then(|mut f| {
+            f.seek(SeekFrom::Start(offset))?;
+            f.write_all(slot.as_str().as_bytes())?;
+            f.sync_all()
+        })
+        .map_err(|e| format!("Failed to write the slot state to {device}: {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_state() {
+        assert_eq!(parse_state(b'a').unwrap(), (Slot::A, false));
+        assert_eq!(parse_state(b'B').unwrap(), (Slot::B, true));
+        assert!(parse_state(0).is_err());
+        assert!(parse_state(0xff).is_err());
+
+        let path = std::env::temp_dir().join(format!("rsinit-slot-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"xxB").unwrap();
+        assert_eq!(read_slot_state(path, 2).unwrap(), (Slot::B, true));
+        write_slot_state(path, 2, Slot::A).unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"xxa");
+        assert!(read_slot_state(path, 3).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::time::Duration;
+
+use log::info;
+use nix::errno::Errno;
+use nix::libc;
+use nix::unistd::{sysconf, SysconfVar};
+
+use crate::util::{dry_run, wait_for_device, Result};
+
+const SWAP_FLAG_PREFER: libc::c_int = 0x8000;
+const SWAP_FLAG_PRIO_MASK: libc::c_int = 0x7fff;
+
+const SWAP_SIGNATURES: [&[u8]; 2] = [b"SWAPSPACE2", b"SWAP-SPACE"];
+
+/// Whether the first page of `device` ends with a swap signature.
+fn is_swap(device: &str, page_size: usize) -> Result<bool> {
+    let mut page = vec![0; page_size];
+    File::open(device)
+        .and_then(|mut f| f.read_exact(&mut page))
+        .map_err(|e| format!("Failed to read {device}: {e}"))?;
+    let signature = &page[page_size - 10..];
+    Ok(SWAP_SIGNATURES.contains(&signature))
+}
+
+/// The `swapon(2)` flags for `priority`. The kernel assigns decreasing negative priorities
+/// without one.
+fn swap_flags(priority: Option<u16>) -> libc::c_int {
+    match priority {
+        Some(priority) => SWAP_FLAG_PREFER | (priority as libc::c_int & SWAP_FLAG_PRIO_MASK),
+        None => 0,
+    }
+}
+
+/// Enable swapping to `device` with the optional `priority` (0-32767).
+pub fn swapon(device: &str, priority: Option<u16>, timeout: Option<Duration>) -> Result<()> {
+    wait_for_device(device, timeout).map_err(|e| format!("Swap device {device} not found: {e}"))?;
+
+    let page_size = sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .unwrap_or(4096) as usize;
+    if !is_swap(device, page_size)? {
+        return Err(format!("{device} is not formatted as swap, run mkswap on it").into());
+    }
+
+    if dry_run() {
+        info!("Would enable swap on {device}");
+        return Ok(());
+    }
+    info!("Enabling swap on {device}");
+    let path = CString::new(device)?;
+    Errno::result(unsafe { libc::swapon(path.as_ptr(), swap_flags(priority)) })
+        .map_err(|e| format!("Failed to enable swap on {device}: {e}"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_flags() {
+        assert_eq!(swap_flags(None), 0);
+        assert_eq!(swap_flags(Some(5)), 0x8005);
+        assert_eq!(swap_flags(Some(32767)), 0xffff);
+    }
+
+    #[test]
+    fn test_is_swap() {
+        let path = std::env::temp_dir().join(format!("rsinit-swap-{}", std::process::id()));
+        let mut page = vec![0; 4096];
+        page[4086..].copy_from_slice(b"SWAPSPACE2");
+        std::fs::write(&path, &page).unwrap();
+        assert!(is_swap(path.to_str().unwrap(), 4096).unwrap());
+
+        std::fs::write(&path, vec![0; 4096]).unwrap();
+        assert!(!is_swap(path.to_str().unwrap(), 4096).unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
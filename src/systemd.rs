@@ -3,27 +3,23 @@
 
 use std::collections::BinaryHeap;
 use std::env;
-use std::fs::read_to_string;
+use std::ffi::CString;
+use std::fs::{read_to_string, File};
+use std::os::fd::AsRawFd;
 use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use nix::mount::{umount, MsFlags};
+use nix::errno::Errno;
+use nix::libc;
+use nix::mount::{umount, umount2, MntFlags, MsFlags};
 use nix::sys::reboot::{reboot, RebootMode};
 
 use crate::cmdline::CmdlineOptions;
-use crate::mount::do_mount;
-use crate::util::{mkdir, Result};
+use crate::mount::{do_mount, mount_run};
+use crate::util::{mkdir, read_file, Result};
 
 pub fn mount_systemd(options: &mut CmdlineOptions) -> Result<()> {
-    do_mount(
-        Option::<&str>::None,
-        "/root/run",
-        Some("tmpfs"),
-        MsFlags::MS_NODEV
-            .union(MsFlags::MS_NOSUID)
-            .union(MsFlags::MS_STRICTATIME),
-        Some("mode=0755"),
-    )?;
-
     if !Path::new("/shutdown").exists() {
         return Ok(());
     }
@@ -34,6 +30,9 @@ pub fn mount_systemd(options: &mut CmdlineOptions) -> Result<()> {
     mkdir("/run")?;
     mkdir("/oldroot")?;
 
+    /* The initramfs is bound below /run, so it must be a tmpfs even with rsinit.run=0 */
+    mount_run()?;
+
     do_mount(
         Some("/"),
         "/root/run/initramfs",
@@ -45,31 +44,146 @@ pub fn mount_systemd(options: &mut CmdlineOptions) -> Result<()> {
     Ok(())
 }
 
-fn umount_root() -> Result<()> {
+/* How long to retry unmounting busy filesystems before detaching them */
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn shutdown_timeout() -> Duration {
+    env::var("RSINIT_SHUTDOWN_TIMEOUT")
+        .ok()
+        .and_then(|timeout| timeout.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)
+}
+
+fn oldroot_mounts() -> BinaryHeap<String> {
+    let mut mounts = BinaryHeap::new();
     if let Ok(data) = read_to_string("/proc/self/mountinfo") {
-        let mut mounts = BinaryHeap::new();
         for line in data.lines() {
             if let Some(mountpoint) = line.split(' ').nth(4) {
                 if mountpoint.starts_with("/oldroot") {
-                    mounts.push(mountpoint);
+                    mounts.push(mountpoint.to_string());
                 }
             }
         }
-        for mountpoint in mounts {
-            umount(mountpoint).map_err(|e| format!("Failed to unmount {mountpoint}: {e}"))?;
+    }
+    mounts
+}
+
+/// Unmount everything below /oldroot, nested mounts first. Busy filesystems are retried until
+/// `timeout` and then detached, so the shutdown is not blocked by lingering processes.
+fn umount_root(timeout: Duration) {
+    let start = Instant::now();
+    loop {
+        let mut mounts = oldroot_mounts();
+        let mut failed = Vec::new();
+        while let Some(mountpoint) = mounts.pop() {
+            if let Err(e) = umount(mountpoint.as_str()) {
+                failed.push((mountpoint, e));
+            }
+        }
+        if failed.is_empty() {
+            return;
+        }
+        if start.elapsed() >= timeout {
+            for (mountpoint, e) in failed {
+                println!("Failed to unmount {mountpoint}: {e}, detaching it");
+                if let Err(e) = umount2(mountpoint.as_str(), MntFlags::MNT_DETACH) {
+                    println!("Failed to detach {mountpoint}: {e}");
+                }
+            }
+            return;
         }
+        thread::sleep(Duration::from_millis(100));
     }
+}
+
+/* The libc crate does not provide the syscall number for all targets */
+#[cfg(target_arch = "x86_64")]
+const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = Some(320);
+#[cfg(any(
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = Some(294);
+#[cfg(target_arch = "arm")]
+const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = Some(401);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64",
+    target_arch = "arm"
+)))]
+const SYS_KEXEC_FILE_LOAD: Option<libc::c_long> = None;
+
+const KEXEC_FILE_NO_INITRAMFS: libc::c_ulong = 0x4;
+
+fn open(path: &str) -> Result<File> {
+    File::open(path).map_err(|e| format!("Failed to open {path}: {e}").into())
+}
+
+/// Load the kernel for `kexec` unless one is loaded already. The kernel, initrd and cmdline
+/// are taken from `RSINIT_KEXEC_KERNEL` (`/oldroot/boot/vmlinuz` by default),
+/// `RSINIT_KEXEC_INITRD` (`/oldroot/boot/initrd`, optional) and `RSINIT_KEXEC_CMDLINE`
+/// (the current cmdline by default).
+fn load_kexec() -> Result<()> {
+    if read_file("/sys/kernel/kexec_loaded").is_ok_and(|loaded| loaded.trim() == "1") {
+        return Ok(());
+    }
+    let syscall = SYS_KEXEC_FILE_LOAD.ok_or("kexec_file_load is not supported")?;
+
+    let kernel_path =
+        env::var("RSINIT_KEXEC_KERNEL").unwrap_or_else(|_| "/oldroot/boot/vmlinuz".into());
+    let initrd_path =
+        env::var("RSINIT_KEXEC_INITRD").unwrap_or_else(|_| "/oldroot/boot/initrd".into());
+    let cmdline = match env::var("RSINIT_KEXEC_CMDLINE") {
+        Ok(cmdline) => cmdline,
+        Err(_) => read_file("/proc/cmdline")?.trim().to_string(),
+    };
+
+    let kernel = open(&kernel_path)?;
+    let initrd = if Path::new(&initrd_path).exists() {
+        Some(open(&initrd_path)?)
+    } else {
+        None
+    };
+    let cmdline = CString::new(cmdline)?;
+    let flags = match initrd {
+        Some(_) => 0,
+        None => KEXEC_FILE_NO_INITRAMFS,
+    };
+
+    println!("Loading {kernel_path} for kexec");
+    Errno::result(unsafe {
+        libc::syscall(
+            syscall,
+            kernel.as_raw_fd(),
+            initrd.as_ref().map_or(-1, |initrd| initrd.as_raw_fd()),
+            cmdline.as_bytes_with_nul().len() as libc::c_ulong,
+            cmdline.as_ptr(),
+            flags,
+        )
+    })
+    .map_err(|e| format!("kexec_file_load failed: {e}"))?;
     Ok(())
 }
 
 pub fn shutdown() -> Result<()> {
-    umount_root()?;
-    let arg = match env::args().nth(1).as_deref() {
+    let mut arg = match env::args().nth(1).as_deref() {
         Some("halt") => RebootMode::RB_HALT_SYSTEM,
         Some("kexec") => RebootMode::RB_KEXEC,
         Some("poweroff") => RebootMode::RB_POWER_OFF,
         _ => RebootMode::RB_AUTOBOOT,
     };
+    /* The kernel is loaded from the old root, so this must happen before unmounting it */
+    if arg == RebootMode::RB_KEXEC {
+        if let Err(e) = load_kexec() {
+            println!("Warning: {e}, rebooting instead");
+            arg = RebootMode::RB_AUTOBOOT;
+        }
+    }
+    umount_root(shutdown_timeout());
     reboot(arg).map_err(|e| format!("reboot failed: {e}"))?;
     Ok(())
 }
@@ -0,0 +1,544 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! A minimal TPM 2.0 client that talks to the kernel resource manager.
+//!
+//! Only the few commands needed to unseal a secret are implemented. All authorizations use the
+//! empty password or a PCR policy, so no HMAC or parameter encryption is needed. The sessions are
+//! not salted, so the unsealed secret crosses the bus to the TPM in the clear. This protects
+//! against reading the disk elsewhere, not against an attacker who can sniff the bus.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+use log::debug;
+
+use crate::util::Result;
+
+const TPM_ST_NO_SESSIONS: u16 = 0x8001;
+const TPM_ST_SESSIONS: u16 = 0x8002;
+
+const TPM_CC_CREATE_PRIMARY: u32 = 0x131;
+const TPM_CC_LOAD: u32 = 0x157;
+const TPM_CC_UNSEAL: u32 = 0x15e;
+const TPM_CC_FLUSH_CONTEXT: u32 = 0x165;
+const TPM_CC_START_AUTH_SESSION: u32 = 0x176;
+const TPM_CC_GET_CAPABILITY: u32 = 0x17a;
+const TPM_CC_HASH: u32 = 0x17d;
+const TPM_CC_POLICY_PCR: u32 = 0x17f;
+const TPM_CC_PCR_EXTEND: u32 = 0x182;
+
+const TPM_CAP_PCRS: u32 = 0x0000_0005;
+
+const TPM_RH_OWNER: u32 = 0x4000_0001;
+const TPM_RH_NULL: u32 = 0x4000_0007;
+const TPM_RS_PW: u32 = 0x4000_0009;
+
+const TPM_ALG_AES: u16 = 0x0006;
+const TPM_ALG_NULL: u16 = 0x0010;
+const TPM_ALG_ECC: u16 = 0x0023;
+const TPM_ALG_CFB: u16 = 0x0043;
+const TPM_ECC_NIST_P256: u16 = 0x0003;
+
+const TPM_SE_POLICY: u8 = 0x01;
+const TPMA_SESSION_CONTINUE_SESSION: u8 = 0x01;
+
+/// fixedTPM, fixedParent, sensitiveDataOrigin, userWithAuth, restricted and decrypt.
+const SRK_ATTRIBUTES: u32 = 0x0003_0072;
+/// The object can be used with a password (the empty one) instead of a policy.
+const TPMA_OBJECT_USER_WITH_AUTH: u32 = 0x0000_0040;
+
+/// The handle of the persistent storage root key, where systemd 254 and later seal below.
+pub const SRK_HANDLE: u32 = 0x8100_0001;
+
+/// The number of PCRs in a bank.
+const PCR_COUNT: u32 = 24;
+
+/// The hash algorithm of the PCR bank `name`.
+pub fn pcr_bank(name: &str) -> Result<u16> {
+    match name {
+        "sha1" => Ok(0x0004),
+        "sha256" => Ok(0x000b),
+        "sha384" => Ok(0x000c),
+        "sha512" => Ok(0x000d),
+        _ => Err(format!("Unsupported PCR bank '{name}'").into()),
+    }
+}
+
+/// The name of the PCR bank with the hash algorithm `bank`, for logging.
+pub fn pcr_bank_name(bank: u16) -> String {
+    match bank {
+        0x0004 => "sha1".into(),
+        0x000b => "sha256".into(),
+        0x000c => "sha384".into(),
+        0x000d => "sha512".into(),
+        _ => format!("{bank:#06x}"),
+    }
+}
+
+/// The lowercase hexadecimal encoding of `data`.
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// Parse a list of PCR indices separated by `,` or `+`.
+pub fn parse_pcrs(pcrs: &str) -> Result<Vec<u32>> {
+    pcrs.split([',', '+'])
+        .filter(|pcr| !pcr.is_empty())
+        .map(|pcr| match pcr.trim().parse::<u32>() {
+            Ok(index) if index < PCR_COUNT => Ok(index),
+            _ => Err(format!("Invalid PCR '{pcr}'").into()),
+        })
+        .collect()
+}
+
+/// Split a sealed object into the marshalled `TPM2B_PRIVATE` and `TPM2B_PUBLIC` that follows it.
+/// This is the format of the `tpm2-blob` in a `systemd-tpm2` LUKS2 token and of the
+/// concatenated output of `tpm2_create -r -u`.
+fn split_blob(blob: &[u8]) -> Result<(&[u8], &[u8])> {
+    let sized = |data: &[u8]| match data {
+        [high, low, ..] => {
+            let len = 2 + u16::from_be_bytes([*high, *low]) as usize;
+            (len <= data.len()).then_some(len)
+        }
+        _ => None,
+    };
+    let private_len = sized(blob).ok_or("Truncated TPM2 private area")?;
+    let (private, public) = blob.split_at(private_len);
+    match sized(public) {
+        Some(public_len) if public_len == public.len() => Ok((private, public)),
+        _ => Err("Invalid TPM2 public area".into()),
+    }
+}
+
+/// Whether the object with the `public` area of [`split_blob`] can be unsealed with the empty
+/// password. Objects that systemd seals always need a policy session.
+fn user_with_auth(public: &[u8]) -> Result<bool> {
+    /* size, type and nameAlg before the objectAttributes */
+    let attributes = public
+        .get(6..10)
+        .ok_or("Truncated TPM2 public area")?
+        .try_into()
+        .unwrap();
+    Ok(u32::from_be_bytes(attributes) & TPMA_OBJECT_USER_WITH_AUTH != 0)
+}
+
+/// A marshalled TPM command.
+struct Command {
+    data: Vec<u8>,
+}
+
+impl Command {
+    fn new(tag: u16, code: u32) -> Command {
+        let mut command = Command { data: Vec::new() };
+        command.u16(tag).u32(0).u32(code);
+        command
+    }
+
+    fn u8(&mut self, value: u8) -> &mut Command {
+        self.data.push(value);
+        self
+    }
+
+    fn u16(&mut self, value: u16) -> &mut Command {
+        self.data.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn u32(&mut self, value: u32) -> &mut Command {
+        self.data.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn bytes(&mut self, value: &[u8]) -> &mut Command {
+        self.data.extend_from_slice(value);
+        self
+    }
+
+    fn tpm2b(&mut self, value: &[u8]) -> &mut Command {
+        self.u16(value.len() as u16).bytes(value)
+    }
+
+    /// An authorization area with a single session without nonce and HMAC.
+    fn auth(&mut self, session: u32, attributes: u8) -> &mut Command {
+        self.u32(9).u32(session).u16(0).u8(attributes).u16(0)
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let size = (self.data.len() as u32).to_be_bytes();
+        self.data[2..6].copy_from_slice(&size);
+        std::mem::take(&mut self.data)
+    }
+}
+
+/// The storage root key template of `tpm2_createprimary -C o -G ecc`.
+fn create_primary_command() -> Vec<u8> {
+    let mut public = Command { data: Vec::new() };
+    public
+        .u16(TPM_ALG_ECC)
+        .u16(pcr_bank("sha256").unwrap())
+        .u32(SRK_ATTRIBUTES)
+        .tpm2b(&[])
+        .u16(TPM_ALG_AES)
+        .u16(128)
+        .u16(TPM_ALG_CFB)
+        .u16(TPM_ALG_NULL)
+        .u16(TPM_ECC_NIST_P256)
+        .u16(TPM_ALG_NULL)
+        .tpm2b(&[])
+        .tpm2b(&[]);
+
+    Command::new(TPM_ST_SESSIONS, TPM_CC_CREATE_PRIMARY)
+        .u32(TPM_RH_OWNER)
+        .auth(TPM_RS_PW, 0)
+        .tpm2b(&[0; 4])
+        .tpm2b(&public.data)
+        .tpm2b(&[])
+        .u32(0)
+        .finish()
+}
+
+fn policy_pcr_command(session: u32, bank: u16, pcrs: &[u32]) -> Vec<u8> {
+    let mut select = [0u8; 3];
+    for pcr in pcrs {
+        select[*pcr as usize / 8] |= 1 << (pcr % 8);
+    }
+    Command::new(TPM_ST_NO_SESSIONS, TPM_CC_POLICY_PCR)
+        .u32(session)
+        .tpm2b(&[])
+        .u32(1)
+        .u16(bank)
+        .u8(select.len() as u8)
+        .bytes(&select)
+        .finish()
+}
+
+/// Extend `pcr` with one digest per bank.
+fn pcr_extend_command(pcr: u32, digests: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut command = Command::new(TPM_ST_SESSIONS, TPM_CC_PCR_EXTEND);
+    command
+        .u32(pcr)
+        .auth(TPM_RS_PW, 0)
+        .u32(digests.len() as u32);
+    for (bank, digest) in digests {
+        command.u16(*bank).bytes(digest);
+    }
+    command.finish()
+}
+
+/// The banks in a `TPML_PCR_SELECTION` of the PCR capability that have `pcr` allocated.
+fn allocated_banks(response: &mut Response, pcr: u32) -> Result<Vec<u16>> {
+    let mut banks = Vec::new();
+    for _ in 0..response.u32()? {
+        let bank = response.u16()?;
+        let size = response.u8()?;
+        let select = response.take(size as usize)?;
+        if select
+            .get(pcr as usize / 8)
+            .is_some_and(|bits| bits & (1 << (pcr % 8)) != 0)
+        {
+            banks.push(bank);
+        }
+    }
+    Ok(banks)
+}
+
+/// A response from the TPM.
+struct Response {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Response {
+    fn take(&mut self, len: usize) -> Result<&[u8]> {
+        let data = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or("Truncated TPM2 response")?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn tpm2b(&mut self) -> Result<Vec<u8>> {
+        let len = u16::from_be_bytes(self.take(2)?.try_into().unwrap());
+        Ok(self.take(len as usize)?.to_vec())
+    }
+}
+
+pub struct Tpm {
+    device: File,
+}
+
+impl Tpm {
+    /// Open the TPM via the kernel resource manager.
+    pub fn open() -> Result<Tpm> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tpmrm0")
+            .map_err(|e| format!("Failed to open /dev/tpmrm0: {e}"))?;
+        Ok(Tpm { device })
+    }
+
+    /// Send `command` and return the response after the header.
+    fn transmit(&mut self, name: &str, command: &[u8]) -> Result<Response> {
+        debug!("Sending TPM2 command {name}");
+        self.device
+            .write_all(command)
+            .map_err(|e| format!("Failed to send TPM2 command {name}: {e}"))?;
+        let mut data = vec![0; 4096];
+        let len = self
+            .device
+            .read(&mut data)
+            .map_err(|e| format!("Failed to read the TPM2 response to {name}: {e}"))?;
+        data.truncate(len);
+
+        let mut response = Response { data, pos: 6 };
+        match response.u32()? {
+            0 => Ok(response),
+            rc => Err(format!("TPM2 command {name} failed with response code {rc:#x}").into()),
+        }
+    }
+
+    fn create_primary(&mut self) -> Result<u32> {
+        self.transmit("CreatePrimary", &create_primary_command())?
+            .u32()
+    }
+
+    fn load(&mut self, parent: u32, private: &[u8], public: &[u8]) -> Result<u32> {
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_LOAD)
+            .u32(parent)
+            .auth(TPM_RS_PW, 0)
+            .bytes(private)
+            .bytes(public)
+            .finish();
+        self.transmit("Load", &command)?.u32()
+    }
+
+    fn start_policy_session(&mut self) -> Result<u32> {
+        let mut nonce = [0u8; 32];
+        getrandom::getrandom(&mut nonce).map_err(|_| "Getrandom failed")?;
+        let command = Command::new(TPM_ST_NO_SESSIONS, TPM_CC_START_AUTH_SESSION)
+            .u32(TPM_RH_NULL)
+            .u32(TPM_RH_NULL)
+            .tpm2b(&nonce)
+            .tpm2b(&[])
+            .u8(TPM_SE_POLICY)
+            .u16(TPM_ALG_NULL)
+            .u16(pcr_bank("sha256")?)
+            .finish();
+        self.transmit("StartAuthSession", &command)?.u32()
+    }
+
+    fn unseal(&mut self, item: u32, session: u32) -> Result<Vec<u8>> {
+        let command = Command::new(TPM_ST_SESSIONS, TPM_CC_UNSEAL)
+            .u32(item)
+            .auth(session, TPMA_SESSION_CONTINUE_SESSION)
+            .finish();
+        let mut response = self.transmit("Unseal", &command)?;
+        response.u32()?;
+        response.tpm2b()
+    }
+
+    fn flush(&mut self, handle: u32) {
+        let command = Command::new(TPM_ST_NO_SESSIONS, TPM_CC_FLUSH_CONTEXT)
+            .u32(handle)
+            .finish();
+        if let Err(e) = self.transmit("FlushContext", &command) {
+            debug!("{e}");
+        }
+    }
+
+    /// Hash `data` with the algorithm `bank` in the TPM.
+    fn hash(&mut self, bank: u16, data: &[u8]) -> Result<Vec<u8>> {
+        let command = Command::new(TPM_ST_NO_SESSIONS, TPM_CC_HASH)
+            .tpm2b(data)
+            .u16(bank)
+            .u32(TPM_RH_NULL)
+            .finish();
+        self.transmit("Hash", &command)?.tpm2b()
+    }
+
+    /// The hash algorithms of the PCR banks that have `pcr` allocated.
+    fn pcr_banks(&mut self, pcr: u32) -> Result<Vec<u16>> {
+        let command = Command::new(TPM_ST_NO_SESSIONS, TPM_CC_GET_CAPABILITY)
+            .u32(TPM_CAP_PCRS)
+            .u32(0)
+            .u32(1)
+            .finish();
+        let mut response = self.transmit("GetCapability", &command)?;
+        /* moreData and the capability */
+        response.take(5)?;
+        allocated_banks(&mut response, pcr)
+    }
+
+    /// Extend the `pcr` in all allocated banks with the hash of `data`, so a bank that is not
+    /// extended cannot be used to fake the measurement.
+    ///
+    /// Returns the bank and digest for each bank that was extended.
+    pub fn measure(&mut self, pcr: u32, data: &[u8]) -> Result<Vec<(u16, Vec<u8>)>> {
+        if pcr >= PCR_COUNT {
+            return Err(format!("Invalid PCR {pcr}").into());
+        }
+        let digests = self
+            .pcr_banks(pcr)?
+            .into_iter()
+            .map(|bank| Ok((bank, self.hash(bank, data)?)))
+            .collect::<Result<Vec<_>>>()?;
+        if digests.is_empty() {
+            return Err(format!("No PCR bank has PCR {pcr} allocated").into());
+        }
+        self.transmit("PCR_Extend", &pcr_extend_command(pcr, &digests))?;
+        Ok(digests)
+    }
+
+    /// Unseal the data in `blob`, which was sealed below the persistent storage root key
+    /// `srk` or, without it, the one created from the template of `tpm2_createprimary -G ecc`.
+    /// With `pcrs`, the object must be bound to a policy of the current values of these PCRs in
+    /// `bank`.
+    pub fn unseal_blob(
+        &mut self,
+        blob: &[u8],
+        srk: Option<u32>,
+        bank: u16,
+        pcrs: &[u32],
+    ) -> Result<Vec<u8>> {
+        let (private, public) = split_blob(blob)?;
+        let password = pcrs.is_empty() && user_with_auth(public)?;
+        let item = match srk {
+            Some(srk) => self.load(srk, private, public)?,
+            None => {
+                let primary = self.create_primary()?;
+                let item = self.load(primary, private, public);
+                self.flush(primary);
+                item?
+            }
+        };
+
+        let data = if password {
+            self.unseal(item, TPM_RS_PW)
+        } else {
+            self.start_policy_session().and_then(|session| {
+                let data = if pcrs.is_empty() {
+                    Ok(())
+                } else {
+                    self.transmit("PolicyPCR", &policy_pcr_command(session, bank, pcrs))
+                        .map(drop)
+                }
+                .and_then(|_| self.unseal(item, session));
+                self.flush(session);
+                data
+            })
+        };
+        self.flush(item);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pcrs() {
+        assert_eq!(parse_pcrs("0,2,7").unwrap(), [0, 2, 7]);
+        assert_eq!(parse_pcrs("7+14").unwrap(), [7, 14]);
+        assert!(parse_pcrs("").unwrap().is_empty());
+        assert!(parse_pcrs("24").is_err());
+        assert!(parse_pcrs("a").is_err());
+    }
+
+    #[test]
+    fn test_split_blob() {
+        let blob = [0, 2, 0xaa, 0xbb, 0, 1, 0xcc];
+        assert_eq!(
+            split_blob(&blob).unwrap(),
+            (&[0, 2, 0xaa, 0xbb][..], &[0, 1, 0xcc][..])
+        );
+        assert!(split_blob(&blob[..6]).is_err());
+        assert!(split_blob(&[0, 4, 0xaa]).is_err());
+        assert!(split_blob(&[blob.as_slice(), &[0]].concat()).is_err());
+    }
+
+    #[test]
+    fn test_user_with_auth() {
+        /* A keyedhash object with fixedTPM and fixedParent, plus userWithAuth */
+        let public =
+            |attributes: u32| [&[0, 10, 0, 8, 0, 0x0b], &attributes.to_be_bytes()[..]].concat();
+        assert!(user_with_auth(&public(0x0000_0052)).unwrap());
+        assert!(!user_with_auth(&public(0x0000_0012)).unwrap());
+        assert!(user_with_auth(&[0, 2, 0, 8]).is_err());
+    }
+
+    #[test]
+    fn test_policy_pcr_command() {
+        assert_eq!(
+            policy_pcr_command(0x0300_0000, pcr_bank("sha256").unwrap(), &[0, 7, 14]),
+            [
+                0x80, 0x01, 0, 0, 0, 26, 0, 0, 0x01, 0x7f, 3, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0x0b,
+                3, 0x81, 0x40, 0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pcr_extend_command() {
+        let command = pcr_extend_command(
+            11,
+            &[
+                (pcr_bank("sha1").unwrap(), vec![0xaa; 20]),
+                (pcr_bank("sha256").unwrap(), vec![0xbb; 32]),
+            ],
+        );
+        assert_eq!(command.len(), 10 + 4 + 4 + 9 + 4 + 2 + 20 + 2 + 32);
+        assert_eq!(
+            &command[..14],
+            [0x80, 0x02, 0, 0, 0, 87, 0, 0, 0x01, 0x82, 0, 0, 0, 11]
+        );
+        assert_eq!(&command[27..33], [0, 0, 0, 2, 0, 0x04]);
+        assert_eq!(&command[53..55], [0, 0x0b]);
+    }
+
+    #[test]
+    fn test_allocated_banks() {
+        /* sha1 without PCR 11, sha256 and sha384 with all PCRs */
+        let mut response = Response {
+            data: vec![
+                0, 0, 0, 3, 0, 0x04, 3, 0xff, 0xf7, 0xff, 0, 0x0b, 3, 0xff, 0xff, 0xff, 0, 0x0c, 3,
+                0xff, 0xff, 0xff,
+            ],
+            pos: 0,
+        };
+        assert_eq!(allocated_banks(&mut response, 11).unwrap(), [0x0b, 0x0c]);
+
+        let mut response = Response {
+            data: vec![0, 0, 0, 1, 0, 0x0b, 3, 0xff],
+            pos: 0,
+        };
+        assert!(allocated_banks(&mut response, 11).is_err());
+        assert_eq!(pcr_bank_name(0x0b), "sha256");
+        assert_eq!(pcr_bank_name(0x12), "0x0012");
+    }
+
+    #[test]
+    fn test_create_primary_command() {
+        let command = create_primary_command();
+        assert_eq!(
+            u32::from_be_bytes(command[2..6].try_into().unwrap()) as usize,
+            command.len()
+        );
+        assert_eq!(&command[6..14], [0, 0, 0x01, 0x31, 0x40, 0, 0, 0x01]);
+    }
+}
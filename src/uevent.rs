@@ -0,0 +1,82 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::mem::{size_of, zeroed};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use crate::util::Result;
+
+/* The multicast group the kernel sends uevents to */
+const UEVENT_GROUP_KERNEL: u32 = 1;
+
+/// A netlink socket that receives the uevents sent by the kernel.
+pub struct UeventSocket {
+    fd: OwnedFd,
+}
+
+impl UeventSocket {
+    pub fn open() -> Result<UeventSocket> {
+        let fd = Errno::result(unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        })
+        .map_err(|e| format!("Failed to open uevent socket: {e}"))?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = UEVENT_GROUP_KERNEL;
+        Errno::result(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        })
+        .map_err(|e| format!("Failed to bind uevent socket: {e}"))?;
+
+        Ok(UeventSocket { fd })
+    }
+
+    /// Wait up to `timeout` for uevents. Returns true if at least one `add` uevent was received.
+    pub fn wait_for_add(&self, timeout: Duration) -> Result<bool> {
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(self.fd.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(0) | Err(Errno::EINTR) => return Ok(false),
+            Ok(_) => (),
+            Err(e) => return Err(format!("Failed to poll uevent socket: {e}").into()),
+        }
+
+        /* Drain all pending messages, the socket is non-blocking */
+        let mut added = false;
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            match Errno::result(len) {
+                Ok(len) => added |= buf[..len as usize].starts_with(b"add@"),
+                Err(Errno::EAGAIN) => break,
+                Err(Errno::EINTR) => continue,
+                /* Some uevents were lost, so assume that the device may have been added */
+                Err(Errno::ENOBUFS) => added = true,
+                Err(e) => return Err(format!("Failed to receive uevent: {e}").into()),
+            }
+        }
+        Ok(added)
+    }
+}
@@ -1,41 +1,63 @@
 // SPDX-FileCopyrightText: 2024 The rsinit Authors
 // SPDX-License-Identifier: GPL-2.0-only
 
-use std::fs::{read_dir, write};
+use std::fs::{read_dir, remove_dir, remove_file, write};
+use std::io;
 use std::os::unix::fs::symlink;
-use std::{thread, time};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use log::info;
+use log::{debug, info, warn};
 use nix::mount::MsFlags;
 
 use crate::cmdline::CmdlineOptions;
 use crate::mount::mount_apivfs;
-use crate::util::{mkdir, Result};
+use crate::util::{mkdir, read_file, Result};
 
 fn write_file<C: AsRef<[u8]>>(path: &str, content: C) -> Result<()> {
     write(path, content).map_err(|e| format!("Failed to write to {path}: {e}").into())
 }
 
+fn list_udcs() -> Result<Vec<String>> {
+    let mut udcs = Vec::new();
+    for entry in
+        read_dir("/sys/class/udc").map_err(|e| format!("Failed to list /sys/class/udc: {e}"))?
+    {
+        let udc = entry
+            .map_err(|e| format!("Failed to inspect an entry in /sys/class/udc: {e}"))?
+            .file_name()
+            .into_string()
+            .map_err(|e| format!("UDC contains invalid UTF-8 {e:?}"))?;
+        udcs.push(udc);
+    }
+    Ok(udcs)
+}
+
+/// Use the requested UDC, or the first one if none was requested.
+fn select_udc(requested: Option<&str>) -> Result<String> {
+    let mut udcs = list_udcs()?;
+    match requested {
+        Some(udc) if udcs.iter().any(|u| u == udc) => Ok(udc.to_string()),
+        Some(udc) => {
+            Err(format!("UDC {udc} not found. Available UDCs: {}", udcs.join(", ")).into())
+        }
+        None if udcs.is_empty() => Err("No UDC found to attach the 9pfs gadget".into()),
+        None => Ok(udcs.swap_remove(0)),
+    }
+}
+
 fn setup_9pfs_gadget(options: &mut CmdlineOptions) -> Result<()> {
     info!("Initializing USB 9pfs gadget ...");
 
-    let device = if let Some(root) = &mut options.root {
+    /* Without rsinit.usbg.udc, root= names the UDC for backwards compatibility */
+    if let Some(root) = &mut options.root {
         if let Some(index) = root.find('/') {
             root.truncate(index)
         }
-        root
-    } else {
-        let udc = read_dir("/sys/class/udc")
-            .map_err(|e| format!("Failed to list /sys/class/udc: {e}"))?
-            .next()
-            .ok_or("No UDC found to attach the 9pfs gadget".to_string())?
-            .map_err(|e| format!("Failed to inspect the first entry in /sys/class/udc: {e}"))?
-            .file_name()
-            .into_string()
-            .map_err(|e| format!("UDC contains invalid UTF-8 {e:?}"))?;
-        options.root = Some(udc);
-        options.root.as_deref().unwrap()
-    };
+    }
+    let udc = select_udc(options.usbg.udc.as_deref().or(options.root.as_deref()))?;
+    let device = options.root.get_or_insert_with(|| udc.clone()).clone();
 
     mount_apivfs(
         "/sys/kernel/config",
@@ -46,21 +68,27 @@ fn setup_9pfs_gadget(options: &mut CmdlineOptions) -> Result<()> {
 
     mkdir("/sys/kernel/config/usb_gadget/9pfs")?;
 
-    write_file("/sys/kernel/config/usb_gadget/9pfs/idVendor", "0x1d6b")?;
-    write_file("/sys/kernel/config/usb_gadget/9pfs/idProduct", "0x0109")?;
+    write_file(
+        "/sys/kernel/config/usb_gadget/9pfs/idVendor",
+        format!("{:#06x}", options.usbg.vid),
+    )?;
+    write_file(
+        "/sys/kernel/config/usb_gadget/9pfs/idProduct",
+        format!("{:#06x}", options.usbg.pid),
+    )?;
 
     mkdir("/sys/kernel/config/usb_gadget/9pfs/strings/0x409")?;
     write_file(
         "/sys/kernel/config/usb_gadget/9pfs/strings/0x409/serialnumber",
-        "01234567",
+        &options.usbg.serial,
     )?;
     write_file(
         "/sys/kernel/config/usb_gadget/9pfs/strings/0x409/manufacturer",
-        "Pengutronix e.K.",
+        &options.usbg.manufacturer,
     )?;
     write_file(
         "/sys/kernel/config/usb_gadget/9pfs/strings/0x409/product",
-        "9PFS Gadget",
+        &options.usbg.product,
     )?;
 
     mkdir("/sys/kernel/config/usb_gadget/9pfs/configs/c.1")?;
@@ -71,11 +99,81 @@ fn setup_9pfs_gadget(options: &mut CmdlineOptions) -> Result<()> {
     mkdir(&function)?;
     symlink(&function, &link)?;
 
-    info!("Attaching 9pfs gatget to UDC {device}");
-    write_file("/sys/kernel/config/usb_gadget/9pfs/UDC", device)?;
+    info!("Attaching 9pfs gatget to UDC {udc}");
+    write_file("/sys/kernel/config/usb_gadget/9pfs/UDC", &udc)?;
+
+    wait_for_configured(&udc, Duration::from_secs(5))
+}
+
+/// Wait until the host has configured the gadget on `udc`.
+fn wait_for_configured(udc: &str, timeout: Duration) -> Result<()> {
+    let state_path = format!("/sys/class/udc/{udc}/state");
+    let start = Instant::now();
+
+    loop {
+        let state = read_file(&state_path)?;
+        if state.trim() == "configured" {
+            debug!("UDC {udc} configured after {:?}", start.elapsed());
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            return Err(format!(
+                "Timeout waiting for the host to configure the 9pfs gadget on UDC {udc} (state: {})",
+                state.trim()
+            )
+            .into());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn remove(path: &str, remove_fn: fn(&str) -> io::Result<()>) -> Result<()> {
+    match remove_fn(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            Err(format!("Failed to remove {path}: {e}").into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Remove all entries in `dir` with `remove_fn`.
+fn remove_entries(dir: &str, remove_fn: fn(&str) -> io::Result<()>) -> Result<()> {
+    let entries = match read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        entries => entries.map_err(|e| format!("Failed to list {dir}: {e}"))?,
+    };
+    for entry in entries.flatten() {
+        remove(&entry.path().to_string_lossy(), remove_fn)?;
+    }
+    Ok(())
+}
+
+/// Remove the 9pfs gadget from configfs in reverse order of its creation. Missing parts are
+/// skipped, so this can be used to clean up a partially created gadget.
+pub fn teardown_9pfs_gadget() -> Result<()> {
+    let gadget = "/sys/kernel/config/usb_gadget/9pfs";
+    if !Path::new(gadget).exists() {
+        return Ok(());
+    }
+
+    /* Fails if the gadget is not attached to a UDC */
+    let _ = write(format!("{gadget}/UDC"), "\n");
+
+    remove_entries(&format!("{gadget}/configs/c.1"), |path| {
+        if Path::new(path).is_symlink() {
+            remove_file(path)
+        } else {
+            Ok(())
+        }
+    })?;
+    remove(&format!("{gadget}/configs/c.1/strings/0x409"), |p| {
+        remove_dir(p)
+    })?;
+    remove(&format!("{gadget}/configs/c.1"), |p| remove_dir(p))?;
+    remove_entries(&format!("{gadget}/functions"), |p| remove_dir(p))?;
+    remove(&format!("{gadget}/strings/0x409"), |p| remove_dir(p))?;
+    remove(gadget, |p| remove_dir(p))?;
 
-    let d = time::Duration::new(1, 0);
-    thread::sleep(d);
     Ok(())
 }
 
@@ -86,7 +184,12 @@ pub fn prepare_9pfs_gadget(options: &mut CmdlineOptions) -> Result<bool> {
             .as_deref()
             .is_some_and(|flags| flags.contains("trans=usbg"))
     {
-        setup_9pfs_gadget(options)?;
+        if let Err(e) = setup_9pfs_gadget(options) {
+            if let Err(e) = teardown_9pfs_gadget() {
+                warn!("{e}");
+            }
+            return Err(e);
+        }
         Ok(true)
     } else {
         Ok(false)
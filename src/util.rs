@@ -1,12 +1,60 @@
 // SPDX-FileCopyrightText: 2025 The rsinit Authors
 // SPDX-License-Identifier: GPL-2.0-only
 
-use std::fs::{create_dir, read_to_string};
-use std::path::Path;
+use std::fs::{copy, create_dir, create_dir_all, read_link, read_to_string, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
-use std::time;
+use std::time::{Duration, Instant};
 
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use log::debug;
+
+pub use crate::error::RsinitError;
+use crate::uevent::UeventSocket;
+
+pub type Result<T, E = RsinitError> = std::result::Result<T, E>;
+
+/* Log mounts, switching root and starting init instead of doing it (rsinit.dry_run) */
+#[cfg(not(test))]
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/* Tests run in parallel threads, so a test of the dry run must not affect the others */
+#[cfg(test)]
+thread_local! {
+    static DRY_RUN: AtomicBool = const { AtomicBool::new(false) };
+}
+
+/// Only log the mounts, switching root and starting init instead of performing them.
+pub fn set_dry_run(enabled: bool) {
+    #[cfg(not(test))]
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+    #[cfg(test)]
+    DRY_RUN.with(|dry_run| dry_run.store(enabled, Ordering::Relaxed));
+}
+
+pub fn dry_run() -> bool {
+    #[cfg(not(test))]
+    return DRY_RUN.load(Ordering::Relaxed);
+    #[cfg(test)]
+    DRY_RUN.with(|dry_run| dry_run.load(Ordering::Relaxed))
+}
+
+/* Set when a signal aborts the boot, checked by the main thread between the stages */
+static ABORT: AtomicBool = AtomicBool::new(false);
+
+/// Abort the boot at the next stage or while waiting for devices, e.g. after SIGTERM.
+pub fn abort_boot() {
+    ABORT.store(true, Ordering::Relaxed);
+}
+
+/// Fail if [`abort_boot`] was called.
+pub fn check_abort() -> Result<()> {
+    if ABORT.load(Ordering::Relaxed) {
+        return Err("The boot was aborted".into());
+    }
+    Ok(())
+}
 
 pub fn mkdir(dir: &str) -> Result<()> {
     if !Path::new(dir).exists() {
@@ -17,21 +65,261 @@ pub fn mkdir(dir: &str) -> Result<()> {
     Ok(())
 }
 
+/// Create `dir` and all missing parent directories.
+pub fn mkdir_p(dir: &str) -> Result<()> {
+    create_dir_all(dir).map_err(|e| format!("Failed to create {dir}: {e}").into())
+}
+
+/// The DHCP lease of `rsinit.net` is written here in the format of /proc/net/pnp.
+pub const PNP_PATH: &str = "/run/net.pnp";
+
+/// Make the nameservers from the DHCP lease or the kernel IP autoconfiguration (`ip=`) available
+/// to the libc resolver if nothing else did. Both are written in resolv.conf format.
+pub fn ensure_resolv_conf() -> Result<()> {
+    if Path::new("/etc/resolv.conf").exists() {
+        return Ok(());
+    }
+    let Some(pnp) = [PNP_PATH, "/proc/net/pnp"]
+        .into_iter()
+        .find(|pnp| Path::new(pnp).exists())
+    else {
+        return Ok(());
+    };
+    mkdir_p("/etc")?;
+    copy(pnp, "/etc/resolv.conf").map_err(|e| format!("Failed to create /etc/resolv.conf: {e}"))?;
+    Ok(())
+}
+
 pub fn read_file(filename: &str) -> std::result::Result<String, String> {
     read_to_string(filename).map_err(|e| format!("Failed to read {filename}: {e}"))
 }
 
-pub fn wait_for_device(root_device: &str) -> Result<()> {
-    let duration = time::Duration::from_millis(5);
-    let path = Path::new(&root_device);
+/// The maximum size of parameter files like `/verity-params` in the initramfs.
+pub const MAX_PARAMS_SIZE: u64 = 64 * 1024;
+
+/// Read `path`, but fail if it is larger than `max_bytes` instead of allocating memory for all
+/// of it. This is used for input that could be unexpectedly large, e.g. a device node.
+pub fn read_limited(path: &Path, max_bytes: u64) -> std::result::Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(max_bytes + 1).read_to_end(&mut content))
+        .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    if content.len() as u64 > max_bytes {
+        return Err(format!(
+            "{} is larger than {max_bytes} bytes",
+            path.display()
+        ));
+    }
+    Ok(content)
+}
+
+/// Like [`read_file`], but fail if the file is larger than `max_bytes`.
+pub fn read_file_limited(filename: &str, max_bytes: u64) -> std::result::Result<String, String> {
+    String::from_utf8(read_limited(Path::new(filename), max_bytes)?)
+        .map_err(|e| format!("Failed to read {filename}: {e}"))
+}
+
+/// Follow `path` once if it is a symlink. A relative target is resolved against the directory
+/// of the link and `.` and `..` are removed, so the result can be shown as mount source.
+fn follow_link(path: &Path) -> PathBuf {
+    let Ok(target) = read_link(path) else {
+        return path.to_path_buf();
+    };
+    let target = path.parent().unwrap_or(Path::new("/")).join(target);
+    let mut result = PathBuf::new();
+    for component in target.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                result.pop();
+            }
+            component => result.push(component),
+        }
+    }
+    result
+}
+
+/// Wait until `root_device` exists. With a `timeout` of `None` wait forever.
+///
+/// The kernel uevents are used to notice new devices. Symlinks created by userspace (e.g. in
+/// /dev/disk/by-*) do not cause a uevent, so the path is checked periodically as well. Without
+/// the uevent socket, the path is polled every 5ms.
+///
+/// Returns the path of the device. If `root_device` is a symlink, its target is returned.
+pub fn wait_for_device(root_device: &str, timeout: Option<Duration>) -> Result<PathBuf> {
+    let mut paths = wait_for_devices(&[root_device], timeout)?;
+    Ok(paths.remove(0))
+}
+
+/// Wait until all `devices` exist like [`wait_for_device`]. The devices are waited for at the
+/// same time, so `timeout` applies to all of them together.
+///
+/// Returns the paths of the devices in the same order. On timeout, the error lists the devices
+/// that are still missing.
+pub fn wait_for_devices(devices: &[&str], timeout: Option<Duration>) -> Result<Vec<PathBuf>> {
+    let start = Instant::now();
+    let mut waiting = devices.to_vec();
+    waiting.retain(|device| !Path::new(device).exists());
+    if !waiting.is_empty() {
+        let uevents = UeventSocket::open()
+            .map_err(|e| debug!("{e}, polling for {}", waiting.join(", ")))
+            .ok();
+        let interval = match uevents {
+            Some(_) => Duration::from_millis(100),
+            None => Duration::from_millis(5),
+        };
+
+        loop {
+            check_abort()?;
+            /* Check (again) after subscribing to uevents in case a device appeared in between */
+            waiting.retain(|device| !Path::new(device).exists());
+            if waiting.is_empty() {
+                break;
+            }
+
+            let remaining = match timeout {
+                Some(timeout) => timeout
+                    .checked_sub(start.elapsed())
+                    .filter(|remaining| !remaining.is_zero())
+                    .ok_or_else(|| {
+                        RsinitError::Device(format!(
+                            "Timeout reached while waiting for {}",
+                            waiting.join(", ")
+                        ))
+                    })?,
+                None => interval,
+            };
 
-    for _ in 0..1000 {
-        if path.exists() {
-            return Ok(());
+            match &uevents {
+                Some(uevents) => {
+                    uevents.wait_for_add(remaining.min(interval))?;
+                }
+                None => thread::sleep(remaining.min(interval)),
+            }
         }
+    }
+
+    Ok(devices
+        .iter()
+        .map(|device| follow_link(Path::new(device)))
+        .collect())
+}
+
+/// Random bytes for the UUID of a device-mapper target or swap space. The UUID only needs to be
+/// unique, so this does not wait for the kernel CRNG in early boot and falls back to hashing the
+/// time and `device` instead.
+#[cfg(any(
+    feature = "dmverity",
+    feature = "luks",
+    feature = "integrity",
+    feature = "zram"
+))]
+pub(crate) fn uuid_random(device: &str) -> [u8; 16] {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+    use std::time::{SystemTime, UNIX_EPOCH};
 
-        thread::sleep(duration);
+    use nix::errno::Errno;
+    use nix::libc;
+
+    let mut rand = [0u8; 16];
+    let ret = unsafe { libc::getrandom(rand.as_mut_ptr().cast(), rand.len(), libc::GRND_NONBLOCK) };
+    if ret == rand.len() as isize {
+        return rand;
     }
+    debug!(
+        "getrandom failed: {}, deriving the UUID for {device} from the time",
+        Errno::last()
+    );
+
+    let mut hasher = DefaultHasher::new();
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    /* The RTC may not be set yet, the time since boot still differs between devices */
+    Instant::now().hash(&mut hasher);
+    device.hash(&mut hasher);
+    let first = hasher.finish();
+    first.hash(&mut hasher);
+    rand[..8].copy_from_slice(&first.to_le_bytes());
+    rand[8..].copy_from_slice(&hasher.finish().to_le_bytes());
+    rand
+}
 
-    Err("Timeout reached while waiting for the device".into())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mkdir_p() {
+        let base = std::env::temp_dir().join(format!("rsinit-mkdir-p-{}", std::process::id()));
+        let dir = base.join("usr/lib/modules");
+        let dir = dir.to_str().unwrap();
+
+        mkdir_p(dir).expect("failed");
+        assert!(Path::new(dir).is_dir());
+        mkdir_p(dir).expect("failed on existing directory");
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn test_read_file_limited() {
+        let path = std::env::temp_dir().join(format!("rsinit-limited-{}", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+        let path = path.to_str().unwrap();
+
+        assert_eq!(read_file_limited(path, 10).expect("failed"), "0123456789");
+        assert!(read_file_limited(path, 9).is_err());
+        assert!(read_file_limited("/does/not/exist", 10).is_err());
+
+        std::fs::write(path, [0xff]).unwrap();
+        assert!(read_file_limited(path, 10).is_err());
+        assert_eq!(read_limited(Path::new(path), 10).expect("failed"), [0xff]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_device() {
+        assert_eq!(
+            wait_for_device("/", Some(Duration::ZERO)).expect("failed"),
+            Path::new("/")
+        );
+
+        let base = std::env::temp_dir().join(format!("rsinit-wait-{}", std::process::id()));
+        mkdir_p(base.join("by-label").to_str().unwrap()).expect("failed");
+        std::fs::write(base.join("sda1"), "").unwrap();
+        std::os::unix::fs::symlink("../sda1", base.join("by-label/root")).unwrap();
+        assert_eq!(
+            wait_for_device(
+                base.join("by-label/root").to_str().unwrap(),
+                Some(Duration::ZERO)
+            )
+            .expect("failed"),
+            base.join("sda1")
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+
+        let start = Instant::now();
+        assert!(wait_for_device("/does/not/exist", Some(Duration::from_millis(50))).is_err());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        assert_eq!(
+            wait_for_devices(&["/", "/proc"], Some(Duration::ZERO)).expect("failed"),
+            [Path::new("/"), Path::new("/proc")]
+        );
+        let start = Instant::now();
+        let e = wait_for_devices(
+            &["/", "/does/not/exist", "/neither"],
+            Some(Duration::from_millis(50)),
+        )
+        .unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            e.to_string(),
+            "Timeout reached while waiting for /does/not/exist, /neither"
+        );
+    }
 }
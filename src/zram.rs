@@ -0,0 +1,121 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+use std::fs::{write, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use log::info;
+use nix::unistd::{sysconf, SysconfVar};
+
+#[cfg(feature = "modules")]
+use crate::modules::load_modules;
+use crate::swap::swapon;
+use crate::util::{dry_run, read_file, uuid_random, Result};
+
+const ZRAM_DEVICE: &str = "/dev/zram0";
+const ZRAM_SYSFS: &str = "/sys/block/zram0";
+/// Prefer the zram device to swap on disk, like zram-generator does.
+const ZRAM_PRIORITY: u16 = 100;
+
+/// Split `rsinit.zram=<size>[,<algorithm>]`. The size is passed to the kernel, which accepts
+/// `K`, `M` and `G` suffixes.
+fn parse_zram(value: &str) -> Result<(&str, Option<&str>)> {
+    let (size, algorithm) = match value.split_once(',') {
+        Some((size, algorithm)) => (size, Some(algorithm)),
+        None => (value, None),
+    };
+    let digits = size.trim_end_matches(['K', 'M', 'G', 'k', 'm', 'g']);
+    if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(format!("Invalid zram size '{size}'").into());
+    }
+    Ok((size, algorithm.filter(|algorithm| !algorithm.is_empty())))
+}
+
+/// The first page of a swap device with `pages` pages, like `mkswap` writes it.
+fn swap_header(pages: u64, page_size: usize, uuid: &[u8; 16]) -> Vec<u8> {
+    let mut header = vec![0; page_size];
+    /* struct swap_header: version, last_page, nr_badpages, uuid, volume_name */
+    header[1024..1028].copy_from_slice(&1u32.to_ne_bytes());
+    header[1028..1032].copy_from_slice(&((pages - 1) as u32).to_ne_bytes());
+    header[1036..1052].copy_from_slice(uuid);
+    header[page_size - 10..].copy_from_slice(b"SWAPSPACE2");
+    header
+}
+
+fn write_sysfs(attr: &str, value: &str) -> Result<()> {
+    let path = format!("{ZRAM_SYSFS}/{attr}");
+    write(&path, value).map_err(|e| format!("Failed to write '{value}' to {path}: {e}").into())
+}
+
+/// Set up `/dev/zram0` as swap with the size and compression algorithm from
+/// `rsinit.zram=<size>[,<algorithm>]`. The zram module is loaded if necessary.
+pub fn setup_zram(config: &str) -> Result<()> {
+    let (size, algorithm) = parse_zram(config)?;
+    if dry_run() {
+        info!("Would set up {ZRAM_DEVICE} as swap with size {size}");
+        return Ok(());
+    }
+
+    #[cfg(feature = "modules")]
+    if !Path::new(ZRAM_SYSFS).exists() {
+        load_modules(&["zram".to_string()])?;
+    }
+    if !Path::new(ZRAM_SYSFS).exists() {
+        return Err("zram is not available".into());
+    }
+
+    info!("Setting up {ZRAM_DEVICE} with size {size}");
+    if let Some(algorithm) = algorithm {
+        write_sysfs("comp_algorithm", algorithm)?;
+    }
+    write_sysfs("disksize", size)?;
+
+    let disksize = read_file(&format!("{ZRAM_SYSFS}/disksize"))?;
+    let disksize = disksize
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("Invalid zram disksize '{disksize}': {e}"))?;
+    let page_size = sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .unwrap_or(4096) as usize;
+    let pages = disksize / page_size as u64;
+    if pages < 10 {
+        return Err(format!("zram size {size} is too small for swap").into());
+    }
+
+    let uuid = uuid_random(ZRAM_DEVICE);
+    OpenOptions::new()
+        .write(true)
+        .open(ZRAM_DEVICE)
+        .and_then(|mut f| f.write_all(&swap_header(pages, page_size, &uuid)))
+        .map_err(|e| format!("Failed to write the swap header to {ZRAM_DEVICE}: {e}"))?;
+
+    swapon(ZRAM_DEVICE, Some(ZRAM_PRIORITY), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_zram() {
+        assert_eq!(parse_zram("512M,lz4").unwrap(), ("512M", Some("lz4")));
+        assert_eq!(parse_zram("1G").unwrap(), ("1G", None));
+        assert_eq!(parse_zram("67108864,").unwrap(), ("67108864", None));
+        assert!(parse_zram("M").is_err());
+        assert!(parse_zram("50%").is_err());
+    }
+
+    #[test]
+    fn test_swap_header() {
+        let header = swap_header(256, 4096, &[0xab; 16]);
+        assert_eq!(header.len(), 4096);
+        assert_eq!(&header[1024..1028], 1u32.to_ne_bytes());
+        assert_eq!(&header[1028..1032], 255u32.to_ne_bytes());
+        assert_eq!(&header[1032..1036], [0; 4]);
+        assert_eq!(&header[1036..1052], [0xab; 16]);
+        assert_eq!(&header[4086..], b"SWAPSPACE2");
+    }
+}
@@ -22,8 +22,12 @@ class RsInit:
             case _:
                 raise NotImplementedError(f"support for architecture {arch} is missing")
 
+        # All features except no_std, which would leave the binaries empty
+        script = Path(__file__).parent / "features_combinations.py"
+        features = subprocess.check_output([script], text=True).splitlines()[-1]
+
         args = shlex.split(os.environ["CARGO"]) if "CARGO" in os.environ else ["cargo"]
-        args += ["build", "--target", target, "--all-features", "--all-targets"]
+        args += ["build", "--target", target, "--features", features, "--all-targets"]
 
         subprocess.check_call(args)
         self.build_path = Path(".") / "target" / target / "debug"
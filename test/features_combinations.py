@@ -2,7 +2,10 @@
 # SPDX-FileCopyrightText: 2026 The rsinit Authors
 # SPDX-License-Identifier: GPL-2.0-only
 
-import itertools
+# Building every subset of the features does not scale, so check no features,
+# each feature alone and all features together. The no_std feature removes
+# everything but the cmdline parser, so it is left out of the combination.
+
 import json
 import subprocess
 
@@ -10,9 +13,7 @@ data = json.loads(subprocess.check_output(["cargo", "metadata", "--no-deps"]))
 features = list(data["packages"][0]["features"].keys())
 features.remove("default")
 
-for x in [
-    ",".join(x)
-    for i in range(len(features))
-    for x in itertools.combinations(features, i)
-]:
-    print(x)
+print("")
+for feature in features:
+    print(feature)
+print(",".join(feature for feature in features if feature != "no_std"))
@@ -0,0 +1,28 @@
+// SPDX-FileCopyrightText: 2026 The rsinit Authors
+// SPDX-License-Identifier: GPL-2.0-only
+
+//! Use the string-only cmdline parser from a `#![no_std]` crate. It is the only module of rsinit
+//! with the `no_std` feature.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use rsinit::cmdline::parse::{tokens, RootOptions};
+
+#[test]
+fn test_no_std_parse() {
+    let options = RootOptions::from_string("root=/dev/vda2 ro init=/sbin/init -- single").unwrap();
+
+    assert_eq!(options.root.as_deref(), Some("/dev/vda2"));
+    assert_eq!(options.read_only, Some(true));
+    assert_eq!(options.init.as_deref(), Some("/sbin/init"));
+    assert_eq!(options.init_args, ["single"]);
+    assert_eq!(
+        tokens("quiet").collect::<Vec<_>>(),
+        [(String::from("quiet"), None)]
+    );
+}